@@ -0,0 +1,216 @@
+// Helpers for running the importer end to end against a mock InfluxDB 1.x server
+
+use clap::Parser;
+use std::path::{Path, PathBuf};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// A mock InfluxDB server recording every write request. It runs on its own runtime so that
+// the importer, which starts runtimes of its own, is called from a plain thread
+pub struct MockInflux {
+    runtime: tokio::runtime::Runtime,
+    server: MockServer,
+}
+
+impl MockInflux {
+    // A server accepting every write with 204 No Content
+    pub fn start() -> Self {
+        let runtime = tokio::runtime::Runtime::new().expect("failed to start the mock server runtime");
+        let server = runtime.block_on(MockServer::start());
+        runtime.block_on(Mock::given(method("POST"))
+            .and(path("/write"))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&server));
+        MockInflux { runtime, server }
+    }
+    
+    pub fn url(&self) -> String {
+        self.server.uri()
+    }
+    
+    // Answer the next `times` writes with this status before accepting them again
+    pub fn fail_writes(&self, status: u16, times: u64) {
+        self.runtime.block_on(Mock::given(method("POST"))
+            .and(path("/write"))
+            .respond_with(ResponseTemplate::new(status))
+            .up_to_n_times(times)
+            .with_priority(1)
+            .mount(&self.server));
+    }
+    
+    // Answer every query with this InfluxQL result
+    pub fn answer_queries(&self, results: serde_json::Value) {
+        self.runtime.block_on(Mock::given(path("/query"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(results))
+            .mount(&self.server));
+    }
+    
+    // Answer GET requests to a health endpoint such as /ping with 200 OK
+    pub fn answer_health(&self, health_path: &str) {
+        self.runtime.block_on(Mock::given(method("GET"))
+            .and(path(health_path))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&self.server));
+    }
+    
+    // Method, path and query parameters of every request received so far, in order
+    pub fn requests(&self) -> Vec<(String, String, Vec<(String, String)>)> {
+        let requests = self.runtime.block_on(self.server.received_requests()).unwrap_or_default();
+        requests
+            .into_iter()
+            .map(|request| {
+                let params = request.url.query_pairs().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+                (request.method.to_string(), request.url.path().to_string(), params)
+            })
+            .collect()
+    }
+    
+    // Bodies of the write requests received so far, including failed ones, in order
+    pub fn writes(&self) -> Vec<String> {
+        let requests = self.runtime.block_on(self.server.received_requests()).unwrap_or_default();
+        requests
+            .into_iter()
+            .filter(|request| request.url.path() == "/write")
+            .map(|request| String::from_utf8(request.body).expect("write body is not UTF-8"))
+            .collect()
+    }
+    
+    // Every line of line protocol received so far, sorted
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.writes().iter().flat_map(|body| body.lines().map(str::to_string).collect::<Vec<_>>()).collect();
+        lines.sort();
+        lines
+    }
+}
+
+// A scratch directory holding the input files, the cache and the summary of each run
+pub struct ImportDir {
+    dir: tempfile::TempDir,
+}
+
+// Outcome of one importer run
+pub struct Import {
+    pub code: i32,
+    // The --summary-json statistics of the run
+    pub summary: serde_json::Value,
+}
+
+impl ImportDir {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("failed to create a scratch directory");
+        std::fs::create_dir(dir.path().join("data")).expect("failed to create the data directory");
+        ImportDir { dir }
+    }
+    
+    pub fn data_dir(&self) -> PathBuf {
+        self.dir.path().join("data")
+    }
+    
+    pub fn cache_file(&self) -> PathBuf {
+        self.dir.path().join("cache.json")
+    }
+    
+    pub fn write_csv(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.data_dir().join(name);
+        std::fs::write(&path, contents).expect("failed to write the input file");
+        path
+    }
+    
+    // Import the data directory into the server with the given extra arguments; pre-flight
+    // checks are skipped as the mock only implements writes
+    pub fn run(&self, influx: &MockInflux, extra: &[&str]) -> Import {
+        self.run_dir(influx, &self.data_dir(), extra)
+    }
+    
+    // Import with another spelling of the data directory as --scan-dir
+    pub fn run_dir(&self, influx: &MockInflux, scan_dir: &Path, extra: &[&str]) -> Import {
+        self.import(influx, ["--scan-dir".to_string(), path_arg(scan_dir)], false, extra)
+    }
+    
+    // Import the data directory with the pre-flight checks, for servers whose health
+    // endpoint the mock answers
+    pub fn run_checked(&self, influx: &MockInflux, extra: &[&str]) -> Import {
+        self.import(influx, ["--scan-dir".to_string(), path_arg(&self.data_dir())], true, extra)
+    }
+    
+    // Import the data directory with the pre-flight checks, for imports expected to fail;
+    // returns the error
+    pub fn run_checked_error(&self, influx: &MockInflux, extra: &[&str]) -> String {
+        let args = self.args(influx, ["--scan-dir".to_string(), path_arg(&self.data_dir())], true, extra);
+        match importer::run(importer::Cli::parse_from(args)) {
+            Ok(code) => panic!("import succeeded with exit code {}", code),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+    
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+    
+    // Import a single file of the data directory with --file
+    pub fn run_file(&self, influx: &MockInflux, name: &str, extra: &[&str]) -> Import {
+        self.import(influx, ["--file".to_string(), path_arg(&self.data_dir().join(name))], false, extra)
+    }
+    
+    // Retry a dead-letter file with `importer retry --dead-letter`
+    pub fn retry(&self, influx: &MockInflux, dead_letter: &Path) -> Import {
+        let mut args = self.args(influx, ["--dead-letter".to_string(), path_arg(dead_letter)], false, &[]);
+        args.insert(1, "retry".to_string());
+        self.run_args(args)
+    }
+    
+    // Run a cache subcommand, e.g. `invalidate <glob>`, on the cache of the scratch directory
+    pub fn cache(&self, command: &[&str]) -> i32 {
+        let mut args = vec!["importer".to_string(), "cache".to_string()];
+        args.extend(command.iter().map(|arg| arg.to_string()));
+        args.extend(["--cache-file".to_string(), path_arg(&self.cache_file())]);
+        importer::run(importer::Cli::parse_from(args)).expect("cache command failed")
+    }
+    
+    fn import(&self, influx: &MockInflux, input: [String; 2], preflight: bool, extra: &[&str]) -> Import {
+        self.run_args(self.args(influx, input, preflight, extra))
+    }
+    
+    fn run_args(&self, args: Vec<String>) -> Import {
+        let code = importer::run(importer::Cli::parse_from(args)).expect("import failed");
+        let summary = std::fs::read(self.summary_file()).expect("no summary was written");
+        Import { code, summary: serde_json::from_slice(&summary).expect("invalid summary") }
+    }
+    
+    fn summary_file(&self) -> PathBuf {
+        self.dir.path().join("summary.json")
+    }
+    
+    // Command line of an import into the server
+    fn args(&self, influx: &MockInflux, input: [String; 2], preflight: bool, extra: &[&str]) -> Vec<String> {
+        let mut args: Vec<String> = vec![
+            "importer".to_string(),
+            "--url".to_string(), influx.url(),
+            "--cache-file".to_string(), path_arg(&self.cache_file()),
+            "--summary-json".to_string(), path_arg(&self.summary_file()),
+            "--measurement".to_string(), "sensors".to_string(),
+            "--no-create-db".to_string(),
+        ];
+        if !preflight {
+            args.push("--skip-preflight".to_string());
+        }
+        // Files are written one at a time, so writes arrive in a predictable order, unless a
+        // test asks for more DB threads
+        if !extra.contains(&"--db-threads") {
+            args.extend(["--db-threads".to_string(), "1".to_string()]);
+        }
+        args.extend(input);
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        args
+    }
+}
+
+impl Default for ImportDir {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn path_arg(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
@@ -0,0 +1,545 @@
+// End-to-end imports against a mock InfluxDB server; no real server is needed
+
+use integration_tests::{ImportDir, MockInflux};
+
+const NANOS: i64 = 1_705_314_600_000_000_000;
+
+// A CSV with `rows` records one minute apart
+fn minutes_csv(rows: usize) -> String {
+    let mut csv = "timestamp,host,value\n".to_string();
+    for row in 0..rows {
+        csv.push_str(&format!("2024-01-15T10:{:02}:00Z,web-1,{}\n", 30 + row, row));
+    }
+    csv
+}
+
+// Paths the cache records as fully imported; files that failed stay marked in progress
+fn imported_paths(dir: &ImportDir) -> Vec<String> {
+    let cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    cache["entries"].as_object().unwrap().values()
+        .filter(|entry| entry["in_progress"] != true)
+        .map(|entry| entry["path"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn basic_import_writes_every_field_type() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", "timestamp,host,cpu,count,ok,state\n2024-01-15T10:30:00Z,web-1,0.5,3,true,up\n");
+    
+    let import = dir.run(&influx, &["--types", "count=int,ok=bool,state=string"]);
+    assert_eq!(import.code, 0);
+    assert_eq!(influx.lines(), [format!("sensors,host=web-1 count=3i,cpu=0.5,ok=true,state=\"up\" {}", NANOS)]);
+    assert_eq!(import.summary["successful_inserts"], 1);
+    assert_eq!(import.summary["failed_inserts"], 0);
+}
+
+#[test]
+fn victoriametrics_targets_write_without_a_database() {
+    let influx = MockInflux::start();
+    influx.answer_health("/health");
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    
+    let import = dir.run_checked(&influx, &["--target-kind", "victoriametrics", "--extra-label", "source=importer", "--extra-label", "site=lab"]);
+    assert_eq!(import.code, 0);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    
+    // The pre-flight check uses /health; /ping and InfluxQL don't exist on VictoriaMetrics
+    let requests = influx.requests();
+    assert_eq!((requests[0].0.as_str(), requests[0].1.as_str()), ("GET", "/health"));
+    assert!(requests.iter().all(|(_, path, _)| path == "/health" || path == "/write"));
+    
+    let writes: Vec<_> = requests.iter().filter(|(_, path, _)| path == "/write").collect();
+    assert_eq!(writes.len(), 1);
+    let params = &writes[0].2;
+    assert!(params.iter().all(|(name, _)| name != "db" && name != "rp"));
+    assert!(params.contains(&("precision".to_string(), "ns".to_string())));
+    let labels: Vec<&str> = params.iter().filter(|(name, _)| name == "extra_label").map(|(_, value)| value.as_str()).collect();
+    assert_eq!(labels, ["source=importer", "site=lab"]);
+    
+    // VictoriaMetrics isn't queried for the latest point, so --incremental is refused
+    let error = dir.run_checked_error(&influx, &["--target-kind", "victoriametrics", "--incremental", "--force"]);
+    assert!(error.contains("--incremental"), "{}", error);
+    assert_eq!(influx.writes().len(), 1);
+}
+
+// A primary server answering the pre-flight checks
+fn checked_influx() -> MockInflux {
+    let influx = MockInflux::start();
+    influx.answer_health("/ping");
+    influx.answer_queries(serde_json::json!({"results": [{"statement_id": 0}]}));
+    influx
+}
+
+#[test]
+fn unreachable_secondary_targets_are_dropped() {
+    let influx = checked_influx();
+    // Without a health endpoint the secondary fails its pre-flight check
+    let secondary = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    
+    let import = dir.run_checked(&influx, &["--secondary-url", &secondary.url()]);
+    assert_eq!(import.code, 0);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    assert_eq!(influx.writes().len(), 1);
+    assert!(secondary.writes().is_empty());
+    assert_eq!(imported_paths(&dir).len(), 1);
+    
+    // Unless every target is required
+    let error = dir.run_checked_error(&influx, &["--secondary-url", &secondary.url(), "--require-all-targets", "--force"]);
+    assert!(error.contains("HTTP 404"), "{}", error);
+    assert_eq!(influx.writes().len(), 1);
+}
+
+#[test]
+fn unchanged_files_are_skipped_on_the_next_run() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    
+    let first = dir.run(&influx, &[]);
+    assert_eq!(first.summary["files_processed"], 1);
+    assert_eq!(first.summary["files_hashed"], 1);
+    assert_eq!(influx.writes().len(), 1);
+    
+    // A cache hit writes nothing
+    let second = dir.run(&influx, &[]);
+    assert_eq!(second.summary["files_skipped"], 1);
+    assert_eq!(second.summary["files_hashed"], 0);
+    assert_eq!(second.summary["files_processed"], 0);
+    assert_eq!(influx.writes().len(), 1);
+    
+    // A changed file misses the cache and is imported again
+    dir.write_csv("metrics.csv", &minutes_csv(3));
+    let third = dir.run(&influx, &[]);
+    assert_eq!(third.summary["files_processed"], 1);
+    // The hash of the cache check is reused when caching the file
+    assert_eq!(third.summary["files_hashed"], 1);
+    assert_eq!(third.summary["successful_inserts"], 3);
+    assert_eq!(influx.writes().len(), 2);
+}
+
+// Rewrite every cache entry with `edit`
+fn edit_cache(dir: &ImportDir, edit: impl Fn(&mut serde_json::Map<String, serde_json::Value>)) {
+    let mut cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    for entry in cache["entries"].as_object_mut().unwrap().values_mut() {
+        edit(entry.as_object_mut().unwrap());
+    }
+    std::fs::write(dir.cache_file(), serde_json::to_vec(&cache).unwrap()).unwrap();
+}
+
+#[test]
+fn other_spellings_of_the_scan_dir_hit_the_same_cache_entries() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    dir.run(&influx, &[]);
+    
+    let detour = dir.path().join("data").join("..").join(".").join("data");
+    let import = dir.run_dir(&influx, &detour, &[]);
+    assert_eq!(import.summary["files_skipped"], 1);
+    assert_eq!(influx.writes().len(), 1);
+    
+    // Entries keyed by a path that isn't canonical are re-keyed when the cache is loaded
+    let canonical = imported_paths(&dir).remove(0);
+    let spelled = detour.join("metrics.csv").to_string_lossy().to_string();
+    let mut cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    let entries = cache["entries"].as_object_mut().unwrap();
+    let key = entries.keys().next().unwrap().clone();
+    let mut entry = entries.remove(&key).unwrap();
+    entry["path"] = spelled.clone().into();
+    cache["entries"][key.replacen(&canonical, &spelled, 1)] = entry;
+    std::fs::write(dir.cache_file(), serde_json::to_vec(&cache).unwrap()).unwrap();
+    
+    let import = dir.run(&influx, &[]);
+    assert_eq!(import.summary["files_skipped"], 1);
+    assert_eq!(imported_paths(&dir), [canonical]);
+}
+
+#[test]
+fn unchanged_size_and_mtime_skip_hashing() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    dir.run(&influx, &[]);
+    
+    // A stale hash goes unnoticed while the size and mtime match
+    edit_cache(&dir, |entry| { entry.insert("hash".to_string(), "stale".into()); });
+    let fast = dir.run(&influx, &[]);
+    assert_eq!(fast.summary["files_skipped"], 1);
+    
+    // --paranoid hashes the file anyway
+    let paranoid = dir.run(&influx, &["--paranoid"]);
+    assert_eq!(paranoid.summary["files_processed"], 1);
+    assert_eq!(influx.writes().len(), 2);
+}
+
+#[test]
+fn entries_without_a_size_and_mtime_are_upgraded() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    dir.run(&influx, &[]);
+    
+    // Entries written by older versions are checked by hash and gain a size and mtime
+    edit_cache(&dir, |entry| {
+        entry.remove("size");
+        entry.remove("mtime");
+    });
+    let import = dir.run(&influx, &[]);
+    assert_eq!(import.summary["files_skipped"], 1);
+    let cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    let entry = cache["entries"].as_object().unwrap().values().next().unwrap().clone();
+    assert_eq!(entry["size"], minutes_csv(2).len());
+    assert!(entry["mtime"].is_string());
+}
+
+#[test]
+fn writes_are_retried_after_503_responses() {
+    let influx = MockInflux::start();
+    influx.fail_writes(503, 2);
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(3));
+    
+    let import = dir.run(&influx, &[]);
+    let writes = influx.writes();
+    assert_eq!(writes.len(), 3);
+    assert!(writes.iter().all(|body| body == &writes[0]));
+    assert_eq!(import.summary["throttled_requests"], 2);
+    assert_eq!(import.summary["successful_inserts"], 3);
+    assert_eq!(import.summary["failed_inserts"], 0);
+    assert_eq!(imported_paths(&dir).len(), 1);
+}
+
+#[test]
+fn records_fail_once_the_retries_are_exhausted() {
+    let influx = MockInflux::start();
+    influx.fail_writes(503, 10);
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(3));
+    
+    let import = dir.run(&influx, &["--write-retries", "1"]);
+    assert_eq!(influx.writes().len(), 2);
+    assert_eq!(import.summary["successful_inserts"], 0);
+    assert_eq!(import.summary["failed_inserts"], 3);
+    // The file is retried on the next run
+    assert!(imported_paths(&dir).is_empty());
+}
+
+#[test]
+fn partially_written_files_are_imported_again() {
+    let influx = MockInflux::start();
+    // The server refuses one batch outright
+    influx.fail_writes(400, 1);
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(4));
+    
+    let import = dir.run(&influx, &["--batch-size", "2"]);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    assert_eq!(import.summary["failed_inserts"], 2);
+    assert!(imported_paths(&dir).is_empty());
+    
+    let retried = dir.run(&influx, &["--batch-size", "2"]);
+    assert_eq!(retried.summary["files_processed"], 1);
+    assert_eq!(retried.summary["successful_inserts"], 4);
+    assert_eq!(imported_paths(&dir).len(), 1);
+}
+
+#[test]
+fn failures_under_the_cache_failure_threshold_are_cached() {
+    let influx = MockInflux::start();
+    influx.fail_writes(400, 1);
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(4));
+    
+    // One record in four failed, under the threshold
+    let import = dir.run(&influx, &["--batch-size", "1", "--cache-failure-threshold", "30"]);
+    assert_eq!(import.summary["failed_inserts"], 1);
+    assert_eq!(imported_paths(&dir).len(), 1);
+    
+    let next = dir.run(&influx, &["--batch-size", "1", "--cache-failure-threshold", "30"]);
+    assert_eq!(next.summary["files_skipped"], 1);
+    assert_eq!(influx.writes().len(), 4);
+}
+
+#[test]
+fn batches_split_at_the_batch_size() {
+    for (batch_size, expected) in [("1", vec![1, 1, 1, 1, 1]), ("2", vec![2, 2, 1]), ("5", vec![5]), ("6", vec![5])] {
+        let influx = MockInflux::start();
+        let dir = ImportDir::new();
+        dir.write_csv("metrics.csv", &minutes_csv(5));
+        
+        let import = dir.run(&influx, &["--batch-size", batch_size]);
+        let sizes: Vec<usize> = influx.writes().iter().map(|body| body.lines().count()).collect();
+        assert_eq!(sizes, expected, "--batch-size {}", batch_size);
+        assert_eq!(import.summary["successful_inserts"], 5);
+    }
+}
+
+#[test]
+fn preserved_order_writes_batches_in_file_order() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(20));
+    
+    dir.run(&influx, &["--batch-size", "1", "--db-threads", "8", "--preserve-order"]);
+    let values: Vec<String> = influx.writes().iter()
+        .map(|body| body.split(['=', ' ']).nth(3).unwrap().to_string())
+        .collect();
+    assert_eq!(values, (0..20).map(|row| row.to_string()).collect::<Vec<_>>());
+}
+
+#[test]
+fn a_single_file_is_imported_without_scanning() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("a.csv", "timestamp,host,value\n2024-01-15T10:30:00Z,web-1,1\n");
+    dir.write_csv("b.csv", "timestamp,host,value\n2024-01-15T10:30:00Z,web-2,2\n");
+    
+    let import = dir.run_file(&influx, "b.csv", &[]);
+    assert_eq!(import.summary["files_found"], 1);
+    assert_eq!(influx.lines(), [format!("sensors,host=web-2 value=2 {}", NANOS)]);
+    assert!(imported_paths(&dir)[0].ends_with("b.csv"));
+}
+
+#[test]
+fn records_before_since_are_left_out() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(5));
+    
+    let import = dir.run(&influx, &["--since", "2024-01-15T10:32:00Z"]);
+    assert_eq!(import.summary["successful_inserts"], 3);
+    assert_eq!(import.summary["records_before_since"], 2);
+}
+
+#[test]
+fn one_file_can_be_imported_into_several_measurements() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    assert_eq!(dir.run(&influx, &[]).summary["successful_inserts"], 2);
+    
+    // Another measurement from a template or a sidecar is another destination
+    let templated = dir.run(&influx, &["--measurement-template", "{host}_cpu"]);
+    assert_eq!(templated.summary["successful_inserts"], 2);
+    assert_eq!(dir.run(&influx, &["--measurement-template", "{host}_cpu"]).summary["files_skipped"], 1);
+    std::fs::write(dir.data_dir().join("metrics.csv.meta.json"), r#"{"measurement": "mem"}"#).unwrap();
+    assert_eq!(dir.run(&influx, &[]).summary["successful_inserts"], 2);
+    assert_eq!(dir.run(&influx, &[]).summary["files_skipped"], 1);
+    let measurements: std::collections::BTreeSet<String> = influx.lines().iter()
+        .map(|line| line.split([',', ' ']).next().unwrap().to_string())
+        .collect();
+    assert_eq!(measurements.into_iter().collect::<Vec<_>>(), ["mem", "sensors", "web-1_cpu"]);
+    assert_eq!(imported_paths(&dir).len(), 3);
+    
+    // Entries keyed by their path alone, from older caches, count for the current destination
+    let mut cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    let entries = cache["entries"].as_object_mut().unwrap();
+    let path = imported_paths(&dir).remove(0);
+    let entry = entries.values().next().unwrap().clone();
+    entries.clear();
+    entries.insert(path, entry);
+    std::fs::write(dir.cache_file(), serde_json::to_vec(&cache).unwrap()).unwrap();
+    assert_eq!(dir.run(&influx, &["--measurement-template", "{host}_cpu"]).summary["files_skipped"], 1);
+}
+
+#[test]
+fn incremental_imports_start_at_the_latest_stored_point() {
+    let influx = MockInflux::start();
+    influx.answer_queries(serde_json::json!({"results": [{"statement_id": 0, "series": [{
+        "name": "sensors",
+        "columns": ["time", "host", "value"],
+        "values": [["2024-01-15T10:33:00Z", "web-1", 3]],
+    }]}]}));
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(5));
+    
+    // The latest stored point is written again, which overwrites it with the same values
+    let import = dir.run(&influx, &["--incremental"]);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    assert_eq!(import.summary["records_before_since"], 3);
+}
+
+#[test]
+fn incremental_imports_look_up_template_measurements_when_written() {
+    let influx = MockInflux::start();
+    influx.answer_queries(serde_json::json!({"results": [{"statement_id": 0, "series": [{
+        "name": "web-1_cpu",
+        "columns": ["time", "value"],
+        "values": [["2024-01-15T10:33:00Z", 3]],
+    }]}]}));
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(5));
+    
+    let import = dir.run(&influx, &["--incremental", "--measurement-template", "{host}_cpu"]);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    assert_eq!(import.summary["records_before_since"], 3);
+    let queried = influx.requests().into_iter()
+        .filter(|(_, path, _)| path == "/query")
+        .any(|(_, _, params)| params.iter().any(|(name, value)| name == "q" && value.contains("web-1_cpu")));
+    assert!(queried);
+}
+
+#[test]
+fn template_measurements_with_control_characters_fail_their_records() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", "timestamp,host,value\n2024-01-15T10:30:00Z,web-1,1\n2024-01-15T10:31:00Z,\"web\t2\",2\n");
+    
+    let import = dir.run(&influx, &["--measurement-template", "{host}_cpu"]);
+    assert_eq!(import.summary["successful_inserts"], 1);
+    assert_eq!(import.summary["failed_inserts"], 1);
+    assert_eq!(influx.lines(), [format!("web-1_cpu value=1 {}", NANOS)]);
+}
+
+#[test]
+fn incremental_imports_of_a_grown_file_add_only_the_new_rows() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    assert_eq!(dir.run(&influx, &["--incremental-files"]).summary["successful_inserts"], 2);
+    
+    dir.write_csv("metrics.csv", &minutes_csv(4));
+    let grown = dir.run(&influx, &["--incremental-files"]);
+    assert_eq!(grown.summary["successful_inserts"], 2);
+    assert_eq!(grown.summary["records_already_imported"], 2);
+    let cache: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.cache_file()).unwrap()).unwrap();
+    let entry = cache["entries"].as_object().unwrap().values().next().unwrap();
+    assert_eq!(entry["max_imported_timestamp"], "2024-01-15T10:33:00Z");
+    
+    // Without --incremental-files a changed file is imported in full
+    dir.write_csv("metrics.csv", &minutes_csv(5));
+    assert_eq!(dir.run(&influx, &[]).summary["successful_inserts"], 5);
+}
+
+#[test]
+fn only_invalidated_and_only_files_are_imported_again() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    for name in ["a.csv", "b.csv", "c.csv"] {
+        dir.write_csv(name, &minutes_csv(1));
+    }
+    assert_eq!(dir.run(&influx, &[]).summary["files_processed"], 3);
+    
+    let invalidated = dir.data_dir().join("a.csv");
+    assert_eq!(dir.cache(&["invalidate", &invalidated.to_string_lossy()]), 0);
+    let rerun = dir.run(&influx, &[]);
+    assert_eq!(rerun.summary["files_processed"], 1);
+    assert_eq!(rerun.summary["files_skipped"], 2);
+    
+    let only = dir.run(&influx, &["--only", "*/b.csv"]);
+    assert_eq!(only.summary["files_processed"], 1);
+    assert_eq!(only.summary["files_skipped"], 2);
+    assert_eq!(imported_paths(&dir).len(), 3);
+}
+
+#[test]
+fn files_are_filtered_by_modification_time() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    let path = dir.write_csv("metrics.csv", &minutes_csv(2));
+    
+    // A file written just now may still be growing
+    let fresh = dir.run(&influx, &["--file-age-min-hours", "1"]);
+    assert_eq!(fresh.summary["files_skipped_age"], 1);
+    assert!(influx.writes().is_empty());
+    
+    let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(3 * 3600);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+    let stale = dir.run(&influx, &["--file-age-max-hours", "2"]);
+    assert_eq!(stale.summary["files_skipped_age"], 1);
+    assert!(influx.writes().is_empty());
+    
+    let settled = dir.run(&influx, &["--file-age-min-hours", "1", "--file-age-max-hours", "4"]);
+    assert_eq!(settled.summary["files_skipped_age"], 0);
+    assert_eq!(settled.summary["successful_inserts"], 2);
+}
+
+#[test]
+fn malformed_rows_only_affect_their_own_file() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    // A record without a timestamp is skipped; a ragged row makes its whole file fail
+    dir.write_csv("good.csv", "timestamp,host,value\n2024-01-15T10:30:00Z,web-1,1\n,web-1,2\n");
+    dir.write_csv("bad.csv", "timestamp,host,value\n2024-01-15T10:30:00Z,web-2,1\n2024-01-15T10:31:00Z,web-2,2,extra\n");
+    
+    let import = dir.run(&influx, &[]);
+    assert_eq!(import.code, 0);
+    assert_eq!(influx.lines(), [format!("sensors,host=web-1 value=1 {}", NANOS)]);
+    assert_eq!(import.summary["records_skipped_no_timestamp"], 1);
+    let imported = imported_paths(&dir);
+    assert_eq!(imported.len(), 1);
+    assert!(imported[0].ends_with("good.csv"));
+}
+
+#[test]
+fn dry_runs_neither_write_nor_cache() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    dir.write_csv("metrics.csv", &minutes_csv(2));
+    
+    let import = dir.run(&influx, &["--dry-run"]);
+    assert_eq!(import.code, 0);
+    assert_eq!(import.summary["dry_run"], true);
+    assert_eq!(import.summary["records_processed"], 2);
+    assert!(influx.writes().is_empty());
+    assert!(!dir.cache_file().exists());
+}
+
+#[test]
+fn dead_letters_failing_again_fail_the_run() {
+    let influx = checked_influx();
+    let dir = ImportDir::new();
+    dir.write_csv("retry.lp", &format!("sensors,host=web-1 value=1 {}\n", NANOS));
+    let replay = dir.data_dir().to_string_lossy().to_string();
+    let again = dir.path().join("again.ndjson").to_string_lossy().to_string();
+    
+    influx.fail_writes(400, 1);
+    let import = dir.run_checked(&influx, &["--import-dead-letters", &replay, "--dead-letter", &again]);
+    assert_eq!(import.code, 5);
+    assert_eq!(import.summary["failed_inserts"], 1);
+    assert_eq!(std::fs::read_to_string(&again).unwrap().lines().count(), 1);
+    
+    let import = dir.run_checked(&influx, &["--import-dead-letters", &replay, "--dead-letter", &again]);
+    assert_eq!(import.code, 0);
+    assert_eq!(import.summary["successful_inserts"], 1);
+    
+    // Writing the failures back into the replayed directory is refused, whatever the spelling
+    let inside = dir.path().join("data/../data/again.ndjson").to_string_lossy().to_string();
+    let error = dir.run_checked_error(&influx, &["--import-dead-letters", &replay, "--dead-letter", &inside]);
+    assert!(error.contains("is inside --import-dead-letters"), "{}", error);
+    assert_eq!(influx.writes().len(), 2);
+}
+
+#[test]
+fn retrying_a_dead_letter_file_keeps_only_the_records_failing_again() {
+    let influx = MockInflux::start();
+    let dir = ImportDir::new();
+    let failed = dir.path().join("failed.ndjson");
+    let entries: Vec<String> = (0..2).map(|row| serde_json::json!({
+        "source_file": "metrics.csv",
+        "measurement": "sensors",
+        "timestamp": "2024-01-15T10:30:00Z",
+        "fields": {"host": format!("web-{}", row), "value": "1"},
+        "line": format!("sensors,host=web-{} value=1 {}", row, NANOS),
+        "error": "HTTP 500",
+        "failed_at": "2024-01-15T11:00:00Z",
+    }).to_string()).collect();
+    std::fs::write(&failed, entries.join("\n") + "\n").unwrap();
+    
+    influx.fail_writes(400, 1);
+    let import = dir.retry(&influx, &failed);
+    assert_eq!(import.code, 5);
+    assert_eq!(import.summary["failed_inserts"], 2);
+    assert_eq!(std::fs::read_to_string(&failed).unwrap().lines().count(), 2);
+    
+    let import = dir.retry(&influx, &failed);
+    assert_eq!(import.code, 0);
+    assert_eq!(import.summary["successful_inserts"], 2);
+    assert!(std::fs::read_to_string(&failed).unwrap().is_empty());
+    assert_eq!(influx.lines(), [format!("sensors,host=web-0 value=1 {}", NANOS), format!("sensors,host=web-0 value=1 {}", NANOS), format!("sensors,host=web-1 value=1 {}", NANOS), format!("sensors,host=web-1 value=1 {}", NANOS)]);
+}
@@ -0,0 +1,174 @@
+// Kafka sink (--kafka-brokers/--kafka-topic), built with the `kafka` cargo feature
+
+use crate::{CursedStatsError, Point, RejectedLine, Sink, WriteOutcome};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::util::Timeout;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// How long a message may wait for its acknowledgement, including the producer's own retries
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Publishes every point as a JSON message keyed by its measurement. The producer batches
+// messages itself; a batch only counts as written once every message has been acknowledged
+pub(crate) struct KafkaSink {
+    producer: FutureProducer,
+    brokers: String,
+    topic: String,
+    // Messages acknowledged so far
+    sent: AtomicUsize,
+}
+
+impl KafkaSink {
+    pub(crate) fn new(brokers: &str, topic: &str, retries: u32, compress: bool) -> Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("linger.ms", "50")
+            .set("acks", "all")
+            .set("message.send.max.retries", retries.to_string())
+            .set("message.timeout.ms", DELIVERY_TIMEOUT.as_millis().to_string())
+            .set("compression.type", if compress { "gzip" } else { "none" })
+            .create()
+            .context("Failed to create the Kafka producer")?;
+        info!("Publishing records to Kafka topic {} on {}", topic, brokers);
+        Ok(KafkaSink { producer, brokers: brokers.to_string(), topic: topic.to_string(), sent: AtomicUsize::new(0) })
+    }
+}
+
+// The message published for a point:
+//   {"timestamp": <nanoseconds since the epoch, or null>, "measurement": "...",
+//    "tags": {"<key>": "<value>", ...}, "fields": {"<key>": <number, bool or string>, ...}}
+pub(crate) fn message_json(point: &Point) -> serde_json::Value {
+    let tags: serde_json::Map<String, serde_json::Value> = point.tags.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect();
+    let fields: serde_json::Map<String, serde_json::Value> = point.fields.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect();
+    serde_json::json!({
+        "timestamp": point.timestamp,
+        "measurement": point.measurement,
+        "tags": tags,
+        "fields": fields,
+    })
+}
+
+#[async_trait::async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> String {
+        self.topic.clone()
+    }
+    
+    // Fetch the topic's metadata to check the brokers are reachable
+    async fn preflight(&mut self) -> Result<()> {
+        let producer = self.producer.clone();
+        let topic = self.topic.clone();
+        tokio::task::spawn_blocking(move || producer.client().fetch_metadata(Some(&topic), Duration::from_secs(10)))
+            .await?
+            .with_context(|| format!("Failed to reach Kafka brokers {}", self.brokers))?;
+        info!("Pre-flight checks passed for Kafka topic {}", self.topic);
+        Ok(())
+    }
+    
+    async fn write_batch(&self, source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let source = source.map(|path| path.to_string_lossy().to_string());
+        let mut headers = OwnedHeaders::new();
+        if let Some(source) = &source {
+            headers = headers.insert(Header { key: "source_file", value: Some(source.as_str()) });
+        }
+        
+        // Queue every message first so the producer can batch them, then wait for the acks
+        let mut payload_bytes = 0;
+        let mut rejected = Vec::new();
+        let mut deliveries = Vec::with_capacity(points.len());
+        for (index, point) in points.iter().enumerate() {
+            let payload = message_json(point).to_string();
+            payload_bytes += payload.len();
+            let mut record = FutureRecord::to(&self.topic)
+                .key(point.measurement.as_str())
+                .payload(payload.as_str())
+                .headers(headers.clone());
+            if let Some(nanos) = point.timestamp {
+                record = record.timestamp(nanos.div_euclid(1_000_000));
+            }
+            loop {
+                match self.producer.send_result(record) {
+                    Ok(delivery) => {
+                        deliveries.push((index, delivery));
+                        break;
+                    }
+                    // Give the producer time to drain its queue before queueing more
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                        record = returned;
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    Err((e, _)) => {
+                        rejected.push(RejectedLine { index, reason: e.to_string() });
+                        break;
+                    }
+                }
+            }
+        }
+        
+        for (index, delivery) in deliveries {
+            match delivery.await {
+                Ok(Ok(_)) => {}
+                Ok(Err((e, _))) => rejected.push(RejectedLine { index, reason: e.to_string() }),
+                Err(_) => rejected.push(RejectedLine { index, reason: "delivery was cancelled".to_string() }),
+            }
+        }
+        self.sent.fetch_add(points.len() - rejected.len(), Ordering::SeqCst);
+        if !rejected.is_empty() {
+            warn!("Kafka did not acknowledge {} of {} messages to {}", rejected.len(), points.len(), self.topic);
+        }
+        rejected.sort_by_key(|r| r.index);
+        Ok(WriteOutcome { payload_bytes, wire_bytes: payload_bytes, rejected })
+    }
+    
+    fn messages_sent(&self) -> usize {
+        self.sent.load(Ordering::SeqCst)
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        self.producer
+            .flush(Timeout::After(DELIVERY_TIMEOUT))
+            .map_err(|cause| CursedStatsError::KafkaWrite { topic: self.topic.clone(), cause })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FieldValue;
+    
+    #[test]
+    fn messages_follow_the_documented_schema() {
+        let mut point = Point::new("sensors", Some(1_705_314_600_000_000_000));
+        point.tags.insert("host".to_string(), "web-1".to_string());
+        point.fields.insert("cpu".to_string(), FieldValue::Float(0.5));
+        point.fields.insert("count".to_string(), FieldValue::Integer(3));
+        point.fields.insert("ok".to_string(), FieldValue::Boolean(true));
+        point.fields.insert("state".to_string(), FieldValue::String("up".to_string()));
+        assert_eq!(
+            message_json(&point),
+            serde_json::json!({
+                "timestamp": 1_705_314_600_000_000_000i64,
+                "measurement": "sensors",
+                "tags": {"host": "web-1"},
+                "fields": {"count": 3, "cpu": 0.5, "ok": true, "state": "up"},
+            }),
+        );
+    }
+    
+    #[test]
+    fn points_without_a_time_have_a_null_timestamp() {
+        let mut point = Point::new("sensors", None);
+        point.fields.insert("cpu".to_string(), FieldValue::Float(f64::NAN));
+        let message = message_json(&point);
+        assert!(message["timestamp"].is_null());
+        assert!(message["fields"]["cpu"].is_null());
+        assert_eq!(message["tags"], serde_json::json!({}));
+    }
+}
@@ -0,0 +1,8779 @@
+//! CSV and Arrow to InfluxDB importer. The `importer` binary is a thin wrapper around [`run`];
+//! the record conversion ([`parse_csv_dynamic`], [`DynamicRecord::into_point`]) can also be
+//! used on its own.
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use clap::{Parser, Subcommand};
+use csv::Reader;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use influxdb::{Client, InfluxDbWriteable, ReadQuery, Timestamp};
+use log::{info, warn, error, debug};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+#[cfg(feature = "kafka")]
+mod kafka;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod sqlite_cache;
+
+// Errors produced while importing, so callers can inspect what went wrong
+#[derive(Debug, Error)]
+pub enum CursedStatsError {
+    #[error("failed to parse CSV {path}{}: {source}", .line.map(|l| format!(" at line {}", l)).unwrap_or_default())]
+    CsvParse {
+        path: PathBuf,
+        line: Option<u64>,
+        #[source]
+        source: csv::Error,
+    },
+    
+    #[error("failed to parse timestamp '{raw}' as {format}")]
+    TimestampParse {
+        raw: String,
+        format: String,
+    },
+    
+    #[error("failed to write to measurement {measurement}: {cause}")]
+    InfluxWrite {
+        measurement: String,
+        #[source]
+        cause: influxdb::Error,
+    },
+    
+    #[error("failed to read Arrow file {path}: {source}")]
+    ArrowParse {
+        path: PathBuf,
+        #[source]
+        source: arrow::error::ArrowError,
+    },
+    
+    #[error("cache file {path} I/O error: {cause}")]
+    CacheIo {
+        path: PathBuf,
+        #[source]
+        cause: std::io::Error,
+    },
+    
+    #[error("failed to write output file {path}: {cause}")]
+    OutputIo {
+        path: PathBuf,
+        #[source]
+        cause: std::io::Error,
+    },
+    
+    #[error("failed to hash {path}: {cause}")]
+    HashIo {
+        path: PathBuf,
+        #[source]
+        cause: std::io::Error,
+    },
+    
+    #[error("cache file {path} has schema version {found} (written by importer {written_by}), expected {expected}; rerun with --migrate-cache or --clear-cache-on-version-mismatch")]
+    CacheVersion {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+        written_by: String,
+    },
+    
+    #[error("cache database {path} error: {cause}")]
+    CacheDb {
+        path: PathBuf,
+        #[source]
+        cause: rusqlite::Error,
+    },
+    
+    #[error("transform script {path} failed: {message}")]
+    Transform {
+        path: PathBuf,
+        message: String,
+    },
+    
+    #[error("failed to write to QuestDB at {addr}: {cause}")]
+    QuestDbWrite {
+        addr: String,
+        #[source]
+        cause: std::io::Error,
+    },
+    
+    #[cfg(feature = "postgres")]
+    #[error("failed to write to PostgreSQL table {table}: {cause}")]
+    PostgresWrite {
+        table: String,
+        #[source]
+        cause: tokio_postgres::Error,
+    },
+    
+    #[cfg(feature = "kafka")]
+    #[error("failed to write to Kafka topic {topic}: {cause}")]
+    KafkaWrite {
+        topic: String,
+        #[source]
+        cause: rdkafka::error::KafkaError,
+    },
+}
+
+impl CursedStatsError {
+    // Wrap a CSV error for the given file, keeping the line it occurred on
+    fn csv(path: &Path, source: csv::Error) -> Self {
+        CursedStatsError::CsvParse {
+            path: path.to_path_buf(),
+            line: source.position().map(|p| p.line()),
+            source,
+        }
+    }
+}
+
+// Dynamic record structure for any CSV format
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DynamicRecord {
+    // Every CSV must have a timestamp column
+    pub timestamp: String,
+    // Nanoseconds since the epoch when the input stores timestamps natively (Arrow); points
+    // use it instead of parsing `timestamp`, which then only shows the time in messages
+    #[serde(skip)]
+    pub timestamp_nanos: Option<i64>,
+    // Remaining columns in file order, read like a map (see Fields)
+    #[serde(flatten)]
+    pub fields: Fields,
+}
+
+// Columns of a record in file order. The column names are shared by every record of a
+// file instead of being allocated per row, which dominates for files with thousands of
+// columns
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fields(Vec<(Arc<str>, String)>);
+
+impl Fields {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Fields(Vec::with_capacity(capacity))
+    }
+    
+    // Value of a column; a column stored twice reads as its last value, like a map
+    pub fn get(&self, column: &str) -> Option<&String> {
+        self.0.iter().rev().find(|(name, _)| &**name == column).map(|(_, value)| value)
+    }
+    
+    pub fn contains_key(&self, column: &str) -> bool {
+        self.get(column).is_some()
+    }
+    
+    // Set a column, replacing its value if it is already present. This scans the columns,
+    // so building a whole record goes through push or collect instead
+    pub fn insert(&mut self, column: impl Into<Arc<str>>, value: String) {
+        let column = column.into();
+        match self.0.iter_mut().find(|(name, _)| *name == column) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((column, value)),
+        }
+    }
+    
+    // Append a column without looking for an earlier value, for the columns of a header
+    // row or map that are already known to be distinct
+    fn push(&mut self, column: Arc<str>, value: String) {
+        self.0.push((column, value));
+    }
+    
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &String) -> bool) {
+        self.0.retain(|(name, value)| keep(name, value));
+    }
+    
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &String)> {
+        self.0.iter().map(|(name, value)| (&**name, value))
+    }
+    
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for Fields {
+    type Item = (Arc<str>, String);
+    type IntoIter = std::vec::IntoIter<(Arc<str>, String)>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// A column given twice keeps its first position and its last value, like insert; the
+// positions are looked up in an index so collecting stays linear in the number of columns
+impl<K: Into<Arc<str>>> FromIterator<(K, String)> for Fields {
+    fn from_iter<I: IntoIterator<Item = (K, String)>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut fields = Fields::with_capacity(iter.size_hint().0);
+        let mut positions: HashMap<Arc<str>, usize> = HashMap::with_capacity(iter.size_hint().0);
+        for (column, value) in iter {
+            let column = column.into();
+            match positions.entry(Arc::clone(&column)) {
+                std::collections::hash_map::Entry::Occupied(position) => fields.0[*position.get()].1 = value,
+                std::collections::hash_map::Entry::Vacant(position) => {
+                    position.insert(fields.len());
+                    fields.push(column, value);
+                }
+            }
+        }
+        fields
+    }
+}
+
+// Stored as a plain map of column to value in dead-letter files
+impl Serialize for Fields {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for Fields {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        // Map keys are distinct already
+        let map = BTreeMap::<String, String>::deserialize(deserializer)?;
+        let mut fields = Fields::with_capacity(map.len());
+        for (column, value) in map {
+            fields.push(Arc::from(column), value);
+        }
+        Ok(fields)
+    }
+}
+
+// Explicit InfluxDB type for a column, overriding the numeric/tag heuristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Float,
+    Int,
+    Bool,
+    String,
+    Tag,
+}
+
+impl ColumnType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ColumnType::Float => "float",
+            ColumnType::Int => "int",
+            ColumnType::Bool => "bool",
+            ColumnType::String => "string",
+            ColumnType::Tag => "tag",
+        }
+    }
+}
+
+impl std::str::FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "float" => Ok(ColumnType::Float),
+            "int" | "integer" => Ok(ColumnType::Int),
+            "bool" | "boolean" => Ok(ColumnType::Bool),
+            "string" => Ok(ColumnType::String),
+            "tag" => Ok(ColumnType::Tag),
+            other => Err(format!("unknown column type '{}' (expected float, int, bool, string or tag)", other)),
+        }
+    }
+}
+
+// Format of the input files picked up by the scanner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum InputFormat {
+    Csv,
+    Arrow,
+}
+
+impl InputFormat {
+    // Extensions scanned for when --extensions isn't given
+    fn default_extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Csv => &["csv"],
+            InputFormat::Arrow => &["arrow", "feather", "ipc"],
+        }
+    }
+    
+    fn label(&self) -> &'static str {
+        match self {
+            InputFormat::Csv => "CSV",
+            InputFormat::Arrow => "Arrow",
+        }
+    }
+    
+    fn parse(&self, path: &Path, options: &ParseOptions) -> std::result::Result<ParseOutput, CursedStatsError> {
+        match self {
+            InputFormat::Csv => parse_csv_dynamic(path.to_path_buf(), options),
+            InputFormat::Arrow => parse_arrow(path, options),
+        }
+    }
+}
+
+// Lowercased file extensions the scanner picks up, from --extensions or the input format
+fn scan_extensions(args: &ImportArgs) -> Vec<String> {
+    if args.extensions.is_empty() {
+        args.format.default_extensions().iter().map(|e| e.to_string()).collect()
+    } else {
+        args.extensions.iter().map(|e| e.trim().trim_start_matches('.').to_lowercase()).collect()
+    }
+}
+
+// Read the paths listed in an --index-file, one per line; '#' comments and blank
+// lines are ignored and relative paths are resolved against the scan directory
+fn load_index_file(path: &Path, scan_dir: &Path) -> Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read index file {}", path.display()))?;
+    let paths: Vec<PathBuf> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| scan_dir.join(line))
+        .collect();
+    info!("Index file {} lists {} files", path.display(), paths.len());
+    Ok(paths)
+}
+
+// Whether a path has one of the extensions, ignoring case (DATA.CSV from Windows exports)
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .map_or(false, |ext| extensions.contains(&ext))
+}
+
+// Case normalization applied to CSV column names (and optionally the measurement)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyCase {
+    // "CPU Load" -> "cpu load"
+    Lower,
+    // "CPU Load" / "cpuLoad" / "cpu-load" -> "cpu_load"
+    Snake,
+}
+
+impl KeyCase {
+    fn apply(&self, key: &str) -> String {
+        match self {
+            KeyCase::Lower => key.to_lowercase(),
+            KeyCase::Snake => {
+                let mut out = String::with_capacity(key.len());
+                let mut prev_lower = false;
+                for c in key.trim().chars() {
+                    if c.is_alphanumeric() {
+                        // Split camelCase words on the lower -> upper transition
+                        if c.is_uppercase() && prev_lower {
+                            out.push('_');
+                        }
+                        out.extend(c.to_lowercase());
+                        prev_lower = c.is_lowercase() || c.is_numeric();
+                    } else {
+                        // Collapse runs of spaces and punctuation into a single underscore
+                        if !out.is_empty() && !out.ends_with('_') {
+                            out.push('_');
+                        }
+                        prev_lower = false;
+                    }
+                }
+                out.trim_end_matches('_').to_string()
+            }
+        }
+    }
+}
+
+// Options that control how a record is converted into an InfluxDB query
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    // Per-column type overrides; unlisted columns use the heuristic
+    pub types: HashMap<String, ColumnType>,
+    // Time given to records whose timestamp can't be parsed
+    pub clock: Clock,
+    // Normalization of tag values, so "Linux" and "linux " don't become separate series
+    pub tag_case: Option<TagCase>,
+    pub tag_trim: bool,
+    // Placeholders such as "N/A" that mark a missing reading in a numeric column (--nan-tokens)
+    pub nan_tokens: Vec<String>,
+    // Raw values that mean no reading in any column, e.g. "-999.0" (--null-sentinel)
+    pub null_sentinels: Vec<String>,
+    // Unit timestamps are rounded to (--time-granularity)
+    pub time_granularity: TimeGranularity,
+}
+
+impl QueryOptions {
+    fn is_nan_token(&self, value: &str) -> bool {
+        !self.nan_tokens.is_empty() && self.nan_tokens.iter().any(|token| token == value.trim())
+    }
+    
+    fn is_null_sentinel(&self, value: &str) -> bool {
+        self.null_sentinels.iter().any(|sentinel| sentinel == value)
+    }
+    
+    fn tag_value(&self, value: String) -> String {
+        let value = if self.tag_trim && value.trim().len() != value.len() {
+            value.trim().to_string()
+        } else {
+            value
+        };
+        match self.tag_case {
+            Some(TagCase::Lower) => value.to_lowercase(),
+            Some(TagCase::Upper) => value.to_uppercase(),
+            None => value,
+        }
+    }
+}
+
+// Case applied to tag values (--tag-lowercase/--tag-uppercase)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagCase {
+    Lower,
+    Upper,
+}
+
+// Unit timestamps are rounded to, so that runs with jitter below it write the same points
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimeGranularity {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl TimeGranularity {
+    fn unit_nanos(&self) -> i64 {
+        match self {
+            TimeGranularity::Ns => 1,
+            TimeGranularity::Us => 1_000,
+            TimeGranularity::Ms => 1_000_000,
+            TimeGranularity::S => 1_000_000_000,
+        }
+    }
+    
+    // Round a nanosecond timestamp to the nearest unit, halves rounding up
+    fn round(&self, nanos: i64) -> i64 {
+        let unit = self.unit_nanos();
+        match nanos.checked_add(unit / 2) {
+            Some(shifted) => shifted.div_euclid(unit) * unit,
+            None => nanos.div_euclid(unit) * unit,
+        }
+    }
+}
+
+// Source of the current time, replaceable with a fixed time in tests
+#[derive(Debug, Clone, Copy)]
+pub struct Clock(pub fn() -> chrono::DateTime<chrono::Utc>);
+
+impl Clock {
+    pub fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock(chrono::Utc::now)
+    }
+}
+
+impl DynamicRecord {
+    // Convert the record into a typed point, honouring the configured options
+    pub fn into_point<S: Into<String>>(self, measurement: S, options: &QueryOptions) -> Point {
+        // Use current time if the timestamp can't be parsed or is outside the nanosecond range
+        let timestamp = self.timestamp_nanos
+            .or_else(|| parse_timestamp(&self.timestamp).ok().and_then(|utc_dt| utc_dt.timestamp_nanos_opt()))
+            .unwrap_or_else(|| options.clock.now().timestamp_nanos_opt().unwrap_or(0));
+        let timestamp = options.time_granularity.round(timestamp);
+        let mut point = Point::new(measurement, Some(timestamp));
+        
+        // Add all fields
+        for (key, value) in self.fields {
+            // A sentinel leaves the field or tag out whatever its type
+            if options.is_null_sentinel(&value) {
+                continue;
+            }
+            if let Some(column_type) = options.types.get(&*key) {
+                // A placeholder in a numeric or boolean column is a missing value, not a parse error
+                if !matches!(column_type, ColumnType::String | ColumnType::Tag) && options.is_nan_token(&value) {
+                    continue;
+                }
+                let value = if *column_type == ColumnType::Tag { options.tag_value(value) } else { value };
+                add_typed_value(&mut point, &key, value, *column_type);
+            } else if let Ok(float_val) = value.parse::<f64>() {
+                // Try to parse as number for fields
+                point.fields.insert(key.to_string(), FieldValue::Float(float_val));
+            } else if options.is_nan_token(&value) {
+                // A missing reading, which mustn't turn the column into a tag
+                continue;
+            } else {
+                // Use as tag if not a number
+                point.tags.insert(key.to_string(), options.tag_value(value));
+            }
+        }
+        
+        point
+    }
+    
+    // Convert the record into a write query, honouring the configured options
+    pub fn into_query_with<S: Into<String>>(self, measurement: S, options: &QueryOptions) -> influxdb::WriteQuery {
+        self.into_point(measurement, options).into_query()
+    }
+}
+
+impl InfluxDbWriteable for DynamicRecord {
+    fn into_query<S: Into<String>>(self, measurement: S) -> influxdb::WriteQuery {
+        self.into_query_with(measurement, &QueryOptions::default())
+    }
+}
+
+// A typed point as handed to the sinks, independent of how a backend serializes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, FieldValue>,
+    // Nanoseconds since the epoch; without one the server assigns the time
+    pub timestamp: Option<i64>,
+}
+
+// Value of a point field with its line protocol type
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Float(f64),
+    Integer(i64),
+    Unsigned(u64),
+    Boolean(bool),
+    String(String),
+}
+
+impl Point {
+    pub fn new<S: Into<String>>(measurement: S, timestamp: Option<i64>) -> Self {
+        Point {
+            measurement: measurement.into(),
+            tags: BTreeMap::new(),
+            fields: BTreeMap::new(),
+            timestamp,
+        }
+    }
+    
+    // Drop the NaN and infinite float fields, which line protocol can't hold; returns how
+    // many were dropped
+    pub fn remove_non_finite_fields(&mut self) -> usize {
+        let before = self.fields.len();
+        self.fields.retain(|_, value| !matches!(value, FieldValue::Float(v) if !v.is_finite()));
+        before - self.fields.len()
+    }
+    
+    // Line protocol needs at least one field per point
+    pub fn validate(&self) -> std::result::Result<(), influxdb::Error> {
+        if self.fields.is_empty() {
+            return Err(influxdb::Error::InvalidQueryError { error: "fields cannot be empty".to_string() });
+        }
+        Ok(())
+    }
+    
+    // Serialize the point as a single line of line protocol
+    pub fn to_line_protocol(&self) -> String {
+        let mut line = escape_line_protocol(&self.measurement, &[',', ' ']);
+        for (key, value) in &self.tags {
+            line.push(',');
+            line.push_str(&escape_line_protocol(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape_line_protocol(value, &[',', '=', ' ']));
+        }
+        let fields: Vec<String> = self.fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape_line_protocol(key, &[',', '=', ' ']), value.to_line_protocol()))
+            .collect();
+        line.push(' ');
+        line.push_str(&fields.join(","));
+        if let Some(timestamp) = self.timestamp {
+            line.push_str(&format!(" {}", timestamp));
+        }
+        line
+    }
+    
+    // Parse a line of line protocol, e.g. from a line protocol file being re-imported
+    pub fn from_line_protocol(line: &str) -> Option<Self> {
+        let parts = split_unescaped(line, ' ');
+        let (series, fields, timestamp) = match parts.as_slice() {
+            [series, fields] => (series, fields, None),
+            [series, fields, timestamp] => (series, fields, Some(timestamp.parse::<i64>().ok()?)),
+            _ => return None,
+        };
+        
+        let mut series = split_unescaped(series, ',').into_iter();
+        let mut point = Point::new(unescape_line_protocol(&series.next()?), timestamp);
+        for tag in series {
+            let [key, value] = <[String; 2]>::try_from(split_unescaped(&tag, '=')).ok()?;
+            point.tags.insert(unescape_line_protocol(&key), unescape_line_protocol(&value));
+        }
+        for field in split_unescaped(fields, ',') {
+            let (key, value) = field.split_once('=')?;
+            point.fields.insert(unescape_line_protocol(key), FieldValue::from_line_protocol(value)?);
+        }
+        point.validate().ok()?;
+        Some(point)
+    }
+    
+    // Convert the point into an influxdb crate query
+    pub fn into_query(self) -> influxdb::WriteQuery {
+        let timestamp = Timestamp::Nanoseconds(self.timestamp.unwrap_or_default() as u128);
+        let mut query = influxdb::WriteQuery::new(timestamp, self.measurement);
+        for (key, value) in self.tags {
+            query = query.add_tag(key, value);
+        }
+        for (key, value) in self.fields {
+            query = match value {
+                FieldValue::Float(v) => query.add_field(key, v),
+                FieldValue::Integer(v) => query.add_field(key, v),
+                FieldValue::Unsigned(v) => query.add_field(key, v),
+                FieldValue::Boolean(v) => query.add_field(key, v),
+                FieldValue::String(v) => query.add_field(key, v),
+            };
+        }
+        query
+    }
+}
+
+impl FieldValue {
+    pub fn to_line_protocol(&self) -> String {
+        match self {
+            FieldValue::Float(v) => v.to_string(),
+            FieldValue::Integer(v) => format!("{}i", v),
+            FieldValue::Unsigned(v) => format!("{}u", v),
+            FieldValue::Boolean(v) => v.to_string(),
+            FieldValue::String(v) => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+        }
+    }
+    
+    pub fn from_line_protocol(value: &str) -> Option<Self> {
+        match line_protocol_type(value) {
+            "string" => {
+                let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+                Some(FieldValue::String(inner.replace("\\\"", "\"").replace("\\\\", "\\")))
+            }
+            "integer" => value[..value.len() - 1].parse().ok().map(FieldValue::Integer),
+            "unsigned" => value[..value.len() - 1].parse().ok().map(FieldValue::Unsigned),
+            "boolean" => Some(FieldValue::Boolean(value.starts_with(['t', 'T']))),
+            _ => value.parse().ok().map(FieldValue::Float),
+        }
+    }
+}
+
+// JSON spelling of a field for the PostgreSQL and Kafka sinks; NaN and infinities become null
+impl From<FieldValue> for serde_json::Value {
+    fn from(value: FieldValue) -> Self {
+        match value {
+            FieldValue::Float(v) => serde_json::Number::from_f64(v).map_or(serde_json::Value::Null, serde_json::Value::Number),
+            FieldValue::Integer(v) => v.into(),
+            FieldValue::Unsigned(v) => v.into(),
+            FieldValue::Boolean(v) => v.into(),
+            FieldValue::String(v) => v.into(),
+        }
+    }
+}
+
+// Backslash-escape the given characters of a measurement, key or tag value
+fn escape_line_protocol(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Parse an RFC3339 timestamp into UTC
+fn parse_timestamp(raw: &str) -> std::result::Result<chrono::DateTime<chrono::Utc>, CursedStatsError> {
+    chrono::DateTime::parse_from_rfc3339(raw)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .map_err(|_| CursedStatsError::TimestampParse {
+            raw: raw.to_string(),
+            format: "RFC3339".to_string(),
+        })
+}
+
+// Add a value with an explicitly declared type, skipping values that don't parse
+fn add_typed_value(point: &mut Point, key: &str, value: String, column_type: ColumnType) {
+    // Empty cells are treated as missing rather than as coercion failures
+    if value.is_empty() {
+        return;
+    }
+    
+    let field = match column_type {
+        ColumnType::Float => match value.trim().parse::<f64>() {
+            Ok(v) => FieldValue::Float(v),
+            Err(_) => {
+                warn!("Column '{}' declared float but value '{}' does not parse, skipping", key, value);
+                return;
+            }
+        },
+        ColumnType::Int => match value.trim().parse::<i64>() {
+            Ok(v) => FieldValue::Integer(v),
+            Err(_) => {
+                warn!("Column '{}' declared int but value '{}' does not parse, skipping", key, value);
+                return;
+            }
+        },
+        ColumnType::Bool => match parse_bool(&value) {
+            Some(v) => FieldValue::Boolean(v),
+            None => {
+                warn!("Column '{}' declared bool but value '{}' does not parse, skipping", key, value);
+                return;
+            }
+        },
+        ColumnType::String => FieldValue::String(value),
+        ColumnType::Tag => {
+            point.tags.insert(key.to_string(), value);
+            return;
+        }
+    };
+    point.fields.insert(key.to_string(), field);
+}
+
+// Parse the boolean spellings accepted by InfluxDB line protocol plus common CSV forms
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "t" | "yes" | "y" | "1" => Some(true),
+        "false" | "f" | "no" | "n" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// Parse a column type map such as "cpu=float,count=int,name=tag"
+// Entries may also be separated by newlines, and '#' starts a comment, so a
+// schema file written by --infer-schema can be fed back in with --types-file
+fn parse_type_map(s: &str) -> Result<HashMap<String, ColumnType>, String> {
+    let mut types = HashMap::new();
+    for line in s.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for entry in line.split(',').filter(|e| !e.trim().is_empty()) {
+            let (column, column_type) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("invalid type mapping '{}' (expected column=type)", entry))?;
+            types.insert(column.trim().to_string(), column_type.parse()?);
+        }
+    }
+    Ok(types)
+}
+
+// Load a column type map from a file
+fn load_type_map(path: &Path) -> Result<HashMap<String, ColumnType>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read types file {}", path.display()))?;
+    parse_type_map(&contents).map_err(|e| anyhow!("Invalid types file {}: {}", path.display(), e))
+}
+
+// Rule a column's values must satisfy for the record to be imported (--validate)
+#[derive(Debug, Clone)]
+pub enum ValidationRule {
+    Range(f64, f64),
+    Regex(regex::Regex),
+    NonEmpty,
+    OneOf(Vec<String>),
+}
+
+// A --validate argument: the column and its rule
+#[derive(Debug, Clone)]
+pub struct ColumnRule {
+    column: String,
+    rule: ValidationRule,
+}
+
+impl ValidationRule {
+    // Empty cells count as missing and only fail `nonempty`
+    fn check(&self, value: &str) -> bool {
+        match self {
+            ValidationRule::NonEmpty => !value.is_empty(),
+            _ if value.is_empty() => true,
+            ValidationRule::Range(min, max) => value.trim().parse::<f64>().map_or(false, |v| v >= *min && v <= *max),
+            ValidationRule::Regex(regex) => regex.is_match(value),
+            ValidationRule::OneOf(values) => values.iter().any(|v| v == value),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationRule::Range(min, max) => write!(f, "range:{}:{}", min, max),
+            ValidationRule::Regex(regex) => write!(f, "regex:{}", regex.as_str()),
+            ValidationRule::NonEmpty => write!(f, "nonempty"),
+            ValidationRule::OneOf(values) => write!(f, "oneof:{}", values.join("|")),
+        }
+    }
+}
+
+// Parse a --validate argument such as "cpu:range:0:100" or "host:regex:^web-[0-9]+$"
+fn parse_validation_rule(s: &str) -> Result<ColumnRule, String> {
+    let (column, rule) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid validation '{}' (expected column:rule)", s))?;
+    let (kind, arg) = rule.split_once(':').unwrap_or((rule, ""));
+    let rule = match kind {
+        "range" => {
+            let (min, max) = arg
+                .split_once(':')
+                .ok_or_else(|| format!("invalid range '{}' (expected range:<min>:<max>)", rule))?;
+            let bound = |v: &str| v.trim().parse::<f64>().map_err(|_| format!("invalid range bound '{}' in '{}'", v, s));
+            let (min, max) = (bound(min)?, bound(max)?);
+            if min > max {
+                return Err(format!("invalid range in '{}': minimum is above maximum", s));
+            }
+            ValidationRule::Range(min, max)
+        }
+        "regex" => ValidationRule::Regex(regex::Regex::new(arg).map_err(|e| format!("invalid regex in '{}': {}", s, e))?),
+        "nonempty" => ValidationRule::NonEmpty,
+        "oneof" => ValidationRule::OneOf(arg.split('|').map(str::to_string).collect()),
+        other => return Err(format!("unknown validation rule '{}' (expected range, regex, nonempty or oneof)", other)),
+    };
+    Ok(ColumnRule { column: column.trim().to_string(), rule })
+}
+
+// A computed field (--field-math): the new column and the expression over other columns
+#[derive(Debug, Clone)]
+pub struct FieldMath {
+    column: String,
+    expression: evalexpr::Node,
+}
+
+impl FieldMath {
+    // Evaluate the expression with the record's columns as variables; None when one of them
+    // is missing or not a number, or the result isn't a finite number
+    fn eval(&self, fields: &Fields) -> Option<f64> {
+        use evalexpr::ContextWithMutableVariables;
+        let mut context = evalexpr::HashMapContext::new();
+        for variable in self.expression.iter_variable_identifiers() {
+            let value = fields.get(variable)?.trim().parse::<f64>().ok()?;
+            context.set_value(variable.to_string(), evalexpr::Value::Float(value)).ok()?;
+        }
+        self.expression.eval_number_with_context(&context).ok().filter(|value| value.is_finite())
+    }
+}
+
+// Parse a --field-math argument such as "efficiency=output_power / input_power"
+fn parse_field_math(s: &str) -> Result<FieldMath, String> {
+    let (column, expression) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid field math '{}' (expected column=expression)", s))?;
+    if column.trim().is_empty() {
+        return Err(format!("invalid field math '{}': the column name is empty", s));
+    }
+    let expression = evalexpr::build_operator_tree(expression)
+        .map_err(|e| format!("invalid expression in '{}': {}", s, e))?;
+    Ok(FieldMath { column: column.trim().to_string(), expression })
+}
+
+// Columns of a wide CSV written to their own measurement (--column-group)
+#[derive(Debug, Clone)]
+struct ColumnGroup {
+    measurement: String,
+    columns: Vec<String>,
+}
+
+// Split a record into one sub-record per --column-group with the group's columns, and one
+// for the file's measurement with the remaining columns; each keeps the timestamp
+fn split_record(record: DynamicRecord, measurement: &str, groups: &[ColumnGroup]) -> Vec<(String, DynamicRecord)> {
+    if groups.is_empty() {
+        return vec![(measurement.to_string(), record)];
+    }
+    let mut split = Vec::with_capacity(groups.len() + 1);
+    for group in groups {
+        // A column may be listed in several groups, e.g. a host tag
+        let fields: Fields = group.columns.iter()
+            .filter_map(|column| record.fields.get(column).map(|value| (column.as_str(), value.clone())))
+            .collect();
+        if !fields.is_empty() {
+            split.push((group.measurement.clone(), DynamicRecord { timestamp: record.timestamp.clone(), timestamp_nanos: record.timestamp_nanos, fields }));
+        }
+    }
+    let DynamicRecord { timestamp, timestamp_nanos, mut fields } = record;
+    fields.retain(|column, _| !groups.iter().any(|group| group.columns.iter().any(|c| c == column)));
+    if !fields.is_empty() {
+        split.push((measurement.to_string(), DynamicRecord { timestamp, timestamp_nanos, fields }));
+    }
+    split
+}
+
+// Drop records whose timestamp and key columns repeat an earlier record of the same file
+// (--dedupe-key), returning the records kept and the number dropped. Without key columns
+// whole records are compared. The key values themselves are kept, borrowed from the
+// records, so records are only collapsed when they really are equal
+fn dedupe_records(records: Vec<DynamicRecord>, key_columns: &[String]) -> (Vec<DynamicRecord>, usize) {
+    let before = records.len();
+    let keep: Vec<bool> = {
+        let mut seen: HashSet<(&str, Vec<(&str, Option<&str>)>)> = HashSet::with_capacity(before);
+        records.iter().map(|record| {
+            let key = if key_columns.is_empty() {
+                let mut fields: Vec<(&str, Option<&str>)> = record.fields.iter().map(|(name, value)| (name, Some(value.as_str()))).collect();
+                fields.sort_unstable();
+                fields
+            } else {
+                key_columns.iter().map(|column| (column.as_str(), record.fields.get(column).map(String::as_str))).collect()
+            };
+            seen.insert((record.timestamp.as_str(), key))
+        }).collect()
+    };
+    let records: Vec<DynamicRecord> = records.into_iter().zip(keep).filter_map(|(record, keep)| keep.then_some(record)).collect();
+    let dropped = before - records.len();
+    (records, dropped)
+}
+
+// Check a --dedupe-key column isn't empty: no column could match it, so every record of a
+// timestamp would collapse into the first
+fn parse_dedupe_column(s: &str) -> Result<String, String> {
+    if s.trim().is_empty() {
+        return Err("column names must not be empty; use \"*\" to compare whole records".to_string());
+    }
+    Ok(s.to_string())
+}
+
+// Check a measurement name is usable in line protocol: InfluxDB rejects every point of an
+// empty or blank measurement, and a control character such as a newline splits the line
+fn parse_measurement(s: &str) -> Result<String, String> {
+    if s.trim().is_empty() {
+        return Err(format!("invalid measurement '{}': it must not be empty or only whitespace", s));
+    }
+    if s.chars().any(char::is_control) {
+        return Err(format!("invalid measurement {:?}: it must not contain control characters", s));
+    }
+    Ok(s.to_string())
+}
+
+// Apply --normalize-keys to a measurement, keeping the name as given when nothing would be
+// left of it, e.g. "---" in snake case
+fn normalize_measurement_name(case: KeyCase, measurement: &str) -> String {
+    let normalized = case.apply(measurement);
+    if normalized.trim().is_empty() {
+        warn!("Normalizing measurement '{}' would leave it empty, keeping it as is", measurement);
+        return measurement.to_string();
+    }
+    normalized
+}
+
+// Measurement named after a record's columns (--measurement-template), e.g. "{region}_{service}"
+#[derive(Debug, Clone, PartialEq)]
+struct MeasurementTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Column(String),
+}
+
+// Written back in the syntax it was parsed from
+impl std::fmt::Display for MeasurementTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(text) => f.write_str(text)?,
+                TemplatePart::Column(column) => write!(f, "{{{}}}", column)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MeasurementTemplate {
+    fn map_columns(self, f: impl Fn(&str) -> String) -> Self {
+        let parts = self.parts.into_iter().map(|part| match part {
+            TemplatePart::Column(column) => TemplatePart::Column(f(&column)),
+            literal => literal,
+        }).collect();
+        MeasurementTemplate { parts }
+    }
+    
+    // Render the measurement of a record, taking the columns used out of it. A column the
+    // record lacks or leaves empty is kept as the literal placeholder and returned
+    fn render<'a>(&'a self, fields: &mut Fields) -> (String, Vec<&'a str>) {
+        let mut measurement = String::new();
+        let mut used = Vec::new();
+        let mut missing = Vec::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(text) => measurement.push_str(text),
+                TemplatePart::Column(column) => match fields.get(column).filter(|value| !value.trim().is_empty()) {
+                    Some(value) => {
+                        measurement.push_str(value.trim());
+                        used.push(column.as_str());
+                    }
+                    None => {
+                        measurement.push_str(&format!("{{{}}}", column));
+                        missing.push(column.as_str());
+                    }
+                },
+            }
+        }
+        fields.retain(|column, _| !used.contains(&column));
+        (measurement, missing)
+    }
+}
+
+// Parse a --measurement-template argument into literal text and {column} placeholders.
+// Braces must balance and the template needs at least one placeholder; the names it
+// renders are checked per record, as they depend on the values
+fn parse_measurement_template(s: &str) -> Result<MeasurementTemplate, String> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(format!("invalid measurement template '{}': unmatched '}}'", s));
+        }
+        let end = rest[start..].find('}').map(|end| start + end)
+            .ok_or_else(|| format!("invalid measurement template '{}': unclosed '{{'", s))?;
+        let column = rest[start + 1..end].trim();
+        if column.is_empty() || column.contains('{') {
+            return Err(format!("invalid measurement template '{}': expected a column name between braces", s));
+        }
+        if start > 0 {
+            parts.push(TemplatePart::Literal(rest[..start].to_string()));
+        }
+        parts.push(TemplatePart::Column(column.to_string()));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest.to_string()));
+    }
+    if !parts.iter().any(|part| matches!(part, TemplatePart::Column(_))) {
+        return Err(format!("invalid measurement template '{}': it has no {{column}} placeholder", s));
+    }
+    Ok(MeasurementTemplate { parts })
+}
+
+// Parse a --column-group argument such as "mem:mem_used,mem_free"
+fn parse_column_group(s: &str) -> Result<ColumnGroup, String> {
+    let (measurement, columns) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid column group '{}' (expected measurement:col1,col2,...)", s))?;
+    let columns: Vec<String> = columns.split(',').map(str::trim).filter(|c| !c.is_empty()).map(str::to_string).collect();
+    if columns.is_empty() {
+        return Err(format!("invalid column group '{}': at least one column is needed", s));
+    }
+    Ok(ColumnGroup { measurement: parse_measurement(measurement.trim())?, columns })
+}
+
+// A parsed file on its way from the parser to the DB writer
+struct ParsedFile {
+    records: Vec<DynamicRecord>,
+    path: PathBuf,
+    hash: String,
+    hash_algo: HashAlgo,
+    // Size and modification time of the file when it was hashed
+    signature: Option<FileSignature>,
+    metadata: SidecarMetadata,
+    // Column types from --tag-indices/--field-indices, resolved to this file's column names
+    column_types: HashMap<String, ColumnType>,
+    // When parsing of the file began, for its wall time
+    started: tokio::time::Instant,
+}
+
+// Optional per-file settings read from a `<file>.meta.json` sidecar next to the CSV
+#[derive(Debug, Default, Deserialize)]
+struct SidecarMetadata {
+    // Measurement to use instead of --measurement
+    measurement: Option<String>,
+    // Extra tags added to every record of the file
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+// Load the sidecar for a CSV (`data.csv.meta.json`, or `data.meta.json`), if there is one
+fn load_sidecar_metadata(path: &Path) -> Result<SidecarMetadata> {
+    let mut full_name = path.as_os_str().to_owned();
+    full_name.push(".meta.json");
+    let candidates = [PathBuf::from(full_name), path.with_extension("meta.json")];
+    
+    let Some(candidate) = candidates.iter().find(|c| c.exists()) else {
+        return Ok(SidecarMetadata::default());
+    };
+    
+    let contents = std::fs::read_to_string(candidate)
+        .with_context(|| format!("Failed to read sidecar {}", candidate.display()))?;
+    let metadata: SidecarMetadata = serde_json::from_str(&contents)
+        .with_context(|| format!("Invalid sidecar {}", candidate.display()))?;
+    if let Some(measurement) = &metadata.measurement {
+        parse_measurement(measurement).map_err(|e| anyhow!("Invalid sidecar {}: {}", candidate.display(), e))?;
+    }
+    debug!("Using sidecar {} for {}", candidate.display(), path.display());
+    Ok(metadata)
+}
+
+// Version of the cache file layout; bump it whenever FileMetadata changes incompatibly
+const CACHE_SCHEMA_VERSION: u32 = 1;
+
+// Root of the cache file
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile<T> {
+    schema_version: u32,
+    importer_version: String,
+    entries: T,
+}
+
+// What to do with a cache file written with another schema version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheMismatch {
+    Fail,
+    Migrate,
+    Clear,
+}
+
+// Where the cache is kept (--cache-backend)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CacheBackend {
+    // One JSON file, or one per shard
+    Json,
+    // A SQLite database, updated one entry at a time
+    Sqlite,
+}
+
+impl CacheBackend {
+    fn default_file(self) -> &'static Path {
+        match self {
+            CacheBackend::Json => Path::new(".import_cache.json"),
+            CacheBackend::Sqlite => Path::new(".import_cache.db"),
+        }
+    }
+}
+
+// Structure to store file metadata for caching
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct FileMetadata {
+    path: String,
+    hash: String,
+    last_processed: chrono::DateTime<chrono::Utc>,
+    records_count: usize,
+    // Outcome of the last import of the file
+    #[serde(default)]
+    successful_count: usize,
+    #[serde(default)]
+    failed_count: usize,
+    // Set when the file is queued and cleared once the DB writer has handled it,
+    // so entries still set after a crash mark interrupted files
+    #[serde(default)]
+    in_progress: bool,
+    // Algorithm `hash` was computed with; entries written before --hash-algo used sha256
+    #[serde(default = "HashAlgo::legacy")]
+    hash_algo: HashAlgo,
+    // Size and modification time of the file when it was hashed; an unchanged pair skips
+    // hashing. Missing from entries written by older versions until they are re-hashed
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    mtime: Option<chrono::DateTime<chrono::Utc>>,
+    // Latest timestamp written from the file, so --incremental-files only adds rows after it
+    // once the file has grown
+    #[serde(default)]
+    max_imported_timestamp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl FileMetadata {
+    fn signature(&self) -> Option<FileSignature> {
+        self.size.zip(self.mtime)
+    }
+}
+
+// A file's size and modification time
+type FileSignature = (u64, chrono::DateTime<chrono::Utc>);
+
+fn file_signature(path: &Path) -> std::io::Result<FileSignature> {
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.len(), metadata.modified()?.into()))
+}
+
+// Structure to track insertion statistics
+#[derive(Debug, Default)]
+struct ImportStats {
+    files_found: usize,
+    files_processed: usize,
+    files_skipped: usize,
+    files_failed: usize,
+    // Files the scanner could not hand to the parser stage
+    files_send_errors: usize,
+    // Files outside --file-age-min-hours/--file-age-max-hours
+    files_skipped_age: usize,
+    // Times a file was read to compute its hash
+    files_hashed: usize,
+    records_processed: usize,
+    // Size of the parsed input files
+    bytes_read: usize,
+    records_skipped_no_timestamp: usize,
+    validation_failures: usize,
+    // --field-math fields that couldn't be computed, one per expression and row
+    math_eval_errors: usize,
+    // Zero-byte and header-only files
+    empty_files: usize,
+    successful_inserts: usize,
+    failed_inserts: usize,
+    records_skipped_cap: usize,
+    records_deduplicated: usize,
+    // NaN and infinite float fields left out of their points
+    fields_non_finite: usize,
+    // Records before --since or the latest stored point with --incremental
+    records_before_since: usize,
+    // Records of a changed file at or before the latest one imported from it (--incremental-files)
+    records_already_imported: usize,
+    dead_lettered: usize,
+    payload_bytes: usize,
+    wire_bytes: usize,
+    cache: CacheStats,
+    failovers: usize,
+    // Write requests answered with HTTP 429 or 503
+    throttled: usize,
+    files_verified: usize,
+    verify_failures: usize,
+    files_archived: usize,
+    lines_written: usize,
+    // Messages acknowledged by Kafka brokers
+    kafka_messages_sent: usize,
+    // Per-target insert counts when mirroring to several servers
+    targets: BTreeMap<String, TargetStats>,
+    // Per-stage throughput with --benchmark
+    benchmark: Option<Benchmark>,
+    timings: Timings,
+    // Start of the run, for the insert rate
+    started: Option<tokio::time::Instant>,
+}
+
+// Inserts into a single mirrored target
+#[derive(Debug, Default)]
+struct TargetStats {
+    successful: usize,
+    failed: usize,
+}
+
+// Structure to track how stale the cache entries of skipped files were
+#[derive(Debug, Default)]
+struct CacheStats {
+    skipped: usize,
+    min_staleness_days: f64,
+    max_staleness_days: f64,
+    total_staleness_days: f64,
+}
+
+impl ImportStats {
+    // Lines of the final statistics block
+    fn summary(&self, dry_run: bool, retention_policy: &Option<String>) -> Vec<String> {
+        let mut lines = vec![
+            format!("\nImport Statistics{}:", if dry_run { " (dry run)" } else { "" }),
+            format!("Files found:       {}", self.files_found),
+            format!("Files processed:   {}", self.files_processed),
+            format!("Files skipped:     {}", self.files_skipped),
+        ];
+        if self.files_failed > 0 {
+            lines.push(format!("Files failed:      {}", self.files_failed));
+        }
+        if self.files_send_errors > 0 {
+            lines.push(format!("Files not queued:  {}", self.files_send_errors));
+        }
+        if self.files_skipped_age > 0 {
+            lines.push(format!("Skipped by age:    {}", self.files_skipped_age));
+        }
+        if self.empty_files > 0 {
+            lines.push(format!("Empty files:       {}", self.empty_files));
+        }
+        lines.push(format!("Records processed: {}", self.records_processed));
+        lines.push(format!("Skipped (no timestamp): {}", self.records_skipped_no_timestamp));
+        if self.validation_failures > 0 {
+            lines.push(format!("Failed validation: {}", self.validation_failures));
+        }
+        if self.math_eval_errors > 0 {
+            lines.push(format!("Field math errors: {}", self.math_eval_errors));
+        }
+        lines.push(format!("Successful inserts: {}", self.successful_inserts));
+        lines.push(format!("Failed inserts:    {}", self.failed_inserts));
+        if self.dead_lettered > 0 {
+            lines.push(format!("Dead-lettered:     {}", self.dead_lettered));
+        }
+        if self.records_skipped_cap > 0 {
+            lines.push(format!("Skipped by cap:    {}", self.records_skipped_cap));
+        }
+        if self.records_deduplicated > 0 {
+            lines.push(format!("Duplicates:        {}", self.records_deduplicated));
+        }
+        if self.fields_non_finite > 0 {
+            lines.push(format!("Non-finite fields: {}", self.fields_non_finite));
+        }
+        if self.records_before_since > 0 {
+            lines.push(format!("Skipped (before --since): {}", self.records_before_since));
+        }
+        if self.records_already_imported > 0 {
+            lines.push(format!("Skipped (already imported): {}", self.records_already_imported));
+        }
+        if let Some(rp) = retention_policy {
+            lines.push(format!("Retention policy:  {}", rp));
+        }
+        if self.files_verified > 0 {
+            lines.push(format!("Verified files:    {} ({} mismatched)", self.files_verified, self.verify_failures));
+        }
+        if self.failovers > 0 {
+            lines.push(format!("Endpoint failovers: {}", self.failovers));
+        }
+        if self.throttled > 0 {
+            lines.push(format!("Throttled requests: {}", self.throttled));
+        }
+        if self.lines_written > 0 {
+            lines.push(format!("Lines written:     {}", self.lines_written));
+        }
+        if self.kafka_messages_sent > 0 {
+            lines.push(format!("Kafka messages:    {}", self.kafka_messages_sent));
+        }
+        if self.files_archived > 0 {
+            lines.push(format!("Files archived:    {}", self.files_archived));
+        }
+        for (target, stats) in &self.targets {
+            lines.push(format!("Target {}: {} successful, {} failed", target, stats.successful, stats.failed));
+        }
+        if self.payload_bytes > 0 {
+            lines.push(format!("Bytes written:     {} ({} on the wire, {:.1}% saved)",
+                     self.payload_bytes,
+                     self.wire_bytes,
+                     100.0 * (1.0 - self.wire_bytes as f64 / self.payload_bytes as f64)));
+        }
+        if self.cache.skipped > 0 {
+            lines.push(format!("Cache staleness (days): min {:.1}, max {:.1}, mean {:.1}",
+                     self.cache.min_staleness_days,
+                     self.cache.max_staleness_days,
+                     self.cache.mean_staleness_days()));
+        }
+        if let Some(rate) = self.insert_rate() {
+            lines.push(format!("Insert rate:       {:.0} points/s", rate));
+        }
+        if let Some((records_per_second, megabytes_per_second)) = self.throughput() {
+            lines.push(format!("Throughput:        {:.0} records/s, {:.2} MB/s read", records_per_second, megabytes_per_second));
+        }
+        if let Some([p50, p95, p99]) = self.timings.batch_percentiles() {
+            lines.push(format!("Batch write latency: p50 {:.1} ms, p95 {:.1} ms, p99 {:.1} ms ({} batches)",
+                     millis(p50), millis(p95), millis(p99), self.timings.batch_latencies.len()));
+        }
+        if let Some((path, slowest)) = self.timings.slowest_file() {
+            lines.push(format!("File wall time:    mean {:.2}s, slowest {:.2}s ({})",
+                     self.timings.mean_file_time().as_secs_f64(), slowest.as_secs_f64(), path));
+        }
+        if let Some(benchmark) = &self.benchmark {
+            lines.extend(benchmark.summary());
+        }
+        lines
+    }
+    
+    // One-line progress report for --stats-interval
+    fn progress(&self) -> String {
+        let mut line = format!("Progress: {} of {} files processed, {} records, {} inserted, {} failed",
+                                  self.files_processed, self.files_found, self.records_processed,
+                                  self.successful_inserts, self.failed_inserts);
+        if let Some(rate) = self.insert_rate() {
+            line.push_str(&format!(", {:.0} points/s", rate));
+        }
+        if let Some([p50, p95, p99]) = self.timings.batch_percentiles() {
+            line.push_str(&format!(", batch latency p50/p95/p99 {:.1}/{:.1}/{:.1} ms", millis(p50), millis(p95), millis(p99)));
+        }
+        line
+    }
+    
+    // Records parsed and megabytes of input read per second since the start of the run
+    fn throughput(&self) -> Option<(f64, f64)> {
+        let seconds = self.started?.elapsed().as_secs_f64();
+        (seconds > 0.0 && self.records_processed > 0)
+            .then(|| (self.records_processed as f64 / seconds, self.bytes_read as f64 / 1_000_000.0 / seconds))
+    }
+    
+    // Outcome reported to --notify-webhook: "failed" when the writer stopped early or
+    // nothing could be written, "partial" when some records or files failed
+    fn status(&self, completed: bool) -> &'static str {
+        if !completed || (self.failed_inserts > 0 && self.successful_inserts == 0) {
+            "failed"
+        } else if self.failed_inserts > 0 || self.files_failed > 0 || self.files_send_errors > 0 {
+            "partial"
+        } else {
+            "success"
+        }
+    }
+    
+    // Successful inserts per second since the start of the run
+    fn insert_rate(&self) -> Option<f64> {
+        let seconds = self.started?.elapsed().as_secs_f64();
+        (seconds > 0.0 && self.successful_inserts > 0).then(|| self.successful_inserts as f64 / seconds)
+    }
+    
+    // Final statistics for --summary-json
+    fn to_json(&self, dry_run: bool) -> serde_json::Value {
+        let percentiles = self.timings.batch_percentiles();
+        let targets: serde_json::Map<String, serde_json::Value> = self.targets.iter()
+            .map(|(target, stats)| (target.clone(), serde_json::json!({ "successful": stats.successful, "failed": stats.failed })))
+            .collect();
+        serde_json::json!({
+            "dry_run": dry_run,
+            "files_found": self.files_found,
+            "files_processed": self.files_processed,
+            "files_skipped": self.files_skipped,
+            "files_failed": self.files_failed,
+            "files_send_errors": self.files_send_errors,
+            "files_skipped_age": self.files_skipped_age,
+            "files_hashed": self.files_hashed,
+            "empty_files": self.empty_files,
+            "records_processed": self.records_processed,
+            "bytes_read": self.bytes_read,
+            "records_per_second": self.throughput().map(|(records, _)| records),
+            "megabytes_per_second": self.throughput().map(|(_, megabytes)| megabytes),
+            "records_skipped_no_timestamp": self.records_skipped_no_timestamp,
+            "validation_failures": self.validation_failures,
+            "math_eval_errors": self.math_eval_errors,
+            "records_deduplicated": self.records_deduplicated,
+            "fields_non_finite": self.fields_non_finite,
+            "records_before_since": self.records_before_since,
+            "records_already_imported": self.records_already_imported,
+            "successful_inserts": self.successful_inserts,
+            "failed_inserts": self.failed_inserts,
+            "dead_lettered": self.dead_lettered,
+            "payload_bytes": self.payload_bytes,
+            "wire_bytes": self.wire_bytes,
+            "throttled_requests": self.throttled,
+            "kafka_messages_sent": self.kafka_messages_sent,
+            "elapsed_seconds": self.started.map(|started| started.elapsed().as_secs_f64()),
+            "points_per_second": self.insert_rate(),
+            "batch_write_latency_ms": {
+                "batches": self.timings.batch_latencies.len(),
+                "p50": percentiles.map(|p| millis(p[0])),
+                "p95": percentiles.map(|p| millis(p[1])),
+                "p99": percentiles.map(|p| millis(p[2])),
+            },
+            "files": self.timings.file_times.iter()
+                .map(|(path, wall_time)| serde_json::json!({ "path": path, "wall_seconds": wall_time.as_secs_f64() }))
+                .collect::<Vec<_>>(),
+            "targets": targets,
+        })
+    }
+}
+
+// Write latency of every batch and wall time of every file, from the start of parsing to
+// its last acknowledged batch
+#[derive(Debug, Default)]
+struct Timings {
+    batch_latencies: Vec<std::time::Duration>,
+    file_times: Vec<(String, std::time::Duration)>,
+}
+
+impl Timings {
+    // p50, p95 and p99 of the batch write latencies (nearest rank)
+    fn batch_percentiles(&self) -> Option<[std::time::Duration; 3]> {
+        if self.batch_latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.batch_latencies.clone();
+        sorted.sort_unstable();
+        let rank = |pct: f64| sorted[((pct / 100.0 * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len()) - 1];
+        Some([rank(50.0), rank(95.0), rank(99.0)])
+    }
+    
+    fn slowest_file(&self) -> Option<(&str, std::time::Duration)> {
+        self.file_times.iter().max_by_key(|(_, time)| *time).map(|(path, time)| (path.as_str(), *time))
+    }
+    
+    fn mean_file_time(&self) -> std::time::Duration {
+        let total: std::time::Duration = self.file_times.iter().map(|(_, time)| *time).sum();
+        total / self.file_times.len().max(1) as u32
+    }
+}
+
+fn millis(duration: std::time::Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+// Work done by the pipeline stages with --benchmark. Throughput is measured over the time
+// spent in each stage, summed across concurrent tasks, so waiting on other stages and
+// channels doesn't count
+#[derive(Debug, Default)]
+struct Benchmark {
+    scanner: StageTimer,
+    parser: StageTimer,
+    serialization: StageTimer,
+    write: StageTimer,
+}
+
+#[derive(Debug, Default)]
+struct StageTimer {
+    busy: std::time::Duration,
+    items: usize,
+    bytes: usize,
+}
+
+impl StageTimer {
+    fn record(&mut self, busy: std::time::Duration, items: usize, bytes: usize) {
+        self.busy += busy;
+        self.items += items;
+        self.bytes += bytes;
+    }
+    
+    fn per_second(&self, count: usize) -> f64 {
+        let seconds = self.busy.as_secs_f64();
+        if seconds > 0.0 { count as f64 / seconds } else { 0.0 }
+    }
+    
+    fn megabytes_per_second(&self) -> f64 {
+        self.per_second(self.bytes) / 1_000_000.0
+    }
+}
+
+impl Benchmark {
+    fn summary(&self) -> Vec<String> {
+        vec![
+            "\nBenchmark:".to_string(),
+            format!("Scanner:           {} files in {:.3}s ({:.1} files/s)",
+                     self.scanner.items, self.scanner.busy.as_secs_f64(), self.scanner.per_second(self.scanner.items)),
+            format!("Parser:            {} records in {:.3}s ({:.0} records/s, {:.1} MB/s of input)",
+                     self.parser.items, self.parser.busy.as_secs_f64(), self.parser.per_second(self.parser.items),
+                     self.parser.megabytes_per_second()),
+            format!("Serialization:     {} records in {:.3}s ({:.0} records/s, {:.1} MB/s of line protocol)",
+                     self.serialization.items, self.serialization.busy.as_secs_f64(),
+                     self.serialization.per_second(self.serialization.items), self.serialization.megabytes_per_second()),
+            format!("DB write (mocked): {} records in {:.3}s ({:.0} records/s, {:.1} MB/s)",
+                     self.write.items, self.write.busy.as_secs_f64(), self.write.per_second(self.write.items),
+                     self.write.megabytes_per_second()),
+        ]
+    }
+}
+
+impl CacheStats {
+    fn record_skip(&mut self, age: chrono::Duration) {
+        let days = age.num_seconds() as f64 / 86_400.0;
+        if self.skipped == 0 {
+            self.min_staleness_days = days;
+            self.max_staleness_days = days;
+        } else {
+            self.min_staleness_days = self.min_staleness_days.min(days);
+            self.max_staleness_days = self.max_staleness_days.max(days);
+        }
+        self.total_staleness_days += days;
+        self.skipped += 1;
+    }
+    
+    fn mean_staleness_days(&self) -> f64 {
+        if self.skipped == 0 {
+            0.0
+        } else {
+            self.total_staleness_days / self.skipped as f64
+        }
+    }
+}
+
+// Kind of server written to; VictoriaMetrics accepts line protocol but has no databases
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TargetKind {
+    #[default]
+    Influxdb,
+    Victoriametrics,
+}
+
+impl TargetKind {
+    // Unauthenticated endpoint used to check that a server is up
+    fn health_path(&self) -> &'static str {
+        match self {
+            TargetKind::Influxdb => "/ping",
+            TargetKind::Victoriametrics => "/health",
+        }
+    }
+}
+
+// InfluxDB API version used for writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+enum ApiVersion {
+    #[value(name = "1")]
+    #[serde(rename = "1")]
+    V1,
+    #[value(name = "2")]
+    #[serde(rename = "2")]
+    V2,
+}
+
+// Log level accepted by --log-level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(&self) -> log::LevelFilter {
+        match self {
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+    
+    // Step the level up by the given number of -v flags, saturating at trace
+    fn raised(self, steps: u8) -> Self {
+        let levels = [LogLevel::Error, LogLevel::Warn, LogLevel::Info, LogLevel::Debug, LogLevel::Trace];
+        let index = levels.iter().position(|l| *l == self).unwrap_or(2) + steps as usize;
+        levels[index.min(levels.len() - 1)]
+    }
+}
+
+/// CSV Importer for InfluxDB - processes CSV files and imports data into InfluxDB
+#[derive(Parser)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    
+    // Without a subcommand the arguments are those of `import`, as before subcommands existed
+    #[command(flatten)]
+    args: ImportArgs,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Import CSV files into InfluxDB (the default when no subcommand is given)
+    Import(ImportArgs),
+    /// Retry the records of the NDJSON file given with --dead-letter, leaving only those that
+    /// fail again in it; takes the import options for the targets
+    Retry(ImportArgs),
+    /// Inspect and maintain the cache file without importing anything
+    Cache {
+        #[command(flatten)]
+        file: CacheFileArgs,
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+}
+
+#[derive(clap::Args)]
+struct CacheFileArgs {
+    /// Path to the cache file [default: .import_cache.json, or .import_cache.db with
+    /// --cache-backend sqlite]
+    #[arg(long, global = true)]
+    cache_file: Option<PathBuf>,
+    
+    /// Where the cache is kept, as with --cache-backend on import
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json, global = true)]
+    cache_backend: CacheBackend,
+    
+    /// Load a cache written with a different schema version, filling in new fields with defaults
+    #[arg(long, conflicts_with = "clear_cache_on_version_mismatch", global = true)]
+    migrate_cache: bool,
+    
+    /// Start with an empty cache when the cache file has a different schema version
+    #[arg(long, global = true)]
+    clear_cache_on_version_mismatch: bool,
+    
+    /// Number of files the cache is split into, as with --cache-shard-count on import
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), global = true)]
+    cache_shard_count: u32,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List the cache entries, sorted by path
+    List {
+        /// Only list entries whose path matches this glob, e.g. "*/2024-*.csv"
+        #[arg(long, value_name = "GLOB")]
+        filter: Option<glob::Pattern>,
+        
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Show the entry of one file and whether the file on disk still matches it
+    Show {
+        path: PathBuf,
+        
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+    },
+    /// Remove the entries of these files, or of the files matching these globs, so the next
+    /// run imports them again
+    Invalidate {
+        #[arg(required = true, value_name = "PATH_OR_GLOB")]
+        targets: Vec<String>,
+    },
+    /// Remove entries of files that no longer exist
+    Prune {
+        /// Also remove entries last imported longer ago than this, e.g. 90d or 12h
+        #[arg(long, value_name = "AGE", value_parser = parse_age)]
+        older_than: Option<chrono::Duration>,
+    },
+    /// Copy the JSON cache into a SQLite cache for --cache-backend sqlite, replacing the
+    /// entries it already has
+    Migrate {
+        /// SQLite cache to write
+        #[arg(long, value_name = "PATH", default_value = ".import_cache.db")]
+        to: PathBuf,
+    },
+}
+
+// Output of `cache list` and `cache show`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ListFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(clap::Args)]
+pub struct ImportArgs {
+    /// Directory to scan for CSV files ("-" reads CSV from standard input, like --stdin)
+    #[arg(short, long, default_value = ".")]
+    scan_dir: PathBuf,
+    
+    /// Read a single CSV file from standard input instead of scanning a directory
+    #[arg(long, conflicts_with_all = ["index_file", "post_import_move", "requeue_in_progress"])]
+    stdin: bool,
+    
+    /// Input file format: csv, or arrow for Arrow IPC / Feather v2 files (.arrow, .feather, .ipc)
+    #[arg(long, value_enum, default_value = "csv")]
+    format: InputFormat,
+    
+    /// Import only the files listed in this file (one path per line, relative to --scan-dir)
+    /// instead of scanning the directory
+    #[arg(long)]
+    index_file: Option<PathBuf>,
+    
+    /// Skip files modified less than this many hours ago, e.g. files still being written
+    #[arg(long, value_name = "HOURS", value_parser = parse_hours)]
+    file_age_min_hours: Option<f64>,
+    
+    /// Skip files last modified more than this many hours ago
+    #[arg(long, value_name = "HOURS", value_parser = parse_hours)]
+    file_age_max_hours: Option<f64>,
+    
+    /// Import only this file instead of scanning a directory
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["scan_dir", "index_file", "stdin"])]
+    file: Option<PathBuf>,
+    
+    /// File extensions to scan for, matched case-insensitively, e.g. "csv,dat,log" [default: csv,
+    /// or arrow,feather,ipc with --format arrow]
+    #[arg(long, value_delimiter = ',', value_name = "EXTS")]
+    extensions: Vec<String>,
+    
+    /// InfluxDB URL, or a comma-separated failover list; repeat to mirror every write to several servers
+    /// sharing the other connection options
+    #[arg(short, long, default_value = "http://127.0.0.1:8086")]
+    url: Vec<String>,
+    
+    /// TOML file listing the InfluxDB servers to mirror writes to, each with its own url/db/auth
+    #[arg(long, conflicts_with = "url")]
+    targets: Option<PathBuf>,
+    
+    /// InfluxDB URL of a secondary target that is written alongside --url, sharing its connection
+    /// options; its failures are logged but don't fail records (repeatable)
+    #[arg(long, value_name = "URL", conflicts_with = "targets")]
+    secondary_url: Vec<String>,
+    
+    /// Number of primary targets that must accept a record for it to count as written (default: all)
+    #[arg(long, value_name = "N")]
+    write_quorum: Option<usize>,
+    
+    /// Also require secondary targets to accept a record for it to count as written
+    #[arg(long, conflicts_with = "write_quorum")]
+    require_all_targets: bool,
+    
+    /// InfluxDB database name
+    #[arg(short = 'b', long, default_value = "cursed_stats")]
+    db_name: String,
+    
+    /// InfluxDB 1.x retention policy to write into (default: the database's default policy)
+    #[arg(long)]
+    retention_policy: Option<String>,
+    
+    /// InfluxDB username
+    #[arg(long)]
+    username: Option<String>,
+    
+    /// InfluxDB password (prefer INFLUXDB_PASSWORD or --password-file to keep it out of shell history)
+    #[arg(long, env = "INFLUXDB_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    
+    /// Path to a file containing the InfluxDB password (takes precedence over --password)
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+    
+    /// InfluxDB 2.x API token (selects the 2.x write path unless --api-version is given)
+    #[arg(long, env = "INFLUXDB_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    
+    /// InfluxDB 2.x organization
+    #[arg(long)]
+    org: Option<String>,
+    
+    /// InfluxDB 2.x bucket (defaults to --db-name)
+    #[arg(long)]
+    bucket: Option<String>,
+    
+    /// InfluxDB API version to write with (default: 2 when a token is given, otherwise 1)
+    #[arg(long, value_enum)]
+    api_version: Option<ApiVersion>,
+    
+    /// Kind of server to write to: influxdb, or victoriametrics (line protocol on /write,
+    /// no database, no InfluxDB pre-flight checks)
+    #[arg(long, value_enum, default_value = "influxdb")]
+    target_kind: TargetKind,
+    
+    /// Label added to every series written to VictoriaMetrics, as NAME=VALUE (repeatable)
+    #[arg(long, value_name = "NAME=VALUE", action = clap::ArgAction::Append)]
+    extra_label: Vec<String>,
+    
+    /// Skip the startup pre-flight checks (ping, credentials, database and retention policy)
+    #[arg(long)]
+    skip_preflight: bool,
+    
+    /// Create the database (1.x) or bucket (2.x) at startup if it doesn't exist (the default)
+    #[arg(long, overrides_with = "no_create_db")]
+    create_db: bool,
+    
+    /// Don't create the database or bucket at startup
+    #[arg(long, overrides_with = "create_db")]
+    no_create_db: bool,
+    
+    /// Additional PEM root certificate to trust when connecting over HTTPS
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+    
+    /// Disable TLS certificate verification (lab environments only)
+    #[arg(long)]
+    insecure_skip_tls_verify: bool,
+    
+    /// PEM client certificate for mutual TLS
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+    
+    /// PEM private key for the client certificate
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+    
+    /// Measurement name for the data
+    #[arg(short, long, default_value = "stats", value_parser = parse_measurement)]
+    measurement: String,
+    
+    /// Name each record's measurement after its columns, e.g. "{region}_{service}"; the columns
+    /// used are left out of the point. Records lacking a column keep its placeholder literally
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_measurement_template, conflicts_with = "verify")]
+    measurement_template: Option<MeasurementTemplate>,
+    
+    /// Explicit per-column types, e.g. "cpu=float,count=int,active=bool,name=tag"
+    #[arg(long, value_parser = parse_type_map)]
+    types: Option<HashMap<String, ColumnType>>,
+    
+    /// File of per-column types (as written by --infer-schema); --types entries take precedence
+    #[arg(long)]
+    types_file: Option<PathBuf>,
+    
+    /// Infer column types from a sample of files, write them to this path and exit without importing
+    #[arg(long)]
+    infer_schema: Option<PathBuf>,
+    
+    /// Number of files to sample with --infer-schema
+    #[arg(long, default_value_t = 20)]
+    schema_sample: usize,
+    
+    /// Normalize CSV column names (and sidecar tag keys) before writing: lower or snake (snake_case)
+    #[arg(long, value_enum, value_name = "CASE")]
+    normalize_keys: Option<KeyCase>,
+    
+    /// Also apply --normalize-keys to the measurement name
+    #[arg(long, requires = "normalize_keys")]
+    normalize_measurement: bool,
+    
+    /// Lowercase all tag values read from the input
+    #[arg(long, conflicts_with = "tag_uppercase")]
+    tag_lowercase: bool,
+    
+    /// Uppercase all tag values read from the input
+    #[arg(long)]
+    tag_uppercase: bool,
+    
+    /// Strip leading and trailing whitespace from all tag values read from the input
+    #[arg(long)]
+    tag_trim: bool,
+    
+    /// Values that mark a missing reading rather than text, e.g. "N/A,NULL,-". They are left out
+    /// instead of turning a numeric column into a tag or failing a declared numeric type
+    #[arg(long, value_delimiter = ',', value_name = "TOKENS")]
+    nan_tokens: Vec<String>,
+    
+    /// A raw value that means no reading, e.g. 9999 or -999.0; fields and tags with exactly this
+    /// value are left out of the point. Repeat for several sentinels
+    #[arg(long, value_name = "VALUE")]
+    null_sentinel: Vec<String>,
+    
+    /// Round timestamps to the nearest ns, us, ms or s, so runs whose times differ by less
+    /// than that write the same points
+    #[arg(long, value_enum, default_value_t = TimeGranularity::Ns, value_name = "UNIT")]
+    time_granularity: TimeGranularity,
+    
+    /// Only import records at or after this time (RFC 3339), e.g. "2024-01-15T00:00:00Z"
+    #[arg(long, value_parser = parse_timestamp, value_name = "TIME")]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    
+    /// Look up the latest point stored for --measurement and each --column-group measurement
+    /// at startup, and for --measurement-template measurements when first written to, and
+    /// only import records at or after it, like a per-measurement --since. Needs InfluxDB
+    /// targets to query
+    #[arg(long, conflicts_with_all = ["output_only", "output_lp", "stdout", "questdb_addr", "pg_dsn", "kafka_brokers"])]
+    incremental: bool,
+    
+    /// Only import the rows of a changed file after the latest one imported from it by an
+    /// earlier run, as recorded in its cache entry; no target is queried
+    #[arg(long)]
+    incremental_files: bool,
+    
+    /// Columns whose cells hold JSON objects to flatten into `<column>_<key>` fields, e.g. "io,meta"
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS")]
+    json_columns: Vec<String>,
+    
+    /// Zero-based position of the timestamp column, whatever its header says
+    #[arg(long, value_name = "INDEX")]
+    timestamp_index: Option<usize>,
+    
+    /// The input files have no header row: the first row is data and columns are named
+    /// column_0, column_1, ...; needs --timestamp-index
+    #[arg(long, requires = "timestamp_index")]
+    no_header: bool,
+    
+    /// Zero-based positions of columns to write as tags, e.g. "1,2"; --types declared for the
+    /// column's name take precedence
+    #[arg(long, value_delimiter = ',', value_name = "INDICES")]
+    tag_indices: Vec<usize>,
+    
+    /// Zero-based positions of columns to write as float fields, e.g. "3,4"; other columns keep
+    /// the numeric/tag heuristic
+    #[arg(long, value_delimiter = ',', value_name = "INDICES")]
+    field_indices: Vec<usize>,
+    
+    /// Skip records whose column fails a rule: range:<min>:<max>, regex:<pattern>, nonempty
+    /// or oneof:<v1>|<v2>|...; repeatable, e.g. --validate cpu:range:0:100
+    #[arg(long, value_name = "COL:RULE", value_parser = parse_validation_rule)]
+    validate: Vec<ColumnRule>,
+    
+    /// Add a field computed from other columns, e.g. "efficiency=output_power / input_power";
+    /// repeatable. Rows where a column of the expression is missing or not a number get no field
+    #[arg(long, value_name = "COL=EXPR", value_parser = parse_field_math)]
+    field_math: Vec<FieldMath>,
+    
+    /// Write these columns to their own measurement, as <measurement>:<col1,col2,...>; repeatable.
+    /// Columns in no group go to the default measurement, and every group gets the timestamp
+    #[arg(long, value_name = "MEASUREMENT:COLUMNS", value_parser = parse_column_group, conflicts_with = "verify")]
+    column_group: Vec<ColumnGroup>,
+    
+    /// Collapse records of a file whose timestamp and these columns repeat an earlier record of
+    /// the same file, keeping the first; "*" compares whole records. This only holds within a
+    /// run: re-importing a file never skips records, and is idempotent only because InfluxDB
+    /// overwrites points with the same measurement, tags and timestamp
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS", value_parser = parse_dedupe_column)]
+    dedupe_key: Vec<String>,
+    
+    /// Lua script defining `function transform(row) ... return row end`, called with a table of
+    /// the columns of every row; the returned table replaces the row, nil or invalid skips it
+    #[arg(long, value_name = "FILE")]
+    transform_script: Option<PathBuf>,
+    
+    /// Log min/max/mean, empty and distinct counts for every column of each file
+    #[arg(long)]
+    column_stats: bool,
+    
+    /// Also write the column statistics to InfluxDB (implies --column-stats)
+    #[arg(long)]
+    column_stats_to_influx: bool,
+    
+    /// Measurement for --column-stats-to-influx (default: <measurement>_meta)
+    #[arg(long, requires = "column_stats_to_influx", value_parser = parse_measurement)]
+    stats_measurement: Option<String>,
+    
+    /// Number of records sent to InfluxDB per write request
+    #[arg(long, default_value_t = 5000)]
+    batch_size: usize,
+    
+    /// Gzip-compress write request bodies (Content-Encoding: gzip)
+    #[arg(long)]
+    compress_writes: bool,
+    
+    /// Number of scanner threads
+    #[arg(long, default_value_t = 2)]
+    scanner_threads: usize,
+    
+    /// Number of parser threads
+    #[arg(long, default_value_t = 4)]
+    parser_threads: usize,
+    
+    /// Maximum number of input files open at once across parser tasks
+    #[arg(long, default_value_t = 64)]
+    max_open_files: usize,
+    
+    /// Read input files through a buffer of this size, e.g. 4M for network filesystems where
+    /// small reads are slow; applies to hashing and parsing
+    #[arg(long, value_name = "BYTES", value_parser = parse_byte_size)]
+    read_buffer_size: Option<usize>,
+    
+    /// Number of DB writer threads
+    #[arg(long, default_value_t = 4)]
+    db_threads: usize,
+    
+    /// Write the batches of each file one after another, in file order, so that when rows share
+    /// a timestamp and series the last row of the file wins in InfluxDB. Files are still written
+    /// concurrently. --dedupe-key instead keeps the first of such rows before anything is written
+    #[arg(long)]
+    preserve_order: bool,
+    
+    /// Capacity of the channel carrying file paths from the scanner to the parser [default: 100000]
+    #[arg(long, alias = "file-buffer-size")]
+    file_channel_size: Option<usize>,
+    
+    /// Capacity of the channel carrying parsed record batches to the DB writer [default: 16]
+    /// (each entry holds a whole file's records, so keep this small)
+    #[arg(long, alias = "record-buffer-size")]
+    record_channel_size: Option<usize>,
+    
+    /// Deprecated: sets both --file-channel-size and --record-channel-size
+    #[arg(long)]
+    buffer_size: Option<usize>,
+    
+    /// Path to the cache file [default: .import_cache.json, or .import_cache.db with
+    /// --cache-backend sqlite]
+    #[arg(long)]
+    cache_file: Option<PathBuf>,
+    
+    /// Keep the cache in one JSON file (or one per shard), rewritten as files finish, or in a
+    /// SQLite database where each finished file updates just its own entry
+    #[arg(long, value_enum, default_value_t = CacheBackend::Json)]
+    cache_backend: CacheBackend,
+    
+    /// Split the cache into this many files, <cache-file>.shard_<i>.json, each saved on its own
+    /// so writers finishing files in different shards don't wait for each other. JSON cache only
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    cache_shard_count: u32,
+    
+    /// Force re-processing of all files even if in cache
+    #[arg(long)]
+    force: bool,
+    
+    /// Import files matching this glob even if they are in the cache; other files are skipped
+    /// as usual. Repeatable
+    #[arg(long, value_name = "GLOB")]
+    only: Vec<glob::Pattern>,
+    
+    /// Don't read or write the cache file; every file is treated as new
+    #[arg(long)]
+    no_cache: bool,
+    
+    /// What identifies a file in the cache: its path together with the measurements and the
+    /// database/bucket written to, so one file can be imported to several destinations, or
+    /// its path alone
+    #[arg(long, value_enum, default_value_t = CacheKey::Destination)]
+    cache_key: CacheKey,
+    
+    /// Hash used to detect changed files: sha256, blake3, xxhash64 or xxh3 (the fastest). Cache entries hashed with
+    /// another algorithm are checked with that one, so switching doesn't re-import anything
+    #[arg(long, value_enum, default_value_t = HashAlgo::default())]
+    hash_algo: HashAlgo,
+    
+    /// Hash every cached file to decide whether it changed, instead of trusting an unchanged
+    /// size and modification time
+    #[arg(long)]
+    paranoid: bool,
+    
+    /// Load a cache written with a different schema version, filling in new fields with defaults
+    #[arg(long, conflicts_with = "clear_cache_on_version_mismatch")]
+    migrate_cache: bool,
+    
+    /// Start with an empty cache when the cache file has a different schema version
+    #[arg(long)]
+    clear_cache_on_version_mismatch: bool,
+    
+    /// Remove cache entries of files that no longer exist before importing
+    #[arg(long, conflicts_with = "no_cache")]
+    prune_cache: bool,
+    
+    /// With --prune-cache, also remove entries last imported longer ago than this, e.g. 90d or 12h
+    #[arg(long, value_name = "AGE", value_parser = parse_age, requires = "prune_cache")]
+    prune_older_than: Option<chrono::Duration>,
+    
+    /// Queue files left in progress by an interrupted run before scanning for new ones
+    #[arg(long)]
+    requeue_in_progress: bool,
+    
+    /// Parse files and build queries without contacting InfluxDB or updating the cache
+    #[arg(long)]
+    dry_run: bool,
+    
+    /// Dry run that measures the throughput of each pipeline stage, serializing every batch
+    /// and timing a mocked write of it
+    #[arg(long, conflicts_with_all = ["post_import_move", "output_only", "output_lp", "stdout", "import_dead_letters"])]
+    benchmark: bool,
+    
+    /// Number of times a failed write request is retried (connection errors, HTTP 429 and 5xx);
+    /// HTTP 429 and 503 pause all writes to the target for the server's Retry-After
+    #[arg(long, default_value_t = 3)]
+    write_retries: u32,
+    
+    /// Treat a file as failed, and don't cache it, if any of its records fail to write
+    #[arg(long)]
+    atomic_file: bool,
+    
+    /// Still cache a partially written file when the percentage of failed records is below this value
+    #[arg(long, value_name = "PCT", default_value_t = 0.0)]
+    cache_failure_threshold: f64,
+    
+    /// Append records that permanently fail to write to this NDJSON file
+    #[arg(long)]
+    dead_letter: Option<PathBuf>,
+    
+    /// Retry the dead letters (NDJSON or line protocol, optionally gzipped) in this file or
+    /// directory instead of scanning for CSVs
+    #[arg(long, value_name = "DIR")]
+    import_dead_letters: Option<PathBuf>,
+    
+    /// Stop writing to a measurement once this many records have been inserted into it
+    #[arg(long, value_name = "N")]
+    max_records_per_measurement: Option<usize>,
+    
+    /// After writing each file, count its records in InfluxDB and compare; mismatches exit with code 4
+    #[arg(long)]
+    verify: bool,
+    
+    /// Tag every point with source_file=<path of the input file> (lets --verify check exactly)
+    #[arg(long)]
+    tag_source_file: bool,
+    
+    /// Move each successfully imported file to this directory, keeping its path relative to --scan-dir
+    #[arg(long, value_name = "DIR", conflicts_with = "dry_run")]
+    post_import_move: Option<PathBuf>,
+    
+    /// Exit with code 2 if no records were processed
+    #[arg(long)]
+    alert_on_zero_records: bool,
+    
+    /// Exit with code 3 if the percentage of failed inserts exceeds this value
+    #[arg(long, value_name = "PCT")]
+    alert_on_failure_rate: Option<f64>,
+    
+    /// POST the final statistics as JSON to this URL when the import finishes
+    #[arg(long, value_name = "URL")]
+    notify_webhook: Option<String>,
+    
+    /// Also POST a "started" event to --notify-webhook when the import begins
+    #[arg(long, requires = "notify_webhook")]
+    notify_on_start: bool,
+    
+    /// Write a point to the _import_runs measurement when the import starts and another when
+    /// it finishes, as an audit log of runs
+    #[arg(long)]
+    write_run_metadata: bool,
+    
+    /// Write line protocol to this file instead of InfluxDB (no network access, cache untouched)
+    #[arg(long, conflicts_with = "dry_run")]
+    output_only: Option<PathBuf>,
+    
+    /// Split --output-only files by the period of each record's timestamp, e.g. <base>_2024-01-15.lp
+    #[arg(long, value_enum, requires = "output_only")]
+    split_files_by_date: Option<SplitPeriod>,
+    
+    /// Write one --output-only file per measurement, e.g. <base>_cpu.lp
+    #[arg(long, requires = "output_only")]
+    split_files_by_measurement: bool,
+    
+    /// Write line protocol to one file per input file in this directory instead of InfluxDB;
+    /// imported files are cached like a real import
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["dry_run", "output_only"])]
+    output_lp: Option<PathBuf>,
+    
+    /// Gzip the files written by --output-lp (.lp.gz)
+    #[arg(long, requires = "output_lp")]
+    output_lp_gzip: bool,
+    
+    /// Print line protocol to standard output instead of writing to InfluxDB (logs go to stderr
+    /// or the log file, cache untouched); with --stdin this is a CSV to line protocol converter
+    #[arg(long, conflicts_with_all = ["dry_run", "output_only", "output_lp", "summary_only"])]
+    stdout: bool,
+    
+    /// Print at most this many points with --stdout, e.g. for a look at the first ones; the
+    /// rest are still parsed but not printed
+    #[arg(long, value_name = "N", requires = "stdout")]
+    limit: Option<usize>,
+    
+    /// Stream line protocol to QuestDB's ILP TCP port instead of InfluxDB; measurements
+    /// become table names
+    #[arg(long, value_name = "HOST:PORT", conflicts_with_all = ["output_only", "output_lp", "stdout"])]
+    questdb_addr: Option<String>,
+    
+    /// Write to a PostgreSQL/TimescaleDB table instead of InfluxDB, e.g.
+    /// "host=localhost user=stats dbname=stats" or postgres://stats@localhost/stats
+    #[cfg(feature = "postgres")]
+    #[arg(long, env = "PG_DSN", hide_env_values = true, conflicts_with_all = ["output_only", "output_lp", "stdout", "questdb_addr"])]
+    pg_dsn: Option<String>,
+    
+    /// Table written with --pg-dsn, optionally schema-qualified; created if needed
+    #[cfg(feature = "postgres")]
+    #[arg(long, default_value = "cursed_stats", requires = "pg_dsn")]
+    pg_table: String,
+    
+    /// Publish every record as a JSON message to Kafka instead of writing to InfluxDB, e.g.
+    /// "kafka-1:9092,kafka-2:9092"
+    #[cfg(feature = "kafka")]
+    #[arg(long, value_name = "BROKERS", requires = "kafka_topic", conflicts_with_all = ["output_only", "output_lp", "stdout", "questdb_addr"])]
+    #[cfg_attr(feature = "postgres", arg(conflicts_with = "pg_dsn"))]
+    kafka_brokers: Option<String>,
+    
+    /// Topic written with --kafka-brokers
+    #[cfg(feature = "kafka")]
+    #[arg(long, requires = "kafka_brokers")]
+    kafka_topic: Option<String>,
+    
+    /// Path to log file (empty to disable file logging)
+    #[arg(long, default_value = "importer.log")]
+    log_file: PathBuf,
+    
+    /// Enable console logging (in addition to file logging if configured)
+    #[arg(long)]
+    console: bool,
+    
+    /// Log level: error, warn, info, debug or trace. The first occurrence sets the console
+    /// level (default: info), a second one the log file level (default: debug)
+    #[arg(long, value_enum, value_name = "LEVEL", action = clap::ArgAction::Append)]
+    log_level: Vec<LogLevel>,
+    
+    /// Raise the console log level one step per occurrence (-v debug, -vv trace)
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    
+    /// Only log warnings and errors to the console and print the final statistics to stdout
+    /// (like --log-level warn --console)
+    #[arg(long, conflicts_with_all = ["log_level", "verbose"])]
+    summary_only: bool,
+    
+    /// Log progress (files, records, insert rate and write latency) every SECS seconds
+    #[arg(long, value_name = "SECS")]
+    stats_interval: Option<u64>,
+    
+    /// Write the final statistics, with write latency percentiles and per-file wall times, to
+    /// this JSON file
+    #[arg(long, value_name = "PATH")]
+    summary_json: Option<PathBuf>,
+}
+
+// Run the subcommand, returning the process exit code: 0 on success, 2-4 when an import's
+// --alert-on-* or --verify check fails. Logging is left to the caller
+pub fn run(cli: Cli) -> Result<i32> {
+    match cli.command {
+        Some(Command::Import(args)) => import(args),
+        Some(Command::Retry(args)) => retry(args),
+        Some(Command::Cache { file, action }) => run_cache_command(&file, &action),
+        None => import(cli.args),
+    }
+}
+
+fn import(args: ImportArgs) -> Result<i32> {
+    import_into(args, None)
+}
+
+// Replay a dead-letter file written by --dead-letter. Records that fail again are collected
+// in a sibling file which then replaces it, so the file always holds what is still missing;
+// a dry run leaves it alone
+fn retry(mut args: ImportArgs) -> Result<i32> {
+    let file = args.dead_letter.take().ok_or_else(|| anyhow!("retry needs the file to replay as --dead-letter <PATH>"))?;
+    if !file.is_file() {
+        return Err(anyhow!("--dead-letter {} is not a file", file.display()));
+    }
+    // Only NDJSON can be written back in the format it was read in
+    let extension = file.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    if !matches!(extension.as_str(), "json" | "ndjson" | "jsonl") {
+        return Err(anyhow!("retry rewrites NDJSON dead-letter files; use --import-dead-letters for {}", file.display()));
+    }
+    args.import_dead_letters = Some(file.clone());
+    if args.dry_run {
+        return import(args);
+    }
+    
+    let mut pending = file.clone().into_os_string();
+    pending.push(".retry");
+    let pending = PathBuf::from(pending);
+    match std::fs::remove_file(&pending) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("Failed to remove the leftover {}: {}", pending.display(), e));
+        }
+        _ => {}
+    }
+    args.dead_letter = Some(pending.clone());
+    let code = import(args)?;
+    std::fs::rename(&pending, &file)
+        .with_context(|| format!("Failed to replace {} with the records that failed again", file.display()))?;
+    Ok(code)
+}
+
+// Import into the given sinks instead of the destinations the arguments configure
+fn import_into(args: ImportArgs, sinks: Option<Vec<Box<dyn Sink>>>) -> Result<i32> {
+    // Schema inference only reads files, it never touches InfluxDB or the cache
+    if let Some(output) = &args.infer_schema {
+        infer_schema(&args.scan_dir, args.format, &scan_extensions(&args), args.schema_sample, &ParseOptions::from_args(&args)?, &args.nan_tokens, output)?;
+        return Ok(0);
+    }
+    
+    // Create shared statistics
+    let stats = Arc::new(Mutex::new(ImportStats {
+        benchmark: args.benchmark.then(Benchmark::default),
+        started: Some(tokio::time::Instant::now()),
+        ..ImportStats::default()
+    }));
+    let benchmark = args.benchmark;
+    
+    // Load file cache if it exists
+    let cache_store = open_cache_store(args.cache_backend, args.cache_file.as_deref(), args.cache_shard_count);
+    let cache = if args.no_cache {
+        info!("Cache disabled (--no-cache): every file will be imported");
+        HashMap::new()
+    } else {
+        let mut cache = match cache_store.load(cache_mismatch(args.migrate_cache, args.clear_cache_on_version_mismatch)) {
+            Ok(cache) => cache,
+            Err(e @ CursedStatsError::CacheVersion { .. }) => return Err(e.into()),
+            Err(e) => {
+                warn!("Ignoring unreadable cache: {}", e);
+                HashMap::new()
+            }
+        };
+        info!("Loaded cache with {} entries", cache.len());
+        let migrated = canonicalize_cache_keys(&mut cache);
+        if migrated > 0 {
+            info!("Converted {} cache entries to canonical paths", migrated);
+        }
+        if args.prune_cache {
+            let pruned = prune_cache(&mut cache, &cache_base_dir(cache_store.as_ref()), args.prune_older_than, chrono::Utc::now());
+            if pruned > 0 && !(args.dry_run || args.benchmark) {
+                cache_store.save_map(&cache)?;
+            }
+            info!("Pruned {} cache entries, {} left", pruned, cache.len());
+        }
+        cache
+    };
+    
+    let cache = Arc::new(Mutex::new(cache));
+    let dry_run = args.dry_run || args.benchmark;
+    let update_cache = !dry_run && args.output_only.is_none() && !args.stdout && !args.no_cache;
+    
+    // Create three Tokio runtimes for different stages
+    let scanner_runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(args.scanner_threads)
+        .thread_name("scanner-pool")
+        .enable_all()
+        .build()
+        .context("Failed to build scanner runtime")?;
+    
+    let parser_runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(args.parser_threads)
+        .thread_name("parser-pool")
+        .enable_all()
+        .build()
+        .context("Failed to build parser runtime")?;
+    
+    let db_runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(args.db_threads)
+        .thread_name("db-pool")
+        .enable_all()
+        .build()
+        .context("Failed to build db runtime")?;
+    
+    // Channels between stages
+    if args.buffer_size.is_some() {
+        warn!("--buffer-size is deprecated, use --file-channel-size and --record-channel-size");
+    }
+    let file_channel_size = args.file_channel_size.or(args.buffer_size).unwrap_or(100_000);
+    let record_channel_size = args.record_channel_size.or(args.buffer_size).unwrap_or(16);
+    let (file_tx, mut file_rx) = mpsc::channel::<QueuedFile>(file_channel_size);
+    let (record_tx, mut record_rx) = mpsc::channel::<ParsedFile>(record_channel_size);
+    
+    // Channels for shutdown coordination
+    let (parser_complete_tx, parser_complete_rx) = oneshot::channel();
+    let (db_complete_tx, db_complete_rx) = oneshot::channel();
+    
+    // Clone stats for each stage
+    let db_stats = Arc::clone(&stats);
+    let parser_stats = Arc::clone(&stats);
+    let scanner_stats = Arc::clone(&stats);
+    
+    // Clone cache for each stage
+    let db_cache = Arc::clone(&cache);
+    let scanner_cache = Arc::clone(&cache);
+    
+    // Build a write target for every configured InfluxDB server
+    let http = build_http_client(&args)?;
+    let targets = load_targets(&args)?
+        .iter()
+        .map(|config| InfluxTarget::connect(config, &args, http.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let secondaries = targets.iter().filter(|t| t.config.secondary).count();
+    let required = if args.require_all_targets { targets.len() } else { targets.len() - secondaries };
+    if required == 0 {
+        return Err(anyhow!("At least one target must be a primary target"));
+    }
+    if let (Some(min), Some(max)) = (args.file_age_min_hours, args.file_age_max_hours) {
+        if min > max {
+            return Err(anyhow!("--file-age-min-hours ({}) is above --file-age-max-hours ({}), every file would be skipped", min, max));
+        }
+    }
+    let write_quorum = match args.write_quorum {
+        Some(n) if n == 0 || n > required => {
+            return Err(anyhow!("--write-quorum must be between 1 and the number of primary targets ({})", required));
+        }
+        Some(n) => n,
+        None => required,
+    };
+    if targets.len() > 1 {
+        info!("Mirroring writes to {} targets (quorum {})", targets.len(), write_quorum);
+    }
+    if secondaries > 0 && !args.require_all_targets {
+        info!("{} secondary target(s) are best effort: their failures don't fail records", secondaries);
+    }
+    
+    // Stage 3: InfluxDB inserter
+    let db_cache_store = Arc::clone(&cache_store);
+    let measurement = args.measurement.clone();
+    let mut types = match &args.types_file {
+        Some(path) => load_type_map(path)?,
+        None => HashMap::new(),
+    };
+    types.extend(args.types.clone().unwrap_or_default());
+    // Type overrides are keyed by column name, so they follow the column normalization
+    let normalize_keys = args.normalize_keys;
+    if let Some(case) = normalize_keys {
+        types = types.into_iter().map(|(column, column_type)| (case.apply(&column), column_type)).collect();
+    }
+    let normalize_measurement = args.normalize_measurement;
+    let column_groups: Vec<ColumnGroup> = args.column_group.iter().map(|group| ColumnGroup {
+        measurement: match normalize_keys.filter(|_| normalize_measurement) {
+            Some(case) => normalize_measurement_name(case, &group.measurement),
+            None => group.measurement.clone(),
+        },
+        columns: group.columns.iter().map(|c| normalize_keys.map_or_else(|| c.clone(), |case| case.apply(c))).collect(),
+    }).collect();
+    let measurement_template = args.measurement_template.clone()
+        .map(|template| template.map_columns(|c| normalize_keys.map_or_else(|| c.to_string(), |case| case.apply(c))));
+    let dedupe_key = (!args.dedupe_key.is_empty()).then(|| {
+        args.dedupe_key.iter()
+            .filter(|column| column.as_str() != "*")
+            .map(|c| normalize_keys.map_or_else(|| c.clone(), |case| case.apply(c)))
+            .collect::<Vec<String>>()
+    });
+    let input_format = args.format;
+    let extensions = scan_extensions(&args);
+    let index = match (&args.file, &args.index_file) {
+        (Some(file), _) if !file.is_file() => return Err(anyhow!("--file {} is not a file", file.display())),
+        (Some(file), _) => Some(vec![file.clone()]),
+        (None, Some(path)) => Some(load_index_file(path, &args.scan_dir)?),
+        (None, None) => None,
+    };
+    // Standard input is read up front and imported as a single unnamed file
+    let stdin_input = if args.stdin || args.scan_dir.as_os_str() == "-" {
+        if input_format != InputFormat::Csv {
+            return Err(anyhow!("Only CSV can be read from standard input"));
+        }
+        if args.index_file.is_some() || args.post_import_move.is_some() {
+            return Err(anyhow!("--index-file and --post-import-move can't be used when reading standard input"));
+        }
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input).context("Failed to read standard input")?;
+        info!("Read {} bytes from standard input", input.len());
+        Some(input)
+    } else {
+        None
+    };
+    let read_stdin = stdin_input.is_some();
+    let stdin_record_tx = stdin_input.as_ref().map(|_| record_tx.clone());
+    let parse_options = Arc::new(ParseOptions::from_args(&args)?);
+    let open_files = Arc::new(tokio::sync::Semaphore::new(args.max_open_files.max(1)));
+    let scanner_parse_options = Arc::clone(&parse_options);
+    let tag_case = match (args.tag_lowercase, args.tag_uppercase) {
+        (true, _) => Some(TagCase::Lower),
+        (_, true) => Some(TagCase::Upper),
+        _ => None,
+    };
+    let query_options = QueryOptions {
+        types,
+        tag_case,
+        tag_trim: args.tag_trim,
+        nan_tokens: args.nan_tokens.clone(),
+        null_sentinels: args.null_sentinel.clone(),
+        time_granularity: args.time_granularity,
+        ..QueryOptions::default()
+    };
+    let batch_size = args.batch_size.max(1);
+    let retention_policy = args.retention_policy.clone();
+    let max_records_per_measurement = args.max_records_per_measurement;
+    let atomic_file = args.atomic_file;
+    let cache_failure_threshold = args.cache_failure_threshold;
+    let column_stats_enabled = args.column_stats || args.column_stats_to_influx;
+    let column_stats_to_influx = args.column_stats_to_influx;
+    let stats_measurement = args.stats_measurement.clone();
+    let summary_only = args.summary_only;
+    let summary_json = args.summary_json.clone();
+    let dead_letter = match &args.dead_letter {
+        Some(path) => Some(Arc::new(DeadLetterWriter::open(path)?)),
+        None => None,
+    };
+    let tag_source_file = args.tag_source_file;
+    let post_import_move = args.post_import_move.clone();
+    let scan_dir = args.scan_dir.clone();
+    if args.verify && targets.iter().any(|t| t.kind == TargetKind::Victoriametrics) {
+        warn!("--verify is not supported for VictoriaMetrics targets, skipping them");
+    }
+    let mut sinks: Vec<Box<dyn Sink>> = match (sinks, &args.output_only, &args.output_lp) {
+        (Some(sinks), _, _) => sinks,
+        (None, Some(path), _) => vec![Box::new(Mutex::new(LineProtocolFile::create(path, args.split_files_by_date, args.split_files_by_measurement)?)) as Box<dyn Sink>],
+        (None, None, Some(dir)) => vec![Box::new(Mutex::new(LineProtocolDir::create(dir, &args.scan_dir, args.output_lp_gzip)?)) as Box<dyn Sink>],
+        (None, None, None) if args.stdout => vec![Box::new(Mutex::new(LineProtocolStdout::new(args.limit))) as Box<dyn Sink>],
+        (None, None, None) if args.questdb_addr.is_some() => vec![Box::new(QuestDbSink::new(
+            args.questdb_addr.as_deref().unwrap_or_default(), args.write_retries,
+        )) as Box<dyn Sink>],
+        #[cfg(feature = "postgres")]
+        (None, None, None) if args.pg_dsn.is_some() => vec![Box::new(postgres::PostgresSink::new(
+            args.pg_dsn.as_deref().unwrap_or_default(), &args.pg_table, &query_options.types, args.write_retries,
+        )?) as Box<dyn Sink>],
+        #[cfg(feature = "kafka")]
+        (None, None, None) if args.kafka_brokers.is_some() => vec![Box::new(kafka::KafkaSink::new(
+            args.kafka_brokers.as_deref().unwrap_or_default(), args.kafka_topic.as_deref().unwrap_or_default(),
+            args.write_retries, args.compress_writes,
+        )?) as Box<dyn Sink>],
+        (None, None, None) => targets.into_iter().map(|t| Box::new(t) as Box<dyn Sink>).collect(),
+    };
+    
+    // Verify connectivity and credentials before any parsing begins
+    if benchmark {
+        info!("Benchmark: measuring pipeline throughput, nothing will be written to InfluxDB or the cache");
+    } else if dry_run {
+        info!("Dry run: nothing will be written to InfluxDB or the cache");
+    } else {
+        // An unreachable secondary target is dropped rather than failing the import
+        sinks = db_runtime.block_on(async {
+            let mut reachable = Vec::with_capacity(sinks.len());
+            for mut sink in sinks {
+                match sink.preflight().await {
+                    Ok(()) => reachable.push(sink),
+                    Err(e) if sink.secondary() && !args.require_all_targets => {
+                        warn!("Dropping secondary target {}: {:#}", sink.name(), e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok::<_, anyhow::Error>(reachable)
+        })?;
+    }
+    let incremental_since = if args.incremental && (dry_run || benchmark) {
+        warn!("--incremental needs to query the targets, ignoring it in a dry run");
+        HashMap::new()
+    } else if args.incremental {
+        // A target without stored points to look up would have every record imported anyway
+        if let Some(sink) = sinks.iter().find(|sink| !sink.secondary() && !sink.queries_last_timestamp()) {
+            return Err(anyhow!("--incremental can't look up the latest point on {}; it only works with InfluxDB targets", sink.name()));
+        }
+        let mut measurements = vec![match normalize_keys.filter(|_| normalize_measurement) {
+            Some(case) => normalize_measurement_name(case, &measurement),
+            None => measurement.clone(),
+        }];
+        measurements.extend(column_groups.iter().map(|group| group.measurement.clone()));
+        let sinks: Vec<&dyn Sink> = sinks.iter().map(|sink| sink.as_ref()).collect();
+        db_runtime.block_on(latest_timestamps(&sinks, measurements))?
+    } else {
+        HashMap::new()
+    };
+    let verifiers: Vec<Verifier> = if args.verify && !dry_run {
+        sinks.iter()
+            .filter_map(|sink| sink.verifier().map(|verifier| Verifier {
+                required: args.require_all_targets || !sink.secondary(),
+                ..verifier
+            }))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let cache_destination = (args.cache_key == CacheKey::Destination).then(|| {
+        let destinations: Vec<String> = sinks.iter().map(|sink| sink.destination()).collect();
+        CacheDestination {
+            targets: destinations.join(","),
+            measurement: args.measurement.clone(),
+            template: args.measurement_template.as_ref().map(|template| template.to_string()),
+            column_groups: column_groups.iter().map(|group| group.measurement.clone()).collect(),
+        }
+    });
+    if let Some(destination) = &cache_destination {
+        let assigned = assign_path_keys(&mut cache.lock().unwrap(), destination);
+        if assigned > 0 {
+            info!("Assigned {} cache entries keyed by path alone to this run's destination", assigned);
+            if update_cache {
+                cache_store.save_map(&cache.lock().unwrap())?;
+            }
+        }
+    }
+    let sink = BatchSink::new(sinks, args.require_all_targets);
+    let mirrored = sink.target_count() > 1;
+    
+    // Files still marked in progress were in flight when a previous run to the same
+    // destination was interrupted
+    let mut in_progress: Vec<PathBuf> = cache.lock().unwrap().iter()
+        .filter(|(key, m)| {
+            m.in_progress && **key == cache_key(&m.path, cache_destination.as_ref().map(|d| d.for_file(Path::new(&m.path))).as_deref())
+        })
+        .map(|(_, m)| PathBuf::from(&m.path))
+        .collect();
+    in_progress.sort();
+    if !in_progress.is_empty() {
+        warn!("{} file(s) were in progress when a previous run stopped{}", in_progress.len(),
+                 if args.requeue_in_progress { ", re-queueing them first" } else { "" });
+        for path in &in_progress {
+            warn!("  {}", path.display());
+        }
+    }
+    
+    if let (Some(url), true) = (&args.notify_webhook, args.notify_on_start) {
+        db_runtime.block_on(notify_webhook(url, serde_json::json!({
+            "status": "started",
+            "dry_run": dry_run,
+            "scan_dir": args.scan_dir,
+            "measurement": args.measurement,
+        })));
+    }
+    
+    // Retrying dead letters replaces the CSV pipeline entirely
+    if let Some(dir) = &args.import_dead_letters {
+        let result = db_runtime.block_on(import_dead_letters(
+            dir, &sink, batch_size, write_quorum, dry_run, &query_options, dead_letter.as_deref(),
+        ));
+        if let Some(url) = &args.notify_webhook {
+            let event = match &result {
+                Ok(stats) => completion_event(stats, true, dry_run),
+                Err(_) => completion_event(&ImportStats::default(), false, dry_run),
+            };
+            db_runtime.block_on(notify_webhook(url, event));
+        }
+        let stats = result?;
+        for line in stats.summary(dry_run, &retention_policy) {
+            if summary_only {
+                println!("{}", line);
+            } else {
+                info!("{}", line);
+            }
+        }
+        if let Some(path) = &summary_json {
+            write_summary_json(path, &stats, dry_run);
+        }
+        if stats.failed_inserts > 0 || stats.files_failed > 0 {
+            error!("{} dead letters failed again and {} files could not be read", stats.failed_inserts, stats.files_failed);
+            return Ok(5);
+        }
+        return Ok(0);
+    }
+    
+    let run_metadata = args.write_run_metadata && !dry_run;
+    if run_metadata {
+        db_runtime.block_on(write_run_metadata(&sink, run_metadata_point("started", &args.scan_dir, None, &query_options.clock)));
+    }
+    
+    // Handles needed to save the cache if the writer panics
+    let panic_cache = Arc::clone(&db_cache);
+    let panic_cache_store = Arc::clone(&db_cache_store);
+    let db_threads = args.db_threads.max(1);
+    let db_writer = Arc::new(DbWriter {
+        sink,
+        write_slots: Arc::new(tokio::sync::Semaphore::new(db_threads)),
+        measurement,
+        normalize_keys,
+        normalize_measurement,
+        column_groups,
+        measurement_template,
+        dedupe_key,
+        since: args.since.and_then(|since| since.timestamp_nanos_opt()),
+        incremental: args.incremental && !dry_run && !benchmark,
+        incremental_since: Mutex::new(incremental_since),
+        file_increments: args.incremental_files && !args.force,
+        query_options,
+        batch_size,
+        dry_run,
+        benchmark,
+        preserve_order: args.preserve_order,
+        compress_writes: args.compress_writes,
+        mirrored,
+        write_quorum,
+        max_records_per_measurement,
+        measurement_counts: Mutex::new(HashMap::new()),
+        column_stats_enabled,
+        column_stats_to_influx,
+        stats_measurement,
+        dead_letter,
+        tag_source_file,
+        verifiers,
+        update_cache,
+        atomic_file,
+        cache_failure_threshold,
+        post_import_move,
+        scan_dir,
+        db_stats,
+        db_cache,
+        db_cache_store,
+        cache_destination: cache_destination.clone(),
+    });
+    let _db_handle: JoinHandle<()> = db_runtime.spawn(async move {
+        // Run the writer in its own task so a panic surfaces as a JoinError here
+        let writer: JoinHandle<()> = tokio::spawn(async move {
+            // Every file is written by its own task so the batches of several files are in
+            // flight at once; at most --db-threads files are held in memory at a time
+            let mut files = tokio::task::JoinSet::new();
+            
+            info!("DB Writer ready, waiting for records...");
+            while let Some(parsed) = record_rx.recv().await {
+                while files.len() >= db_threads {
+                    propagate_panic(files.join_next().await);
+                }
+                let db_writer = Arc::clone(&db_writer);
+                files.spawn(async move { db_writer.write_file(parsed).await });
+            }
+            while !files.is_empty() {
+                propagate_panic(files.join_next().await);
+            }
+            
+            info!("DB Writer finished");
+            let DbWriter { sink, db_stats, db_cache, db_cache_store, scan_dir, query_options, .. } = &*db_writer;
+            if run_metadata {
+                let point = run_metadata_point("finished", scan_dir, Some(&db_stats.lock().unwrap()), &query_options.clock);
+                write_run_metadata(sink, point).await;
+            }
+            if let Err(e) = sink.finish() {
+                error!("Failed to finish output: {}", e);
+            }
+            
+            // Display final statistics
+            let mut stats = db_stats.lock().unwrap();
+            stats.failovers = sink.failovers();
+            stats.throttled = sink.throttled();
+            stats.lines_written = sink.lines_written();
+            stats.kafka_messages_sent = sink.messages_sent();
+            for line in stats.summary(dry_run, &retention_policy) {
+                if summary_only {
+                    println!("{}", line);
+                } else {
+                    info!("{}", line);
+                }
+            }
+            if let Some(path) = &summary_json {
+                write_summary_json(path, &stats, dry_run);
+            }
+            
+            // Final cache save
+            if update_cache {
+                if let Err(e) = db_cache_store.flush(db_cache) {
+                    error!("Failed to save final cache: {}", e);
+                }
+            }
+        });
+        
+        let result = match writer.await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("DB writer failed: {}", e);
+                // Keep the files that were fully written before the failure
+                if update_cache {
+                    let cache = panic_cache.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+                    match panic_cache_store.save_map(&cache) {
+                        Ok(()) => info!("Saved cache with {} entries after DB writer failure", cache.len()),
+                        Err(e) => error!("Failed to save cache after DB writer failure: {}", e),
+                    }
+                }
+                Err(e.to_string())
+            }
+        };
+        
+        // Signal completion
+        let _ = db_complete_tx.send(result);
+    });
+    
+    // Periodic progress reports until the import completes
+    let progress = args.stats_interval.filter(|&secs| secs > 0).map(|secs| {
+        let stats = Arc::clone(&stats);
+        db_runtime.spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(secs));
+            // The first tick completes immediately
+            let mut last_tick = interval.tick().await;
+            let (mut last_records, mut last_bytes) = (0, 0);
+            loop {
+                let tick = interval.tick().await;
+                let (progress, records, bytes) = {
+                    let stats = stats.lock().unwrap();
+                    (stats.progress(), stats.records_processed, stats.bytes_read)
+                };
+                // Rolling throughput since the previous report
+                let seconds = (tick - last_tick).as_secs_f64();
+                info!("{} (last {:.0}s: {:.0} records/s, {:.2} MB/s read)", progress, seconds,
+                         (records - last_records) as f64 / seconds, (bytes - last_bytes) as f64 / 1_000_000.0 / seconds);
+                (last_tick, last_records, last_bytes) = (tick, records, bytes);
+            }
+        })
+    });
+    
+    // Stage 2: CSV parser
+    let hash_algo = args.hash_algo;
+    let _parser_handle: JoinHandle<()> = parser_runtime.spawn(async move {
+        let record_tx = record_tx; // Take ownership
+        
+        info!("CSV Parser ready, waiting for files...");
+        while let Some(QueuedFile { path, hashed }) = file_rx.recv().await {
+            let path_str = path.display().to_string(); // For error reporting
+            let record_tx = record_tx.clone(); 
+            let parser_stats_clone = Arc::clone(&parser_stats);
+            let parse_options = Arc::clone(&parse_options);
+            let open_files = Arc::clone(&open_files);
+            
+            info!("Processing file: {}", path_str);
+            {
+                let mut stats = parser_stats.lock().unwrap();
+                stats.files_processed += 1;
+            }
+            
+            tokio::spawn(async move {
+                // Hold a permit while the file is open so a low ulimit -n isn't exceeded
+                if open_files.available_permits() == 0 {
+                    debug!("Waiting for an open file slot for {} (--max-open-files)", path_str);
+                }
+                let permit = open_files.acquire_owned().await;
+                
+                // Calculate file hash for consistency checking, unless the scanner already did.
+                // The size and mtime are read first, so a file changing while it is hashed is
+                // hashed again on the next run
+                let (file_hash, signature) = match hashed {
+                    Some(hashed) => hashed,
+                    None => {
+                        let signature = file_signature(&path).ok();
+                        parser_stats_clone.lock().unwrap().files_hashed += 1;
+                        match hash_algo.hasher().hash_file(&path, parse_options.read_buffer_size) {
+                            Ok(hash) => (hash, signature),
+                            Err(e) => {
+                                error!("Failed to calculate hash for {}: {}", path_str, e);
+                                return;
+                            }
+                        }
+                    }
+                };
+                
+                // Per-file overrides from an optional sidecar metadata file
+                let metadata = match load_sidecar_metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        error!("Skipping {}: {}", path_str, e);
+                        return;
+                    }
+                };
+                
+                let parse_started = tokio::time::Instant::now();
+                let parsed = input_format.parse(&path, &parse_options);
+                let parse_time = parse_started.elapsed();
+                drop(permit);
+                match parsed {
+                    Ok(ParseOutput { records, skipped_no_timestamp, validation_failures, math_eval_errors, column_types, empty }) => {
+                        let input_bytes = signature.map_or(0, |(size, _)| size as usize);
+                        {
+                            let mut stats = parser_stats_clone.lock().unwrap();
+                            stats.records_processed += records.len();
+                            stats.bytes_read += input_bytes;
+                            stats.records_skipped_no_timestamp += skipped_no_timestamp;
+                            stats.validation_failures += validation_failures;
+                            stats.math_eval_errors += math_eval_errors;
+                            stats.empty_files += empty as usize;
+                            if let Some(benchmark) = &mut stats.benchmark {
+                                benchmark.parser.record(parse_time, records.len(), input_bytes);
+                            }
+                        }
+                        if skipped_no_timestamp > 0 {
+                            warn!("Skipped {} records without timestamp in {}", skipped_no_timestamp, path_str);
+                        }
+                        
+                        // Still handed on so the file is cached like any other
+                        if empty {
+                            info!("{} is empty (no data rows)", path_str);
+                        } else {
+                            info!("Parsed {} records from {}", records.len(), path_str);
+                        }
+                        let parsed = ParsedFile { records, path, hash: file_hash, hash_algo, signature, metadata, column_types, started: parse_started };
+                        if let Err(e) = record_tx.send(parsed).await {
+                            error!("Failed to send records: {}", e);
+                        }
+                    },
+                    Err(e) => error!("Failed to parse CSV {}: {}", path_str, e),
+                }
+            });
+        }
+        info!("CSV Parser finished");
+        
+        // Signal completion
+        let _ = parser_complete_tx.send(());
+    });
+    
+    // Stage 1: File scanner
+    let db_result = scanner_runtime.block_on(async {
+        match (&args.file, &args.index_file) {
+            _ if stdin_input.is_some() => info!("Importing CSV from standard input"),
+            (Some(path), _) => info!("Importing {}", path.display()),
+            (None, Some(path)) => info!("Importing the files listed in {}", path.display()),
+            (None, None) => info!("Starting scan for {} files in {}", input_format.label(), args.scan_dir.display()),
+        }
+        let force = args.force;
+        let only = &args.only;
+        let paranoid = args.paranoid;
+        let min_age = args.file_age_min_hours.map(hours);
+        let max_age = args.file_age_max_hours.map(hours);
+        
+        // Resume interrupted files before anything else
+        let mut requeued = std::collections::HashSet::new();
+        if args.requeue_in_progress {
+            for path in in_progress.into_iter().filter(|p| p.exists()) {
+                info!("Re-queueing interrupted file: {}", path.display());
+                scanner_stats.lock().unwrap().files_found += 1;
+                requeued.insert(canonical_path(&path));
+                if !queue_file(&file_tx, QueuedFile { path, hashed: None }, &scanner_stats).await {
+                    break;
+                }
+            }
+        }
+        
+        // Standard input bypasses the parser stage and goes straight to the DB writer
+        if let (Some(input), Some(record_tx)) = (stdin_input, stdin_record_tx) {
+            let destination = cache_destination.as_ref().map(|d| d.key(None));
+            import_stdin(input, &scanner_parse_options, &scanner_cache, destination.as_deref(), force, &scanner_stats, record_tx).await;
+        }
+        
+        // A single file or an index file replaces the directory walk
+        let candidates: Box<dyn Iterator<Item = PathBuf>> = match index {
+            _ if read_stdin => Box::new(std::iter::empty()),
+            Some(paths) => Box::new(paths.into_iter()),
+            None => Box::new(WalkDir::new(&args.scan_dir).into_iter()
+                .filter_map(Result::ok)
+                .map(|entry| entry.path().to_owned())
+                .filter(|path| has_extension(path, &extensions))),
+        };
+        
+        let scan_started = tokio::time::Instant::now();
+        // Keys of the cache entries changed while scanning, saved once the scan completes
+        let mut touched = Vec::new();
+        for path in candidates {
+            let path_str = canonical_path(&path);
+            if requeued.contains(&path_str) {
+                continue;
+            }
+            info!("Found {}: {}", input_format.label(), path.display());
+            
+            {
+                let mut stats = scanner_stats.lock().unwrap();
+                stats.files_found += 1;
+            }
+            
+            // Leave files that are still being written, or too old to be of interest, alone
+            if let Some(age) = file_age(&path).filter(|_| min_age.is_some() || max_age.is_some()) {
+                let limit = match (min_age, max_age) {
+                    (Some(min), _) if age < min => Some("--file-age-min-hours"),
+                    (_, Some(max)) if age > max => Some("--file-age-max-hours"),
+                    _ => None,
+                };
+                if let Some(limit) = limit {
+                    debug!("Skipping {}: modified {:.1} hours ago ({})", path.display(), age.as_secs_f64() / 3600.0, limit);
+                    scanner_stats.lock().unwrap().files_skipped_age += 1;
+                    continue;
+                }
+            }
+            
+            // Skip if already in cache and unchanged, unless force flag is set. A hash computed
+            // here under the current --hash-algo is handed on so the parser doesn't repeat it
+            let mut hashed = None;
+            let destination = cache_destination.as_ref().map(|d| d.for_file(&path));
+            let forced = !force && only.iter().any(|pattern| pattern.matches(&path_str) || pattern.matches_path(&path));
+            if forced {
+                info!("Importing {} regardless of the cache (--only)", path.display());
+            }
+            if !force && !forced {
+                let key = cache_key(&path_str, destination.as_deref());
+                let cached = scanner_cache.lock().unwrap().get(&key).cloned();
+                if let Some(metadata) = cached.filter(|m| !m.in_progress) {
+                    let signature = file_signature(&path).ok();
+                    // An unchanged size and mtime is trusted without hashing unless --paranoid
+                    let unchanged = if !paranoid && signature.is_some() && signature == metadata.signature() {
+                        Some("size and mtime unchanged")
+                    } else {
+                        // Entries hashed with another --hash-algo are checked with their own algorithm
+                        scanner_stats.lock().unwrap().files_hashed += 1;
+                        match metadata.hash_algo.hasher().hash_file(&path, scanner_parse_options.read_buffer_size) {
+                            Ok(hash) if hash == metadata.hash => {
+                                let rehash = Some(hash_algo).filter(|algo| *algo != metadata.hash_algo);
+                                if update_cache && (rehash.is_some() || signature != metadata.signature()) {
+                                    if rehash.is_some() {
+                                        scanner_stats.lock().unwrap().files_hashed += 1;
+                                    }
+                                    refresh_entry(&scanner_cache, &key, &path, signature, rehash);
+                                    touched.push(key.clone());
+                                }
+                                Some("hash unchanged")
+                            }
+                            Ok(hash) => {
+                                if metadata.hash_algo == hash_algo {
+                                    hashed = Some((hash, signature));
+                                }
+                                None
+                            }
+                            Err(_) => None, // Process the file if its hash can't be calculated
+                        }
+                    };
+                    if let Some(reason) = unchanged {
+                        let age = chrono::Utc::now() - metadata.last_processed;
+                        info!("Skipping {}: imported {} ago ({})", path.display(), format_age(age), reason);
+                        {
+                            let mut stats = scanner_stats.lock().unwrap();
+                            stats.files_skipped += 1;
+                            stats.cache.record_skip(age);
+                        }
+                        continue;
+                    }
+                }
+            }
+            
+            if update_cache {
+                let key = cache_key(&path_str, destination.as_deref());
+                mark_in_progress(&mut scanner_cache.lock().unwrap(), &key, &path_str);
+                touched.push(key);
+            }
+            if !queue_file(&file_tx, QueuedFile { path, hashed }, &scanner_stats).await {
+                break;
+            }
+        }
+        
+        info!("Scan completed");
+        {
+            let mut stats = scanner_stats.lock().unwrap();
+            let files_found = stats.files_found;
+            if let Some(benchmark) = &mut stats.benchmark {
+                benchmark.scanner.record(scan_started.elapsed(), files_found, 0);
+            }
+        }
+        // Persist the in-progress markers of files still queued, and the refreshed entries
+        if update_cache {
+            if let Err(e) = cache_store.save_entries(&scanner_cache, &touched) {
+                error!("Failed to save cache: {}", e);
+            }
+        }
+        // Close the channel when done scanning
+        drop(file_tx);
+        
+        // Wait for parser to finish
+        if let Err(e) = parser_complete_rx.await {
+            error!("Error waiting for parser to complete: {}", e);
+        }
+        
+        // Wait for DB writer to finish
+        let db_result = db_complete_rx.await
+            .unwrap_or_else(|e| Err(format!("stopped without signalling completion: {}", e)));
+        
+        info!("All tasks completed");
+        db_result
+    });
+    if let Some(progress) = progress {
+        progress.abort();
+    }
+    if let Some(url) = &args.notify_webhook {
+        let event = completion_event(&stats.lock().unwrap(), db_result.is_ok(), dry_run);
+        db_runtime.block_on(notify_webhook(url, event));
+    }
+    db_result.map_err(|e| anyhow!("DB writer failed: {}", e))?;
+    
+    // Signal suspicious runs to automated pipelines through the exit code
+    let stats = stats.lock().unwrap();
+    if args.alert_on_zero_records && stats.records_processed == 0 {
+        error!("No records were processed; check --scan-dir and the input files");
+        return Ok(2);
+    }
+    if let Some(max_pct) = args.alert_on_failure_rate {
+        let attempted = stats.successful_inserts + stats.failed_inserts;
+        if attempted > 0 {
+            let failure_rate = stats.failed_inserts as f64 / attempted as f64;
+            if failure_rate > max_pct / 100.0 {
+                error!("Failure rate {:.2}% exceeds the allowed {:.2}%", failure_rate * 100.0, max_pct);
+                return Ok(3);
+            }
+        }
+    }
+    if stats.verify_failures > 0 {
+        error!("Verification failed for {} file(s)", stats.verify_failures);
+        return Ok(4);
+    }
+    
+    Ok(0)
+}
+
+// Settings and shared state of the DB stage; each parsed file is written by its own task
+struct DbWriter {
+    sink: BatchSink,
+    // Bounds the number of batch writes in flight across all files
+    write_slots: Arc<tokio::sync::Semaphore>,
+    measurement: String,
+    normalize_keys: Option<KeyCase>,
+    normalize_measurement: bool,
+    column_groups: Vec<ColumnGroup>,
+    measurement_template: Option<MeasurementTemplate>,
+    // Key columns of --dedupe-key; empty to compare whole records
+    dedupe_key: Option<Vec<String>>,
+    // Earliest timestamp imported (--since), and per measurement with --incremental. The
+    // measurements of --measurement-template are only known once written to, so they are
+    // looked up then (incremental)
+    since: Option<i64>,
+    incremental: bool,
+    incremental_since: Mutex<HashMap<String, Option<i64>>>,
+    // Skip the rows of changed files up to the latest one imported from them (--incremental-files)
+    file_increments: bool,
+    query_options: QueryOptions,
+    batch_size: usize,
+    dry_run: bool,
+    // Serialize and time a mocked write of every batch in dry runs (--benchmark)
+    benchmark: bool,
+    // Write the batches of a file sequentially (--preserve-order)
+    preserve_order: bool,
+    compress_writes: bool,
+    mirrored: bool,
+    write_quorum: usize,
+    max_records_per_measurement: Option<usize>,
+    // Records written (or reserved by files being written) per measurement
+    measurement_counts: Mutex<HashMap<String, usize>>,
+    column_stats_enabled: bool,
+    column_stats_to_influx: bool,
+    stats_measurement: Option<String>,
+    dead_letter: Option<Arc<DeadLetterWriter>>,
+    tag_source_file: bool,
+    verifiers: Vec<Verifier>,
+    update_cache: bool,
+    atomic_file: bool,
+    cache_failure_threshold: f64,
+    post_import_move: Option<PathBuf>,
+    scan_dir: PathBuf,
+    db_stats: Arc<Mutex<ImportStats>>,
+    db_cache: Arc<Mutex<HashMap<String, FileMetadata>>>,
+    db_cache_store: Arc<dyn CacheStore>,
+    // Measurement and destination in cache keys (--cache-key destination)
+    cache_destination: Option<CacheDestination>,
+}
+
+impl DbWriter {
+    // Serialize every batch as a write would, then time a mocked write that prepares the
+    // request body (gzipped with --compress-writes) without sending it
+    fn benchmark_writes(&self, points: &[Point]) {
+        for batch in points.chunks(self.batch_size) {
+            let started = tokio::time::Instant::now();
+            let body = batch.iter().map(Point::to_line_protocol).collect::<Vec<_>>().join("\n");
+            let serialized = tokio::time::Instant::now();
+            let body_bytes = body.len();
+            let sent = if self.compress_writes {
+                gzip(body.as_bytes()).map_or(0, |body| body.len())
+            } else {
+                std::hint::black_box(body.into_bytes()).len()
+            };
+            
+            let written = serialized.elapsed();
+            
+            let mut stats = self.db_stats.lock().unwrap();
+            if let Some(benchmark) = &mut stats.benchmark {
+                benchmark.serialization.record(serialized - started, batch.len(), body_bytes);
+                benchmark.write.record(written, batch.len(), sent);
+            }
+        }
+    }
+    
+    // Latest timestamp imported from a file by an earlier run, from its cache entry
+    fn imported_until(&self, key: &str) -> Option<i64> {
+        self.db_cache.lock().unwrap().get(key)
+            .and_then(|metadata| metadata.max_imported_timestamp)
+            .and_then(|timestamp| timestamp.timestamp_nanos_opt())
+    }
+    
+    // Earliest timestamp imported into each of these measurements, from --since and
+    // --incremental. Measurements not seen before are looked up first; if that fails
+    // their records are all imported and the lookup is tried again for the next file
+    async fn cutoffs(&self, measurements: BTreeSet<&str>) -> HashMap<String, Option<i64>> {
+        if self.incremental {
+            let unknown: Vec<String> = {
+                let known = self.incremental_since.lock().unwrap();
+                measurements.iter().filter(|m| !known.contains_key(*m)).map(|m| m.to_string()).collect()
+            };
+            if !unknown.is_empty() {
+                let sinks: Vec<&dyn Sink> = self.sink.sinks.iter().map(|sink| sink.as_ref()).collect();
+                match latest_timestamps(&sinks, unknown).await {
+                    Ok(latest) => self.incremental_since.lock().unwrap().extend(latest),
+                    Err(e) => error!("Failed to look up the latest points, importing every record: {:#}", e),
+                }
+            }
+        }
+        let known = self.incremental_since.lock().unwrap();
+        measurements.into_iter()
+            .map(|m| (m.to_string(), self.since.max(known.get(m).copied().flatten())))
+            .collect()
+    }
+    
+    // Write one parsed file and record the outcome in the statistics and the cache. The
+    // cache entry is only added once every batch of the file has completed
+    async fn write_file(&self, parsed: ParsedFile) {
+        let ParsedFile { records, path: file_path, hash: file_hash, hash_algo, signature, metadata, column_types, started } = parsed;
+        info!("Received batch of {} records from {}", records.len(), file_path.display());
+        
+        let (records, deduplicated) = match &self.dedupe_key {
+            Some(key_columns) => dedupe_records(records, key_columns),
+            None => (records, 0),
+        };
+        if deduplicated > 0 {
+            info!("Collapsed {} duplicate records in {}", deduplicated, file_path.display());
+        }
+        
+        // Columns classified by position, unless --types declares their name
+        let file_options;
+        let query_options = if column_types.is_empty() {
+            &self.query_options
+        } else {
+            let mut options = self.query_options.clone();
+            for (column, column_type) in column_types {
+                options.types.entry(column).or_insert(column_type);
+            }
+            file_options = options;
+            &file_options
+        };
+        
+        // Sidecar metadata overrides the measurement and adds tags for this file
+        let mut file_measurement = metadata.measurement.clone().unwrap_or_else(|| self.measurement.clone());
+        if let Some(case) = self.normalize_keys.filter(|_| self.normalize_measurement) {
+            file_measurement = normalize_measurement_name(case, &file_measurement);
+        }
+        
+        let mut successful = 0;
+        let mut failed = 0;
+        let mut payload_bytes = 0;
+        let mut wire_bytes = 0;
+        let mut dead_letters = Vec::new();
+        let mut before_since = 0;
+        let mut non_finite = 0;
+        
+        // A changed file that was imported before only adds the rows after those written then
+        let path_str = canonical_path(&file_path);
+        let destination = self.cache_destination.as_ref().map(|d| d.key(metadata.measurement.as_deref()));
+        let key = cache_key(&path_str, destination.as_deref());
+        let imported_until = self.imported_until(&key);
+        let skip_until = imported_until.filter(|_| self.file_increments);
+        let mut already_imported = 0;
+        
+        // Convert every record to a point, keeping the source rows alongside
+        // the points when failures go to a dead-letter file
+        let mut points = Vec::with_capacity(records.len());
+        let mut sources = Vec::new();
+        let column_stats = self.column_stats_enabled.then(|| ColumnStats::compute(&records));
+        let mut unrendered: BTreeMap<&str, usize> = BTreeMap::new();
+        let split = records.into_iter().flat_map(|mut record| {
+            let default = match &self.measurement_template {
+                Some(template) => {
+                    let (measurement, missing) = template.render(&mut record.fields);
+                    for column in missing {
+                        *unrendered.entry(column).or_insert(0) += 1;
+                    }
+                    match self.normalize_keys.filter(|_| self.normalize_measurement) {
+                        Some(case) => normalize_measurement_name(case, &measurement),
+                        None => measurement,
+                    }
+                }
+                None => file_measurement.clone(),
+            };
+            split_record(record, &default, &self.column_groups).into_iter()
+                .map(move |(measurement, record)| (measurement == default, measurement, record))
+        }).collect::<Vec<_>>();
+        for (column, count) in unrendered {
+            warn!("{} records in {} have no value for {{{}}} of --measurement-template, keeping the placeholder",
+                     count, file_path.display(), column);
+        }
+        let cutoffs = self.cutoffs(split.iter().map(|(_, measurement, _)| measurement.as_str()).collect()).await;
+        for (is_default, measurement, record) in split {
+            let source = self.dead_letter.as_ref().map(|_| record.clone());
+            let mut point = record.into_point(measurement, query_options);
+            non_finite += point.remove_non_finite_fields();
+            // Only tag columns may be left over for the default measurement once the groups
+            // have taken their columns
+            if !self.column_groups.is_empty() && point.fields.is_empty() && is_default {
+                continue;
+            }
+            if let (Some(Some(cutoff)), Some(timestamp)) = (cutoffs.get(&point.measurement), point.timestamp) {
+                if timestamp < *cutoff {
+                    before_since += 1;
+                    continue;
+                }
+            }
+            if let (Some(until), Some(timestamp)) = (skip_until, point.timestamp) {
+                if timestamp <= until {
+                    already_imported += 1;
+                    continue;
+                }
+            }
+            for (key, value) in &metadata.tags {
+                let key = self.normalize_keys.map_or_else(|| key.clone(), |case| case.apply(key));
+                point.tags.insert(key, value.clone());
+            }
+            if self.tag_source_file {
+                point.tags.insert("source_file".to_string(), file_path.to_string_lossy().to_string());
+            }
+            debug!("Point: {:?}", &point);
+            // A rendered measurement can hold anything its columns do
+            let rendered = match &self.measurement_template {
+                Some(_) if is_default => parse_measurement(&point.measurement).map_err(|error| influxdb::Error::InvalidQueryError { error }),
+                _ => Ok(()),
+            };
+            match rendered.and_then(|_| point.validate()) {
+                Ok(()) => {
+                    points.push(point);
+                    sources.extend(source);
+                }
+                Err(cause) => {
+                    let e = CursedStatsError::InfluxWrite { measurement: point.measurement.clone(), cause };
+                    error!("Failed to build record: {}", e);
+                    failed += 1;
+                    if let Some(record) = source {
+                        dead_letters.push(DeadLetterEntry::new(&file_path, &point.measurement, record, None, e.to_string()));
+                    }
+                }
+            }
+        }
+        
+        // Enforce the per-measurement safety cap. The points are reserved up front so files
+        // written concurrently can't exceed the cap together
+        let mut skipped_cap = 0;
+        if let Some(cap) = self.max_records_per_measurement {
+            let mut counts = self.measurement_counts.lock().unwrap();
+            let mut skipped: BTreeMap<&str, usize> = BTreeMap::new();
+            let keep: Vec<bool> = points.iter().map(|point| {
+                let inserted = counts.entry(point.measurement.clone()).or_insert(0);
+                if *inserted < cap {
+                    *inserted += 1;
+                    true
+                } else {
+                    *skipped.entry(&point.measurement).or_insert(0) += 1;
+                    false
+                }
+            }).collect();
+            for (measurement, count) in &skipped {
+                warn!("Measurement {} reached the cap of {} records, skipping {} records from {}",
+                         measurement, cap, count, file_path.display());
+                skipped_cap += count;
+            }
+            if skipped_cap > 0 {
+                let mut kept = keep.iter();
+                points.retain(|_| *kept.next().unwrap_or(&true));
+                let mut kept = keep.iter();
+                sources.retain(|_| *kept.next().unwrap_or(&true));
+            }
+        }
+        if non_finite > 0 {
+            warn!("Left {} NaN or infinite float fields of {} out of their points", non_finite, file_path.display());
+        }
+        if already_imported > 0 {
+            info!("Skipped {} records of {} imported by an earlier run (--incremental-files)", already_imported, file_path.display());
+        }
+        // Recorded in the cache entry once the file is written
+        let latest_point = points.iter().filter_map(|point| point.timestamp).max();
+        
+        // Write the batches concurrently, to every target when mirroring; the write slots
+        // bound the number of requests in flight across all files. With --preserve-order
+        // each batch waits for the previous one of the file to complete
+        let mut previous: Option<oneshot::Receiver<()>> = None;
+        let writes: Vec<JoinHandle<(Vec<TargetWrite>, std::time::Duration)>> = if self.dry_run {
+            if self.benchmark {
+                self.benchmark_writes(&points);
+            }
+            successful += points.len();
+            Vec::new()
+        } else {
+            points.chunks(self.batch_size).map(|batch| {
+                let batch = batch.to_vec();
+                let sink = self.sink.clone();
+                let write_slots = Arc::clone(&self.write_slots);
+                let source = file_path.clone();
+                let (done_tx, done_rx) = oneshot::channel();
+                let after = if self.preserve_order { previous.replace(done_rx) } else { None };
+                tokio::spawn(async move {
+                    if let Some(after) = after {
+                        let _ = after.await;
+                    }
+                    let _permit = write_slots.acquire_owned().await;
+                    let write_started = tokio::time::Instant::now();
+                    let outcomes = sink.write(Some(&source), batch).await;
+                    let _ = done_tx.send(());
+                    (outcomes, write_started.elapsed())
+                })
+            }).collect()
+        };
+        
+        // Collect the outcomes in batch order
+        let mut target_results: BTreeMap<String, TargetStats> = BTreeMap::new();
+        let mut secondary_targets = BTreeSet::new();
+        let mut batch_latencies = Vec::new();
+        // Points per measurement that no quorum stored, to release from the cap
+        let mut unwritten: HashMap<&str, usize> = HashMap::new();
+        for (batch_index, (batch, write)) in points.chunks(self.batch_size).zip(writes).enumerate() {
+            let outcomes = match write.await {
+                Ok((outcomes, latency)) => {
+                    batch_latencies.push(latency);
+                    outcomes
+                }
+                Err(e) => {
+                    error!("Write task for a batch of {} records failed: {}", batch.len(), e);
+                    failed += batch.len();
+                    for point in batch {
+                        *unwritten.entry(&point.measurement).or_insert(0) += 1;
+                    }
+                    continue;
+                }
+            };
+            // Number of targets that stored each point of the batch
+            let mut accepted = vec![0; batch.len()];
+            for outcome in outcomes {
+                let target_stats = target_results.entry(outcome.target.clone()).or_default();
+                if !outcome.required {
+                    secondary_targets.insert(outcome.target.clone());
+                }
+                let target = Some(outcome.target).filter(|_| self.mirrored);
+                match outcome.result {
+                    Ok(written) => {
+                        // A partial write only rejects some points; the rest were stored
+                        target_stats.successful += batch.len() - written.rejected.len();
+                        target_stats.failed += written.rejected.len();
+                        payload_bytes += written.payload_bytes;
+                        wire_bytes += written.wire_bytes;
+                        if outcome.required {
+                            for count in &mut accepted {
+                                *count += 1;
+                            }
+                        }
+                        for rejected in &written.rejected {
+                            error!("Record rejected by {}: {}", target.as_deref().unwrap_or("InfluxDB"), rejected.reason);
+                            // Secondary targets are best effort: their rejections are only logged
+                            if !outcome.required {
+                                continue;
+                            }
+                            accepted[rejected.index] -= 1;
+                            if self.dead_letter.is_some() {
+                                let index = batch_index * self.batch_size + rejected.index;
+                                dead_letters.push(DeadLetterEntry::new(
+                                    &file_path, &batch[rejected.index].measurement, sources[index].clone(),
+                                    Some(batch[rejected.index].to_line_protocol()), rejected.reason.clone(),
+                                ).with_target(target.clone()));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to write batch of {} records: {}", batch.len(), e);
+                        target_stats.failed += batch.len();
+                        if self.dead_letter.is_some() && outcome.required {
+                            let start = batch_index * self.batch_size;
+                            for (record, point) in sources[start..start + batch.len()].iter().zip(batch) {
+                                dead_letters.push(DeadLetterEntry::new(
+                                    &file_path, &point.measurement, record.clone(), Some(point.to_line_protocol()), e.to_string(),
+                                ).with_target(target.clone()));
+                            }
+                        }
+                    }
+                }
+            }
+            
+            // A record counts as written once enough targets have stored it
+            let written = accepted.iter().filter(|&&count| count >= self.write_quorum).count();
+            successful += written;
+            failed += batch.len() - written;
+            for (point, _) in batch.iter().zip(&accepted).filter(|(_, &count)| count < self.write_quorum) {
+                *unwritten.entry(&point.measurement).or_insert(0) += 1;
+            }
+        }
+        {
+            let mut stats = self.db_stats.lock().unwrap();
+            stats.timings.batch_latencies.extend(batch_latencies);
+            stats.timings.file_times.push((file_path.to_string_lossy().to_string(), started.elapsed()));
+        }
+        
+        // Data quality statistics for the file's columns
+        if let Some(column_stats) = &column_stats {
+            for (column, stats) in column_stats {
+                info!("Column {} in {}: {}", column, file_path.display(), stats);
+            }
+            if self.column_stats_to_influx && !self.dry_run {
+                let stats_measurement = self.stats_measurement.clone()
+                    .unwrap_or_else(|| format!("{}_meta", file_measurement));
+                let stats_points = ColumnStats::points(column_stats, &stats_measurement, &file_path);
+                for outcome in self.sink.write(Some(&file_path), stats_points).await {
+                    match outcome.result {
+                        Ok(written) if written.rejected.is_empty() => {}
+                        Ok(written) => warn!("{} column statistics rejected by {}", written.rejected.len(), outcome.target),
+                        Err(e) => error!("Failed to write column statistics: {}", e),
+                    }
+                }
+            }
+        }
+        
+        // A line protocol file that can't be completed leaves the whole input file unwritten
+        if let Err(e) = self.sink.end_file(&file_path) {
+            error!("Failed to finish line protocol for {}: {}", file_path.display(), e);
+            failed += successful;
+            successful = 0;
+        }
+        
+        // Query the records back and compare with what each target accepted
+        let mut verify_failed = false;
+        if let Some(range) = timestamp_range(&points).filter(|_| !self.verifiers.is_empty()) {
+            let source_file = self.tag_source_file.then(|| file_path.to_string_lossy().to_string());
+            for verifier in &self.verifiers {
+                let expected = target_results.get(&verifier.target).map_or(0, |t| t.successful);
+                let counted = verifier.client
+                    .count_points(&file_measurement, verifier.retention_policy.as_deref(), range, source_file.as_deref())
+                    .await;
+                let failed = match counted {
+                    Ok(found) if found == expected => {
+                        info!("Verified {} records from {} on {}", found, file_path.display(), verifier.target);
+                        false
+                    }
+                    Ok(found) if found > expected && source_file.is_none() => {
+                        // Without a source-file tag other data in the same time range is counted too
+                        warn!("Found {} records in the time range of {} on {}, expected {}; the range may hold other data (use --tag-source-file for exact checks)",
+                                 found, file_path.display(), verifier.target, expected);
+                        false
+                    }
+                    Ok(found) => {
+                        error!("Verification failed for {} on {}: wrote {} records, found {}",
+                                  file_path.display(), verifier.target, expected, found);
+                        true
+                    }
+                    Err(e) => {
+                        error!("Could not verify {} on {}: {:#}", file_path.display(), verifier.target, e);
+                        true
+                    }
+                };
+                if failed && !verifier.required {
+                    warn!("Ignoring the failed verification of {} on secondary target {}", file_path.display(), verifier.target);
+                }
+                let failed = failed && verifier.required;
+                {
+                    let mut stats = self.db_stats.lock().unwrap();
+                    stats.files_verified += 1;
+                    stats.verify_failures += usize::from(failed);
+                }
+                verify_failed |= failed;
+            }
+        }
+        
+        // Keep permanently failed records for inspection or a later retry
+        let mut dead_lettered = 0;
+        if let Some(writer) = self.dead_letter.as_ref().filter(|_| !dead_letters.is_empty()) {
+            match writer.write(&dead_letters) {
+                Ok(()) => dead_lettered = dead_letters.len(),
+                Err(e) => error!("Failed to write dead letters: {}", e),
+            }
+        }
+        
+        // Release the reserved points that weren't written; none were when the line protocol
+        // output of the file couldn't be completed
+        if successful == 0 {
+            unwritten.clear();
+            for point in &points {
+                *unwritten.entry(&point.measurement).or_insert(0) += 1;
+            }
+        }
+        {
+            let mut counts = self.measurement_counts.lock().unwrap();
+            for (measurement, released) in unwritten {
+                if let Some(count) = counts.get_mut(measurement) {
+                    *count = count.saturating_sub(released);
+                }
+            }
+        }
+        
+        // Update statistics
+        {
+            let mut stats = self.db_stats.lock().unwrap();
+            stats.records_skipped_cap += skipped_cap;
+            stats.records_deduplicated += deduplicated;
+            stats.fields_non_finite += non_finite;
+            stats.records_before_since += before_since;
+            stats.records_already_imported += already_imported;
+            stats.dead_lettered += dead_lettered;
+            stats.successful_inserts += successful;
+            stats.failed_inserts += failed;
+            stats.payload_bytes += payload_bytes;
+            stats.wire_bytes += wire_bytes;
+            if self.mirrored {
+                for (target, result) in &target_results {
+                    let target_stats = stats.targets.entry(target.clone()).or_default();
+                    target_stats.successful += result.successful;
+                    target_stats.failed += result.failed;
+                }
+            }
+        }
+        
+        // When mirroring, the file must have been fully written to a quorum of targets
+        let complete_targets = target_results.iter()
+            .filter(|(target, t)| t.failed == 0 && !secondary_targets.contains(*target))
+            .count();
+        
+        let completed = if !self.update_cache {
+            false
+        } else if successful == 0 && failed > 0 {
+            // Leave the file out of the cache so it is retried on the next run
+            warn!("Not caching {}: every record failed to write", file_path.display());
+            false
+        } else if self.mirrored && complete_targets < self.write_quorum {
+            warn!("Not caching {}: only {} of {} targets stored every record (quorum {})",
+                     file_path.display(), complete_targets, target_results.len() - secondary_targets.len(), self.write_quorum);
+            self.db_stats.lock().unwrap().files_failed += 1;
+            false
+        } else if self.atomic_file && failed > 0 {
+            // In atomic mode a partially written file counts as failed and is reprocessed wholesale
+            warn!("Marking {} as failed: {} of {} records failed to write (--atomic-file)",
+                     file_path.display(), failed, successful + failed);
+            self.db_stats.lock().unwrap().files_failed += 1;
+            false
+        } else if failed > 0 && failed as f64 * 100.0 / (successful + failed) as f64 >= self.cache_failure_threshold {
+            // Retry partially imported files on the next run; rewriting the same timestamps is idempotent
+            warn!("Not caching {}: {} of {} records failed to write (--cache-failure-threshold {}%)",
+                     file_path.display(), failed, successful + failed, self.cache_failure_threshold);
+            false
+        } else {
+            true
+        };
+        
+        if self.update_cache {
+            let mut cache = self.db_cache.lock().unwrap();
+            if completed {
+                // Add to cache
+                cache.insert(key, FileMetadata {
+                    path: path_str.clone(),
+                    hash: file_hash,
+                    last_processed: self.query_options.clock.now(),
+                    records_count: successful + failed,
+                    successful_count: successful,
+                    failed_count: failed,
+                    in_progress: false,
+                    hash_algo,
+                    size: signature.map(|(size, _)| size),
+                    mtime: signature.map(|(_, mtime)| mtime),
+                    max_imported_timestamp: latest_point.max(imported_until).map(chrono::DateTime::from_timestamp_nanos),
+                });
+            } else {
+                // The file was handled, just not successfully
+                clear_in_progress(&mut cache, &key);
+            }
+            drop(cache);
+            
+            // Save cache after each file to prevent data loss
+            if let Err(e) = self.db_cache_store.save_entries(&self.db_cache, std::slice::from_ref(&key)) {
+                error!("Failed to save cache: {}", e);
+            }
+        }
+        
+        // Archive files that were written without any error
+        let write_ok = failed == 0 && !verify_failed && (!self.mirrored || complete_targets >= self.write_quorum);
+        if let Some(dest) = self.post_import_move.as_ref().filter(|_| write_ok && !self.dry_run) {
+            match archive_file(&file_path, &self.scan_dir, dest) {
+                Ok(archived) => {
+                    info!("Moved {} to {}", file_path.display(), archived.display());
+                    self.db_stats.lock().unwrap().files_archived += 1;
+                }
+                Err(e) => error!("Failed to move {}: {:#}", file_path.display(), e),
+            }
+        }
+        
+        info!("File processed: {} records, {} successful, {} failed", 
+                 successful + failed, successful, failed);
+    }
+}
+
+// Re-raise a panic from a file task so the DB writer fails as a whole
+fn propagate_panic(result: Option<std::result::Result<(), tokio::task::JoinError>>) {
+    if let Some(Err(e)) = result {
+        if e.is_panic() {
+            std::panic::resume_unwind(e.into_panic());
+        }
+        error!("File write task failed: {}", e);
+    }
+}
+
+// Data quality statistics for one column of a file
+#[derive(Debug, Default)]
+struct ColumnStats {
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: f64,
+    numeric_count: usize,
+    null_count: usize,
+    distinct: std::collections::HashSet<String>,
+}
+
+impl ColumnStats {
+    // Statistics for every column of a file, sorted by column name
+    fn compute(records: &[DynamicRecord]) -> BTreeMap<String, ColumnStats> {
+        let mut columns: BTreeMap<String, ColumnStats> = BTreeMap::new();
+        for record in records {
+            for (key, value) in record.fields.iter() {
+                match columns.get_mut(key) {
+                    Some(stats) => stats.observe(value),
+                    None => columns.entry(key.to_string()).or_default().observe(value),
+                }
+            }
+        }
+        columns
+    }
+    
+    fn observe(&mut self, value: &str) {
+        let value = value.trim();
+        if value.is_empty() {
+            self.null_count += 1;
+            return;
+        }
+        // NaN and infinite values are left out of points, so they don't count as numbers here
+        if let Some(v) = value.parse::<f64>().ok().filter(|v| v.is_finite()) {
+            self.min = Some(self.min.map_or(v, |min| min.min(v)));
+            self.max = Some(self.max.map_or(v, |max| max.max(v)));
+            self.sum += v;
+            self.numeric_count += 1;
+        }
+        if !self.distinct.contains(value) {
+            self.distinct.insert(value.to_string());
+        }
+    }
+    
+    fn mean(&self) -> Option<f64> {
+        (self.numeric_count > 0).then(|| self.sum / self.numeric_count as f64)
+    }
+    
+    // One point per column, tagged with the column and source file
+    fn points(columns: &BTreeMap<String, ColumnStats>, measurement: &str, source_file: &Path) -> Vec<Point> {
+        let now = chrono::Utc::now().timestamp_nanos_opt().unwrap_or(0);
+        columns
+            .iter()
+            .map(|(column, stats)| {
+                let mut point = Point::new(measurement, Some(now));
+                point.tags.insert("column_name".to_string(), column.clone());
+                point.tags.insert("source_file".to_string(), source_file.to_string_lossy().to_string());
+                point.fields.insert("null_count".to_string(), FieldValue::Integer(stats.null_count as i64));
+                point.fields.insert("distinct_count".to_string(), FieldValue::Integer(stats.distinct.len() as i64));
+                if let (Some(min), Some(max), Some(mean)) = (stats.min, stats.max, stats.mean()) {
+                    point.fields.insert("min".to_string(), FieldValue::Float(min));
+                    point.fields.insert("max".to_string(), FieldValue::Float(max));
+                    point.fields.insert("mean".to_string(), FieldValue::Float(mean));
+                }
+                point
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for ColumnStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let (Some(min), Some(max), Some(mean)) = (self.min, self.max, self.mean()) {
+            write!(f, "min {}, max {}, mean {:.3}, ", min, max, mean)?;
+        }
+        write!(f, "{} empty, {} distinct", self.null_count, self.distinct.len())
+    }
+}
+
+// Tracks which types every non-empty value of a column satisfies
+struct ColumnProfile {
+    seen: bool,
+    // Whether a --nan-tokens placeholder was seen; they only stand in for numeric readings
+    missing_readings: bool,
+    all_bool: bool,
+    all_int: bool,
+    all_float: bool,
+}
+
+impl ColumnProfile {
+    fn observe(&mut self, value: &str, nan_tokens: &[String]) {
+        let value = value.trim();
+        if nan_tokens.iter().any(|token| token == value) {
+            self.missing_readings = true;
+            return;
+        }
+        if value.is_empty() {
+            return;
+        }
+        self.seen = true;
+        self.all_bool &= value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false");
+        self.all_int &= value.parse::<i64>().is_ok();
+        self.all_float &= value.parse::<f64>().is_ok();
+    }
+    
+    fn best_type(&self) -> ColumnType {
+        if !self.seen {
+            if self.missing_readings { ColumnType::Float } else { ColumnType::Tag }
+        } else if self.all_bool {
+            ColumnType::Bool
+        } else if self.all_int {
+            ColumnType::Int
+        } else if self.all_float {
+            ColumnType::Float
+        } else {
+            ColumnType::Tag
+        }
+    }
+}
+
+// Infer a type for every column in a sample of files and write an editable mapping file
+fn infer_schema(scan_dir: &Path, format: InputFormat, extensions: &[String], sample: usize, options: &ParseOptions, nan_tokens: &[String], output: &Path) -> Result<()> {
+    info!("Inferring schema from up to {} files in {}", sample, scan_dir.display());
+    
+    let mut profiles: HashMap<String, ColumnProfile> = HashMap::new();
+    let mut sampled = 0;
+    
+    for entry in WalkDir::new(scan_dir).into_iter().filter_map(Result::ok) {
+        if sampled >= sample {
+            break;
+        }
+        let path = entry.path().to_owned();
+        if !has_extension(&path, extensions) {
+            continue;
+        }
+        
+        match format.parse(&path, options) {
+            Ok(output) => {
+                sampled += 1;
+                for record in &output.records {
+                    for (key, value) in record.fields.iter() {
+                        profiles
+                            .entry(key.to_string())
+                            .or_insert(ColumnProfile { seen: false, missing_readings: false, all_bool: true, all_int: true, all_float: true })
+                            .observe(value, nan_tokens);
+                    }
+                }
+            }
+            Err(e) => error!("Failed to parse {} {}: {}", format.label(), path.display(), e),
+        }
+    }
+    
+    let mut columns: Vec<_> = profiles.into_iter().collect();
+    columns.sort_by(|a, b| a.0.cmp(&b.0));
+    
+    let mut contents = format!(
+        "# Column types inferred by --infer-schema from {} file(s)\n\
+         # Edit as needed and pass back with --types-file\n",
+        sampled
+    );
+    for (column, profile) in &columns {
+        contents.push_str(&format!("{}={}\n", column, profile.best_type().as_str()));
+    }
+    
+    std::fs::write(output, contents)
+        .with_context(|| format!("Failed to write schema file {}", output.display()))?;
+    info!("Wrote {} column types to {}", columns.len(), output.display());
+    
+    Ok(())
+}
+
+// Set up logging to both file and console
+pub fn setup_logging(cli: &Cli) -> Result<()> {
+    match &cli.command {
+        Some(Command::Import(args)) | Some(Command::Retry(args)) => init_logging(args, true),
+        // Cache commands print their results to stdout and leave the import's log file alone
+        Some(Command::Cache { .. }) => init_logging(&cli.args, false),
+        None => init_logging(&cli.args, true),
+    }
+}
+
+fn init_logging(args: &ImportArgs, log_to_file: bool) -> Result<()> {
+    use pretty_env_logger::env_logger::{Target, WriteStyle};
+    
+    // The first --log-level sets the console, a second one the file; -v steps the console up from there
+    let console_level = if args.summary_only {
+        LogLevel::Warn
+    } else {
+        args.log_level.first().copied().unwrap_or(LogLevel::Info).raised(args.verbose)
+    };
+    let file_level = args.log_level.get(1).copied().unwrap_or(LogLevel::Debug);
+    
+    // The console logs to stderr so stdout stays clean for --stdout and --summary-only
+    let console_logger = || {
+        pretty_env_logger::formatted_builder()
+            .filter_level(console_level.filter())
+            .write_style(WriteStyle::Auto)
+            .target(Target::Stderr)
+            .build()
+    };
+    let file_logger = |path: &Path| -> Result<_> {
+        let log_file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        // Keep colour escape codes out of the file
+        Ok(pretty_env_logger::formatted_builder()
+            .filter_level(file_level.filter())
+            .write_style(WriteStyle::Never)
+            .target(Target::Pipe(Box::new(log_file)))
+            .build())
+    };
+    
+    // Configure console and file logging
+    let log_to_file = log_to_file && !args.log_file.to_string_lossy().is_empty();
+    let console = args.console || args.summary_only;
+    let max_level = if console && log_to_file {
+        // Log to both file and console using custom logic
+        log::set_boxed_logger(Box::new(LogDispatcher {
+            console: console_logger(),
+            file: file_logger(&args.log_file)?,
+        }))?;
+        console_level.filter().max(file_level.filter())
+    } else if log_to_file {
+        // Only log to file
+        log::set_boxed_logger(Box::new(file_logger(&args.log_file)?))?;
+        file_level.filter()
+    } else {
+        // Only log to console
+        log::set_boxed_logger(Box::new(console_logger()))?;
+        console_level.filter()
+    };
+    log::set_max_level(max_level);
+    
+    Ok(())
+}
+
+// Resolve the InfluxDB password from the password file or the --password/env value
+fn resolve_password(args: &ImportArgs) -> Result<Option<String>> {
+    if let Some(path) = &args.password_file {
+        let password = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read password file {}", path.display()))?;
+        return Ok(Some(password.trim_end_matches(['\r', '\n']).to_string()));
+    }
+    
+    Ok(args.password.clone())
+}
+
+// Build the HTTP client used for all InfluxDB requests, applying the TLS options
+fn build_http_client(args: &ImportArgs) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path)
+            .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+        let cert = reqwest::Certificate::from_pem(&pem)
+            .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+        builder = builder.add_root_certificate(cert);
+    }
+    
+    if let (Some(cert_path), Some(key_path)) = (&args.client_cert, &args.client_key) {
+        // rustls expects the certificate and key in a single PEM bundle
+        let mut pem = std::fs::read(cert_path)
+            .with_context(|| format!("Failed to read client certificate {}", cert_path.display()))?;
+        pem.extend(std::fs::read(key_path)
+            .with_context(|| format!("Failed to read client key {}", key_path.display()))?);
+        let identity = reqwest::Identity::from_pem(&pem)
+            .context("Invalid client certificate or key")?;
+        builder = builder.identity(identity);
+    }
+    
+    if args.insecure_skip_tls_verify {
+        warn!("**************************************************************");
+        warn!("* TLS certificate verification is DISABLED                   *");
+        warn!("* (--insecure-skip-tls-verify). Do not use this in production *");
+        warn!("**************************************************************");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    
+    builder.build().context("Failed to build HTTP client")
+}
+
+// Check that the InfluxDB server answers /ping, reporting TLS and TCP failures distinctly
+async fn ping_server(http: &reqwest::Client, url: &str, health_path: &str) -> Result<()> {
+    let started = std::time::Instant::now();
+    let response = http.get(format!("{}{}", url.trim_end_matches('/'), health_path)).send().await
+        .map_err(|e| anyhow!("Server at {} is not reachable: {}", url, describe_connection_error(&e)))?;
+    let latency = started.elapsed();
+    if !response.status().is_success() {
+        return Err(anyhow!("Server at {} answered {} with HTTP {}", url, health_path, response.status().as_u16()));
+    }
+    let version = response.headers()
+        .get("X-Influxdb-Version")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("unknown");
+    info!("Connected to InfluxDB {} at {} ({} ms)", version, url, latency.as_millis());
+    
+    Ok(())
+}
+
+// Describe a request failure, distinguishing TLS problems from TCP ones
+fn describe_connection_error(e: &reqwest::Error) -> String {
+    let mut tls = false;
+    let mut detail = e.to_string();
+    let mut source = Some(e as &dyn std::error::Error);
+    while let Some(err) = source {
+        let message = err.to_string();
+        let lower = message.to_lowercase();
+        tls |= lower.contains("certificate") || lower.contains("tls") || lower.contains("handshake");
+        detail = message;
+        source = err.source();
+    }
+    
+    if tls {
+        format!("TLS error: {}", detail)
+    } else if e.is_timeout() {
+        format!("request timed out: {}", detail)
+    } else if e.is_connect() {
+        format!("TCP connection failed: {}", detail)
+    } else {
+        detail
+    }
+}
+
+// Check that InfluxDB 1.x accepts our credentials
+async fn check_connection(client: &Client) -> Result<()> {
+    // Ping does not require authentication, so run a trivial query against the database
+    match client.query(ReadQuery::new("SHOW MEASUREMENTS LIMIT 1")).await {
+        Ok(_) => Ok(()),
+        Err(influxdb::Error::AuthenticationError) => {
+            Err(anyhow!("InfluxDB rejected the supplied credentials (check --username/--password)"))
+        }
+        Err(influxdb::Error::AuthorizationError) => {
+            Err(anyhow!("InfluxDB user is not authorized to access database {}", client.database_name()))
+        }
+        Err(influxdb::Error::DatabaseError { error }) if error.contains("database not found") => {
+            Err(anyhow!("Database {} does not exist on the InfluxDB server", client.database_name()))
+        }
+        Err(e) => Err(anyhow!("InfluxDB connectivity check failed: {}", e)),
+    }
+}
+
+// Check that a retention policy exists on the 1.x database
+async fn check_retention_policy(client: &Client, rp: &str) -> Result<()> {
+    let query = format!("SHOW RETENTION POLICIES ON \"{}\"", client.database_name().replace('"', "\\\""));
+    let body = match client.query(ReadQuery::new(query)).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Could not list retention policies to verify {}: {}", rp, e);
+            return Ok(());
+        }
+    };
+    
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap_or_default();
+    let policies: Vec<&str> = response["results"][0]["series"][0]["values"]
+        .as_array()
+        .map(|rows| rows.iter().filter_map(|row| row[0].as_str()).collect())
+        .unwrap_or_default();
+    
+    if policies.contains(&rp) {
+        info!("Writing into retention policy {}", rp);
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Retention policy {} does not exist on database {} (available: {})",
+            rp, client.database_name(), policies.join(", ")
+        ))
+    }
+}
+
+// Describe the retention policy for the startup banner
+fn describe_retention_policy(rp: &Option<String>) -> String {
+    match rp {
+        Some(rp) => format!(" (retention policy {})", rp),
+        None => String::new(),
+    }
+}
+
+// API-specific client used to check the write target before importing
+#[derive(Clone)]
+enum WriteTarget {
+    V1(Client),
+    V2(V2Client),
+}
+
+impl WriteTarget {
+    async fn check_connection(&self) -> Result<()> {
+        match self {
+            WriteTarget::V1(client) => check_connection(client).await,
+            WriteTarget::V2(client) => client.check_connection().await,
+        }
+    }
+    
+    // Create the database or bucket if it doesn't exist yet
+    async fn create_database(&self) -> Result<()> {
+        match self {
+            WriteTarget::V1(client) => create_database(client).await,
+            WriteTarget::V2(client) => client.create_bucket().await,
+        }
+    }
+    
+    // Count the points of a measurement between two nanosecond timestamps (inclusive),
+    // optionally only those tagged with the given source file
+    async fn count_points(
+        &self,
+        measurement: &str,
+        retention_policy: Option<&str>,
+        range: (i64, i64),
+        source_file: Option<&str>,
+    ) -> Result<usize> {
+        match self {
+            WriteTarget::V1(client) => {
+                let identifier = |s: &str| format!("\"{}\"", s.replace('"', "\\\""));
+                let from = match retention_policy {
+                    Some(rp) => format!("{}.{}", identifier(rp), identifier(measurement)),
+                    None => identifier(measurement),
+                };
+                let mut query = format!("SELECT COUNT(*) FROM {} WHERE time >= {} AND time <= {}", from, range.0, range.1);
+                if let Some(source_file) = source_file {
+                    query.push_str(&format!(" AND \"source_file\" = '{}'",
+                                            source_file.replace('\\', "\\\\").replace('\'', "\\'")));
+                }
+                let body = client.query(ReadQuery::new(query)).await?;
+                let response: serde_json::Value = serde_json::from_str(&body)?;
+                if let Some(error) = response["results"][0]["error"].as_str() {
+                    return Err(anyhow!("{}", error));
+                }
+                // One count per field; the largest is the number of points
+                let count = response["results"][0]["series"][0]["values"][0]
+                    .as_array()
+                    .map(|row| row.iter().skip(1).filter_map(|v| v.as_u64()).max().unwrap_or(0))
+                    .unwrap_or(0);
+                Ok(count as usize)
+            }
+            WriteTarget::V2(client) => client.count_points(measurement, range, source_file).await,
+        }
+    }
+    
+    // Nanosecond timestamp of the latest point of a measurement, if it has any
+    async fn last_timestamp(&self, measurement: &str, retention_policy: Option<&str>) -> Result<Option<i64>> {
+        match self {
+            WriteTarget::V1(client) => {
+                let identifier = |s: &str| format!("\"{}\"", s.replace('"', "\\\""));
+                let from = match retention_policy {
+                    Some(rp) => format!("{}.{}", identifier(rp), identifier(measurement)),
+                    None => identifier(measurement),
+                };
+                // last(*) reports the epoch as its time when there are several fields, so
+                // fetch the newest point instead
+                let query = format!("SELECT * FROM {} ORDER BY time DESC LIMIT 1", from);
+                let body = client.query(ReadQuery::new(query)).await?;
+                let response: serde_json::Value = serde_json::from_str(&body)?;
+                if let Some(error) = response["results"][0]["error"].as_str() {
+                    return Err(anyhow!("{}", error));
+                }
+                match response["results"][0]["series"][0]["values"][0][0].as_str() {
+                    Some(time) => Ok(parse_timestamp(time)?.timestamp_nanos_opt()),
+                    None => Ok(None),
+                }
+            }
+            WriteTarget::V2(client) => client.last_timestamp(measurement).await,
+        }
+    }
+}
+
+// Latest timestamp stored in each measurement across the primary targets, for --incremental.
+// A measurement that some target holds no points of yet is imported in full (None)
+async fn latest_timestamps(sinks: &[&dyn Sink], measurements: Vec<String>) -> Result<HashMap<String, Option<i64>>> {
+    let mut latest = HashMap::new();
+    for measurement in measurements {
+        let mut oldest: Option<Option<i64>> = None;
+        for sink in sinks.iter().filter(|sink| !sink.secondary()) {
+            let timestamp = sink.last_timestamp(&measurement).await?;
+            oldest = Some(oldest.map_or(timestamp, |oldest| oldest.min(timestamp)));
+        }
+        match oldest.flatten() {
+            Some(nanos) => {
+                info!("Latest point in measurement {}: {}, importing records from then on (--incremental)",
+                         measurement, chrono::DateTime::from_timestamp_nanos(nanos).to_rfc3339());
+                latest.insert(measurement, Some(nanos));
+            }
+            None => {
+                info!("No points in measurement {} yet, importing all of its records (--incremental)", measurement);
+                latest.insert(measurement, None);
+            }
+        }
+    }
+    Ok(latest)
+}
+
+// Checks written points against a target after each file (--verify)
+struct Verifier {
+    target: String,
+    client: WriteTarget,
+    retention_policy: Option<String>,
+    // Failed checks of secondary targets are only logged
+    required: bool,
+}
+
+// Smallest and largest timestamp of a set of points
+fn timestamp_range(points: &[Point]) -> Option<(i64, i64)> {
+    let timestamps = points.iter().filter_map(|point| point.timestamp);
+    timestamps.fold(None, |range, ts| match range {
+        None => Some((ts, ts)),
+        Some((min, max)) => Some((min.min(ts), max.max(ts))),
+    })
+}
+
+// Create the InfluxDB 1.x database if it doesn't exist yet
+async fn create_database(client: &Client) -> Result<()> {
+    let name = client.database_name();
+    
+    // SHOW DATABASES needs admin rights, so probe the database itself instead
+    match client.query(ReadQuery::new("SHOW MEASUREMENTS LIMIT 1")).await {
+        Ok(_) => {
+            info!("Database {} already exists", name);
+            return Ok(());
+        }
+        Err(influxdb::Error::DatabaseError { error }) if error.contains("database not found") => {}
+        // Credential and connectivity problems are reported by the connectivity check
+        Err(_) => return Ok(()),
+    }
+    
+    let query = format!("CREATE DATABASE \"{}\"", name.replace('"', "\\\""));
+    match client.query(ReadQuery::new(query)).await {
+        Ok(_) => {
+            info!("Created database {}", name);
+            Ok(())
+        }
+        Err(influxdb::Error::AuthorizationError) | Err(influxdb::Error::AuthenticationError) => Err(anyhow!(
+            "Database {} does not exist and the InfluxDB user is not allowed to create it; \
+             create it manually or pass --no-create-db",
+            name
+        )),
+        Err(e) => Err(anyhow!("Failed to create database {}: {}", name, e)),
+    }
+}
+
+// Posts batches of line protocol to a 1.x (/write) or 2.x (/api/v2/write) endpoint
+// How often a writer that failed over checks whether the preferred endpoint is back
+const PREFERRED_RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+// Longest Retry-After honored, so a misbehaving server can't stall the import indefinitely
+const MAX_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(300);
+
+#[derive(Clone)]
+struct LineWriter {
+    // URL (list) of the target as configured, used to identify it in logs and stats
+    target: String,
+    http: reqwest::Client,
+    // Base URLs of the endpoints, in order of preference, and the write path on each
+    endpoints: Vec<String>,
+    path: &'static str,
+    health_path: &'static str,
+    // Endpoint currently written to; shared by clones of the writer
+    active: Arc<AtomicUsize>,
+    failovers: Arc<AtomicUsize>,
+    last_recheck: Arc<Mutex<std::time::Instant>>,
+    // Writes to the target pause until then after a 429 or 503, across all writer tasks
+    throttled_until: Arc<Mutex<Option<tokio::time::Instant>>>,
+    throttled: Arc<AtomicUsize>,
+    params: Vec<(&'static str, String)>,
+    auth_header: Option<String>,
+    compress: bool,
+    retries: u32,
+}
+
+impl LineWriter {
+    fn new(
+        config: &TargetConfig,
+        endpoints: &[String],
+        path: &'static str,
+        params: Vec<(&'static str, String)>,
+        auth_header: Option<String>,
+        args: &ImportArgs,
+        http: reqwest::Client,
+    ) -> Self {
+        LineWriter {
+            target: config.url.clone(),
+            http,
+            endpoints: endpoints.to_vec(),
+            path,
+            health_path: config.kind.unwrap_or_default().health_path(),
+            active: Arc::new(AtomicUsize::new(0)),
+            failovers: Arc::new(AtomicUsize::new(0)),
+            last_recheck: Arc::new(Mutex::new(std::time::Instant::now())),
+            throttled_until: Arc::new(Mutex::new(None)),
+            throttled: Arc::new(AtomicUsize::new(0)),
+            params,
+            auth_header,
+            compress: args.compress_writes,
+            retries: args.write_retries,
+        }
+    }
+    
+    // Write a batch of lines; a partial write is reported through the rejected lines
+    async fn write(&self, batch: &[String]) -> std::result::Result<WriteOutcome, influxdb::Error> {
+        let body = batch.join("\n");
+        let payload_bytes = body.len();
+        let body = if self.compress {
+            gzip(body.as_bytes()).map_err(|e| influxdb::Error::InvalidQueryError { error: e.to_string() })?
+        } else {
+            body.into_bytes()
+        };
+        let sent = body.len();
+        
+        self.recheck_preferred().await;
+        
+        let mut attempt = 0;
+        let mut failed_over = 0;
+        loop {
+            self.wait_for_throttle().await;
+            let endpoint = self.active.load(Ordering::SeqCst);
+            let result = self.send(&self.endpoints[endpoint], body.clone(), batch).await;
+            if let Err((_, Retry::Throttled(_))) = &result {
+                self.throttled.fetch_add(1, Ordering::SeqCst);
+            }
+            match result {
+                Ok(rejected) => return Ok(WriteOutcome { payload_bytes, wire_bytes: sent, rejected }),
+                // The server asked us to slow down, so every writer waits rather than failing over
+                Err((e, Retry::Throttled(retry_after))) if attempt < self.retries => {
+                    let delay = retry_after.unwrap_or_else(|| std::time::Duration::from_millis(500 * 2u64.pow(attempt)));
+                    attempt += 1;
+                    warn!("Write to {} throttled ({}), retry {}/{} in {:?}", self.target, e, attempt, self.retries, delay);
+                    self.throttle(delay);
+                }
+                // Try the other endpoints straight away before backing off
+                Err((e, Retry::Backoff)) if failed_over + 1 < self.endpoints.len() => {
+                    failed_over += 1;
+                    self.fail_over(endpoint, &e);
+                }
+                Err((e, Retry::Backoff)) if attempt < self.retries => {
+                    let delay = std::time::Duration::from_millis(500 * 2u64.pow(attempt));
+                    attempt += 1;
+                    failed_over = 0;
+                    warn!("Write failed ({}), retry {}/{} in {:?}", e, attempt, self.retries, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+    }
+    
+    // Pause writes to the target for at least `delay`
+    fn throttle(&self, delay: std::time::Duration) {
+        let until = tokio::time::Instant::now() + delay;
+        let mut throttled_until = self.throttled_until.lock().unwrap();
+        if throttled_until.map_or(true, |current| current < until) {
+            *throttled_until = Some(until);
+        }
+    }
+    
+    async fn wait_for_throttle(&self) {
+        let until = *self.throttled_until.lock().unwrap();
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+    
+    // Move to the next endpoint after a connection error or 5xx
+    fn fail_over(&self, from: usize, cause: &influxdb::Error) {
+        let next = (from + 1) % self.endpoints.len();
+        // Another writer sharing the state may already have moved on
+        if self.active.compare_exchange(from, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            let count = self.failovers.fetch_add(1, Ordering::SeqCst) + 1;
+            warn!("Write to {} failed ({}), failing over to {} (failover {})",
+                     self.endpoints[from], cause, self.endpoints[next], count);
+        }
+    }
+    
+    // Switch back to the preferred endpoint once its health check passes again
+    async fn recheck_preferred(&self) {
+        if self.active.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        {
+            let mut last_recheck = self.last_recheck.lock().unwrap();
+            if last_recheck.elapsed() < PREFERRED_RECHECK_INTERVAL {
+                return;
+            }
+            *last_recheck = std::time::Instant::now();
+        }
+        
+        let preferred = &self.endpoints[0];
+        match self.http.get(format!("{}{}", preferred, self.health_path)).send().await {
+            Ok(response) if response.status().is_success() => {
+                info!("Preferred endpoint {} is healthy again, switching back", preferred);
+                self.active.store(0, Ordering::SeqCst);
+            }
+            _ => debug!("Preferred endpoint {} is still unavailable", preferred),
+        }
+    }
+    
+    // Send a single write request, returning the lines rejected by a partial write;
+    // errors carry whether retrying may help
+    async fn send(&self, endpoint: &str, body: Vec<u8>, batch: &[String]) -> std::result::Result<Vec<RejectedLine>, (influxdb::Error, Retry)> {
+        let mut request = self.http.post(format!("{}{}", endpoint, self.path))
+            .query(&self.params)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(body);
+        if self.compress {
+            request = request.header("Content-Encoding", "gzip");
+        }
+        if let Some(auth) = &self.auth_header {
+            request = request.header("Authorization", auth);
+        }
+        
+        let response = request.send().await
+            .map_err(|e| (influxdb::Error::ConnectionError { error: describe_connection_error(&e) }, Retry::Backoff))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(Vec::new());
+        }
+        // A 503 from one endpoint of a failover list fails over like any other 5xx
+        let throttled = status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || (status == reqwest::StatusCode::SERVICE_UNAVAILABLE && self.endpoints.len() == 1);
+        let retry = if throttled {
+            Retry::Throttled(response.headers().get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(retry_after))
+        } else if status.is_server_error() {
+            Retry::Backoff
+        } else {
+            Retry::Never
+        };
+        let body = response.text().await.unwrap_or_default();
+        if retry == Retry::Never {
+            if let Some(rejected) = parse_partial_write(&body, batch) {
+                return Ok(rejected);
+            }
+        }
+        Err((write_error(status, &body), retry))
+    }
+}
+
+// Whether a failed write request may succeed when retried
+#[derive(Debug, PartialEq)]
+enum Retry {
+    Never,
+    // Connection errors and 5xx: fail over, then back off exponentially
+    Backoff,
+    // HTTP 429, and 503 without other endpoints: wait for the Retry-After delay when the
+    // server gave one
+    Throttled(Option<std::time::Duration>),
+}
+
+// Delay requested by a Retry-After header, given in seconds or as an HTTP date
+fn retry_after(value: &str) -> Option<std::time::Duration> {
+    let value = value.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => std::time::Duration::from_secs(seconds),
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+// Result of writing one batch
+struct WriteOutcome {
+    payload_bytes: usize,
+    wire_bytes: usize,
+    // Lines the server refused while storing the rest of the batch
+    rejected: Vec<RejectedLine>,
+}
+
+// A single line of a batch rejected by a partial write
+#[derive(Debug, PartialEq)]
+struct RejectedLine {
+    // Index of the line within the batch
+    index: usize,
+    reason: String,
+}
+
+// Work out which lines of a batch were rejected from a partial-write error body, with
+// the reason for each; the other lines were stored. Returns None when the body isn't a
+// partial write or names no line of the batch, and the caller then fails the whole batch
+fn parse_partial_write(body: &str, batch: &[String]) -> Option<Vec<RejectedLine>> {
+    let (message, line) = if let Ok(e) = serde_json::from_str::<V2ErrorResponse>(body) {
+        (e.message, e.line)
+    } else if let Ok(e) = serde_json::from_str::<V1ErrorResponse>(body) {
+        (e.error, None)
+    } else {
+        return None;
+    };
+    if !message.to_ascii_lowercase().contains("partial write") {
+        return None;
+    }
+    
+    let mut rejected: Vec<RejectedLine> = Vec::new();
+    let reject = |rejected: &mut Vec<RejectedLine>, index: usize, reason: &str| {
+        if index < batch.len() && !rejected.iter().any(|r| r.index == index) {
+            rejected.push(RejectedLine { index, reason: reason.trim().to_string() });
+        }
+    };
+    
+    for entry in message.lines() {
+        let entry = entry.trim();
+        if let Some((number, reason)) = entry.strip_prefix("line ").and_then(|rest| rest.split_once(": ")) {
+            // 2.x: "line 3: <reason>" with 1-based line numbers
+            if let Some(index) = number.trim().parse::<usize>().ok().and_then(|n| n.checked_sub(1)) {
+                reject(&mut rejected, index, reason);
+            }
+        } else if let Some(start) = entry.find("unable to parse '") {
+            // "unable to parse '<line>': <reason>" quotes the offending line
+            let rest = &entry[start + "unable to parse '".len()..];
+            if let Some((text, reason)) = rest.rsplit_once("': ") {
+                if let Some(index) = batch.iter().position(|l| l == text) {
+                    reject(&mut rejected, index, &format!("unable to parse: {}", reason));
+                }
+            }
+        } else if let Some(conflict) = FieldTypeConflict::parse(entry) {
+            // Type conflicts name the field, not the line, so match lines by field type
+            for (index, line) in batch.iter().enumerate() {
+                if conflict.matches(line) {
+                    reject(&mut rejected, index, entry);
+                }
+            }
+        }
+    }
+    
+    // 2.x may only report the first failing line in the "line" field
+    if let Some(index) = line.and_then(|n| (n as usize).checked_sub(1)) {
+        if rejected.is_empty() {
+            reject(&mut rejected, index, &message);
+        }
+    }
+    
+    if rejected.is_empty() {
+        None
+    } else {
+        rejected.sort_by_key(|r| r.index);
+        Some(rejected)
+    }
+}
+
+// `field type conflict: input field "f" on measurement "m" is type float, already exists as type integer`
+struct FieldTypeConflict {
+    field: String,
+    measurement: String,
+    input_type: String,
+}
+
+impl FieldTypeConflict {
+    fn parse(entry: &str) -> Option<Self> {
+        let rest = &entry[entry.find("input field \"")? + "input field \"".len()..];
+        let (field, rest) = rest.split_once("\" on measurement \"")?;
+        let (measurement, rest) = rest.split_once("\" is type ")?;
+        let input_type = rest.split(|c: char| c == ',' || c.is_whitespace()).next()?;
+        Some(FieldTypeConflict {
+            field: field.to_string(),
+            measurement: measurement.to_string(),
+            input_type: input_type.to_string(),
+        })
+    }
+    
+    // Whether a line protocol line writes the conflicting field with the rejected type
+    fn matches(&self, line: &str) -> bool {
+        let parts = split_unescaped(line, ' ');
+        let (Some(series), Some(fields)) = (parts.first(), parts.get(1)) else {
+            return false;
+        };
+        let measurement = split_unescaped(series, ',').into_iter().next().unwrap_or_default();
+        if unescape_line_protocol(&measurement) != self.measurement {
+            return false;
+        }
+        split_unescaped(fields, ',').iter().any(|field| {
+            let Some((key, value)) = field.split_once('=') else {
+                return false;
+            };
+            unescape_line_protocol(key) == self.field && line_protocol_type(value) == self.input_type
+        })
+    }
+}
+
+// Split on a separator that is neither backslash-escaped nor inside a quoted string
+fn split_unescaped(s: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+    let mut quoted = false;
+    for c in s.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == separator && !quoted {
+            parts.push(std::mem::take(&mut current));
+            continue;
+        }
+        current.push(c);
+    }
+    parts.push(current);
+    parts
+}
+
+// Remove line protocol escaping from a measurement name or key
+fn unescape_line_protocol(s: &str) -> String {
+    s.replace("\\ ", " ").replace("\\,", ",").replace("\\=", "=")
+}
+
+// InfluxDB type name of a line protocol field value
+fn line_protocol_type(value: &str) -> &'static str {
+    if value.starts_with('"') {
+        "string"
+    } else if value.strip_suffix('i').map_or(false, |v| v.parse::<i64>().is_ok()) {
+        "integer"
+    } else if value.strip_suffix('u').map_or(false, |v| v.parse::<u64>().is_ok()) {
+        "unsigned"
+    } else if matches!(value, "t" | "T" | "true" | "True" | "TRUE" | "f" | "F" | "false" | "False" | "FALSE") {
+        "boolean"
+    } else {
+        "float"
+    }
+}
+
+// A destination for points: an InfluxDB or VictoriaMetrics target, a line protocol file
+// or directory, or standard output. New backends only need to implement this trait
+#[async_trait::async_trait]
+trait Sink: Send + Sync {
+    // Name of the sink in logs and per-target statistics
+    fn name(&self) -> String;
+    
+    // Check the destination can be written before any parsing begins
+    async fn preflight(&mut self) -> Result<()> {
+        Ok(())
+    }
+    
+    // Write a batch of points from an input file, reporting the bytes sent and any points
+    // the destination rejected
+    async fn write_batch(&self, source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError>;
+    
+    // Complete the output of an input file
+    fn end_file(&self, _source: &Path) -> std::result::Result<(), CursedStatsError> {
+        Ok(())
+    }
+    
+    // Flush any buffered output once all batches have been written
+    fn flush(&self) -> std::result::Result<(), CursedStatsError>;
+    
+    // Checks written points against the destination after each file (--verify)
+    fn verifier(&self) -> Option<Verifier> {
+        None
+    }
+    
+    // Nanosecond timestamp of the latest point stored in a measurement (--incremental);
+    // None when it holds no points or the destination can't be queried
+    async fn last_timestamp(&self, _measurement: &str) -> Result<Option<i64>> {
+        Ok(None)
+    }
+    
+    // Whether last_timestamp looks up the stored points rather than always giving None
+    fn queries_last_timestamp(&self) -> bool {
+        false
+    }
+    
+    // Number of endpoint failovers so far
+    fn failovers(&self) -> usize {
+        0
+    }
+    
+    // Number of write requests the destination throttled so far
+    fn throttled(&self) -> usize {
+        0
+    }
+    
+    // Lines written to local line protocol output
+    fn lines_written(&self) -> usize {
+        0
+    }
+    
+    // Messages acknowledged by a message broker
+    fn messages_sent(&self) -> usize {
+        0
+    }
+    
+    // Best-effort targets whose failures are logged but don't fail records
+    fn secondary(&self) -> bool {
+        false
+    }
+    
+    // Where the sink writes to, for --cache-key destination
+    fn destination(&self) -> String {
+        self.name()
+    }
+}
+
+// The sinks batches are written to; several when mirroring to more than one target.
+// Cloning shares the same sinks, so several files can write concurrently
+#[derive(Clone)]
+struct BatchSink {
+    sinks: Vec<Arc<dyn Sink>>,
+    // Count secondary targets towards the write quorum (--require-all-targets)
+    require_all_targets: bool,
+}
+
+// Result of writing a batch to one target
+struct TargetWrite {
+    target: String,
+    // Whether the outcome counts towards the write quorum; secondary failures are only logged
+    required: bool,
+    result: std::result::Result<WriteOutcome, CursedStatsError>,
+}
+
+impl BatchSink {
+    fn new(sinks: Vec<Box<dyn Sink>>, require_all_targets: bool) -> Self {
+        BatchSink { sinks: sinks.into_iter().map(Arc::from).collect(), require_all_targets }
+    }
+    
+    fn required(&self, sink: &Arc<dyn Sink>) -> bool {
+        self.require_all_targets || !sink.secondary()
+    }
+    
+    // Total number of endpoint failovers across all targets
+    fn failovers(&self) -> usize {
+        self.sinks.iter().map(|sink| sink.failovers()).sum()
+    }
+    
+    fn throttled(&self) -> usize {
+        self.sinks.iter().map(|sink| sink.throttled()).sum()
+    }
+    
+    fn target_count(&self) -> usize {
+        self.sinks.len()
+    }
+    
+    fn lines_written(&self) -> usize {
+        self.sinks.iter().map(|sink| sink.lines_written()).sum()
+    }
+    
+    fn messages_sent(&self) -> usize {
+        self.sinks.iter().map(|sink| sink.messages_sent()).sum()
+    }
+    
+    fn end_file(&self, source: &Path) -> std::result::Result<(), CursedStatsError> {
+        self.sinks.iter().try_for_each(|sink| sink.end_file(source))
+    }
+    
+    // Write a batch of points from an input file to every target, reporting per target
+    async fn write(&self, source: Option<&Path>, points: Vec<Point>) -> Vec<TargetWrite> {
+        if let [sink] = self.sinks.as_slice() {
+            let result = sink.write_batch(source, points).await;
+            return vec![TargetWrite { target: sink.name(), required: self.required(sink), result }];
+        }
+        
+        // Write to all targets concurrently so a slow or failing one doesn't hold up the others
+        let measurement = points.first().map(|p| p.measurement.clone()).unwrap_or_default();
+        let handles: Vec<_> = self.sinks.iter().map(|sink| {
+            let sink = Arc::clone(sink);
+            let source = source.map(Path::to_path_buf);
+            let points = points.clone();
+            tokio::spawn(async move { sink.write_batch(source.as_deref(), points).await })
+        }).collect();
+        let mut outcomes = Vec::with_capacity(handles.len());
+        for (sink, handle) in self.sinks.iter().zip(handles) {
+            let result = handle.await.unwrap_or_else(|e| Err(CursedStatsError::InfluxWrite {
+                measurement: measurement.clone(),
+                cause: influxdb::Error::ConnectionError { error: format!("write task failed: {}", e) },
+            }));
+            outcomes.push(TargetWrite { target: sink.name(), required: self.required(sink), result });
+        }
+        outcomes
+    }
+    
+    fn finish(&self) -> std::result::Result<(), CursedStatsError> {
+        self.sinks.iter().try_for_each(|sink| sink.flush())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for InfluxTarget {
+    fn name(&self) -> String {
+        self.writer.target.clone()
+    }
+    
+    async fn preflight(&mut self) -> Result<()> {
+        if self.skip_preflight {
+            warn!("Skipping pre-flight checks for {} (--skip-preflight)", self.writer.target);
+        } else {
+            // Start on the first reachable endpoint of a failover list
+            let endpoint = first_reachable_endpoint(&self.http, &self.endpoints, self.kind).await?;
+            if endpoint > 0 {
+                self.use_endpoint(endpoint)?;
+            }
+        }
+        // VictoriaMetrics has no databases, retention policies or InfluxQL to check
+        if self.kind == TargetKind::Influxdb {
+            if self.create_database {
+                self.target.create_database().await?;
+            }
+            if !self.skip_preflight {
+                self.target.check_connection().await?;
+                if let (WriteTarget::V1(client), Some(rp)) = (&self.target, &self.retention_policy) {
+                    check_retention_policy(client, rp).await?;
+                }
+            }
+        }
+        if !self.skip_preflight {
+            info!("Pre-flight checks passed for {}", self.writer.target);
+        }
+        Ok(())
+    }
+    
+    async fn write_batch(&self, _source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let measurement = points.first().map(|p| p.measurement.clone()).unwrap_or_default();
+        let batch: Vec<String> = points.iter().map(Point::to_line_protocol).collect();
+        self.writer.write(&batch).await.map_err(|cause| CursedStatsError::InfluxWrite { measurement, cause })
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        Ok(())
+    }
+    
+    fn verifier(&self) -> Option<Verifier> {
+        (self.kind == TargetKind::Influxdb).then(|| Verifier {
+            target: self.writer.target.clone(),
+            client: self.target.clone(),
+            retention_policy: self.retention_policy.clone(),
+            required: true,
+        })
+    }
+    
+    async fn last_timestamp(&self, measurement: &str) -> Result<Option<i64>> {
+        if self.kind != TargetKind::Influxdb {
+            return Ok(None);
+        }
+        self.target.last_timestamp(measurement, self.retention_policy.as_deref()).await
+            .with_context(|| format!("Failed to query the latest point of {} on {}", measurement, self.writer.target))
+    }
+    
+    fn queries_last_timestamp(&self) -> bool {
+        self.kind == TargetKind::Influxdb
+    }
+    
+    fn failovers(&self) -> usize {
+        self.writer.failovers.load(Ordering::SeqCst)
+    }
+    
+    fn throttled(&self) -> usize {
+        self.writer.throttled.load(Ordering::SeqCst)
+    }
+    
+    fn secondary(&self) -> bool {
+        self.config.secondary
+    }
+    
+    fn destination(&self) -> String {
+        let db = match self.api_version {
+            ApiVersion::V1 => self.db_name.as_str(),
+            ApiVersion::V2 => self.config.bucket.as_deref().unwrap_or(&self.db_name),
+        };
+        format!("{}/{}", self.config.url, db)
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Mutex<LineProtocolFile> {
+    fn name(&self) -> String {
+        self.lock().unwrap().base.display().to_string()
+    }
+    
+    async fn preflight(&mut self) -> Result<()> {
+        info!("Output only: writing line protocol to {} instead of InfluxDB", self.get_mut().unwrap().base.display());
+        Ok(())
+    }
+    
+    async fn write_batch(&self, _source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let sent = self.lock().unwrap().write(&points)?;
+        Ok(WriteOutcome { payload_bytes: sent.saturating_sub(1), wire_bytes: sent, rejected: Vec::new() })
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        self.lock().unwrap().finish()
+    }
+    
+    fn lines_written(&self) -> usize {
+        self.lock().unwrap().lines
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Mutex<LineProtocolDir> {
+    fn name(&self) -> String {
+        self.lock().unwrap().dir.display().to_string()
+    }
+    
+    async fn preflight(&mut self) -> Result<()> {
+        info!("Writing line protocol files to {} instead of InfluxDB", self.get_mut().unwrap().dir.display());
+        Ok(())
+    }
+    
+    async fn write_batch(&self, source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let batch: Vec<String> = points.iter().map(Point::to_line_protocol).collect();
+        let payload_bytes = batch.iter().map(|line| line.len() + 1).sum::<usize>().saturating_sub(1);
+        let sent = self.lock().unwrap().write(source, &batch)?;
+        Ok(WriteOutcome { payload_bytes, wire_bytes: sent, rejected: Vec::new() })
+    }
+    
+    fn end_file(&self, source: &Path) -> std::result::Result<(), CursedStatsError> {
+        self.lock().unwrap().close(Some(source))
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        self.lock().unwrap().finish()
+    }
+    
+    fn lines_written(&self) -> usize {
+        self.lock().unwrap().lines
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Mutex<LineProtocolStdout> {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+    
+    async fn preflight(&mut self) -> Result<()> {
+        info!("Writing line protocol to standard output instead of InfluxDB");
+        Ok(())
+    }
+    
+    // Blocking on a slow reader holds up this batch, which in turn stops the bounded
+    // channels from filling further
+    async fn write_batch(&self, _source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let mut stdout = self.lock().unwrap();
+        let body = points.iter().take(stdout.remaining()).map(Point::to_line_protocol).collect::<Vec<_>>().join("\n");
+        let sent = stdout.write(&body)?;
+        Ok(WriteOutcome { payload_bytes: body.len(), wire_bytes: sent, rejected: Vec::new() })
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        self.lock().unwrap().flush()
+    }
+    
+    fn lines_written(&self) -> usize {
+        self.lock().unwrap().lines
+    }
+}
+
+// Streams line protocol to QuestDB's InfluxDB line protocol port over TCP (--questdb-addr).
+// QuestDB has no databases, so each measurement becomes a table
+struct QuestDbSink {
+    addr: String,
+    retries: u32,
+    // Reopened on the next attempt after a write fails
+    stream: tokio::sync::Mutex<Option<tokio::net::TcpStream>>,
+}
+
+impl QuestDbSink {
+    fn new(addr: &str, retries: u32) -> Self {
+        QuestDbSink { addr: addr.to_string(), retries, stream: tokio::sync::Mutex::new(None) }
+    }
+    
+    async fn connect(&self) -> std::io::Result<tokio::net::TcpStream> {
+        let stream = tokio::net::TcpStream::connect(&self.addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+    
+    // Send a whole batch; a failed connection is dropped so the next attempt reconnects
+    async fn send(&self, body: &[u8]) -> std::io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+        
+        let mut stream = self.stream.lock().await;
+        let connection = match stream.take() {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+        let connection = stream.insert(connection);
+        if let Err(e) = connection.write_all(body).await {
+            *stream = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// Table name for a measurement, with the characters QuestDB doesn't allow in table names
+// replaced by underscores
+fn questdb_table_name(measurement: &str) -> String {
+    measurement
+        .chars()
+        .map(|c| if c.is_control() || c == '\u{feff}' || ".?,'\"\\/:()+*%~".contains(c) { '_' } else { c })
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl Sink for QuestDbSink {
+    fn name(&self) -> String {
+        self.addr.clone()
+    }
+    
+    async fn preflight(&mut self) -> Result<()> {
+        let stream = self.connect().await
+            .with_context(|| format!("Failed to connect to QuestDB at {}", self.addr))?;
+        *self.stream.get_mut() = Some(stream);
+        info!("Writing line protocol to QuestDB at {}", self.addr);
+        Ok(())
+    }
+    
+    // A batch that fails part-way is sent again in full. Every point carries its timestamp,
+    // so tables with deduplication enabled store the repeated lines only once
+    async fn write_batch(&self, _source: Option<&Path>, mut points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        for point in &mut points {
+            point.measurement = questdb_table_name(&point.measurement);
+        }
+        let mut body = points.iter().map(Point::to_line_protocol).collect::<Vec<_>>().join("\n");
+        body.push('\n');
+        
+        let mut attempt = 0;
+        loop {
+            match self.send(body.as_bytes()).await {
+                Ok(()) => return Ok(WriteOutcome { payload_bytes: body.len(), wire_bytes: body.len(), rejected: Vec::new() }),
+                Err(e) if attempt < self.retries => {
+                    let delay = std::time::Duration::from_millis(500 * 2u64.pow(attempt));
+                    attempt += 1;
+                    warn!("Write to QuestDB at {} failed ({}), reconnecting, retry {}/{} in {:?}", self.addr, e, attempt, self.retries, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(cause) => return Err(CursedStatsError::QuestDbWrite { addr: self.addr.clone(), cause }),
+            }
+        }
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        Ok(())
+    }
+}
+
+// What a cache entry is keyed by (--cache-key)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CacheKey {
+    Path,
+    Destination,
+}
+
+// The path a file is cached under: absolute, with symlinks and `.`/`..` resolved, so that
+// invocations from another directory or with another spelling of --scan-dir find the same
+// entries. Paths that can't be resolved, e.g. of deleted files, are kept as given
+fn canonical_path(path: &Path) -> String {
+    let Ok(canonical) = std::fs::canonicalize(path) else {
+        return path.to_string_lossy().to_string();
+    };
+    let canonical = canonical.to_string_lossy().to_string();
+    // Windows paths are case-insensitive, and canonicalize adds the verbatim prefix
+    #[cfg(windows)]
+    let canonical = canonical.strip_prefix(r"\\?\").unwrap_or(&canonical).replace('\\', "/").to_lowercase();
+    canonical
+}
+
+// Re-key entries cached under paths that aren't canonical, e.g. relative ones from before
+// keys were canonicalized, when the file can still be found. Where both forms are cached the
+// more recently imported entry wins. Returns the number of entries re-keyed
+fn canonicalize_cache_keys(cache: &mut HashMap<String, FileMetadata>) -> usize {
+    let stale: Vec<(String, String)> = cache.iter()
+        .filter_map(|(key, metadata)| {
+            // Destination keys carry the path as their prefix
+            let suffix = key.strip_prefix(metadata.path.as_str())?;
+            let canonical = canonical_path(Path::new(&metadata.path));
+            (canonical != metadata.path).then(|| (key.clone(), format!("{}{}", canonical, suffix)))
+        })
+        .collect();
+    let migrated = stale.len();
+    for (key, canonical_key) in stale {
+        let Some(mut metadata) = cache.remove(&key) else {
+            continue;
+        };
+        metadata.path = canonical_path(Path::new(&metadata.path));
+        if cache.get(&canonical_key).map_or(true, |existing| existing.last_processed < metadata.last_processed) {
+            cache.insert(canonical_key, metadata);
+        }
+    }
+    migrated
+}
+
+// Key of a file's cache entry; with --cache-key destination the same file has one entry per
+// destination it was imported to
+fn cache_key(path: &str, destination: Option<&str>) -> String {
+    match destination {
+        Some(destination) => format!("{} -> {}", path, destination),
+        None => path.to_string(),
+    }
+}
+
+// Where a run writes, for destination cache keys: the targets and the measurements, which
+// a sidecar can change per file
+#[derive(Debug, Clone)]
+struct CacheDestination {
+    targets: String,
+    measurement: String,
+    // --measurement-template, which names the measurements instead of --measurement and sidecars
+    template: Option<String>,
+    // --column-group measurements records are split into as well
+    column_groups: Vec<String>,
+}
+
+impl CacheDestination {
+    // Destination part of a key, given the measurement of the file's sidecar, e.g.
+    // "cpu+mem@http://localhost:8086/cursed_stats". Without sidecar and column groups this
+    // is the key of caches written before they were taken into account
+    fn key(&self, sidecar_measurement: Option<&str>) -> String {
+        let mut measurements = self.template.as_deref().or(sidecar_measurement).unwrap_or(&self.measurement).to_string();
+        for group in &self.column_groups {
+            measurements.push('+');
+            measurements.push_str(group);
+        }
+        format!("{}@{}", measurements, self.targets)
+    }
+    
+    // Destination part of the key of a file on disk; a sidecar that can't be read is
+    // reported when the file is parsed
+    fn for_file(&self, path: &Path) -> String {
+        let sidecar = load_sidecar_metadata(path).ok().and_then(|metadata| metadata.measurement);
+        self.key(sidecar.as_deref())
+    }
+}
+
+// Re-key entries cached under their path alone, from before destination keys were the
+// default, to the destination of this run; they are assumed to have been imported there.
+// Entries already cached for the destination are kept. Returns the number re-keyed
+fn assign_path_keys(cache: &mut HashMap<String, FileMetadata>, destination: &CacheDestination) -> usize {
+    let bare: Vec<String> = cache.iter()
+        .filter(|(key, metadata)| **key == metadata.path)
+        .map(|(key, _)| key.clone())
+        .collect();
+    let assigned = bare.len();
+    for key in bare {
+        let Some(metadata) = cache.remove(&key) else {
+            continue;
+        };
+        let destination = destination.for_file(Path::new(&metadata.path));
+        cache.entry(cache_key(&key, Some(&destination))).or_insert(metadata);
+    }
+    assigned
+}
+
+// Time period used to split --output-only files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum SplitPeriod {
+    Day,
+    Week,
+    Month,
+}
+
+impl SplitPeriod {
+    // Label of the period containing a nanosecond timestamp, e.g. 2024-01-15, 2024-W03 or 2024-01
+    fn label(&self, nanos: i64) -> Option<String> {
+        let dt = chrono::DateTime::from_timestamp(nanos.div_euclid(1_000_000_000), nanos.rem_euclid(1_000_000_000) as u32)?;
+        let format = match self {
+            SplitPeriod::Day => "%Y-%m-%d",
+            SplitPeriod::Week => "%G-W%V",
+            SplitPeriod::Month => "%Y-%m",
+        };
+        Some(dt.format(format).to_string())
+    }
+}
+
+// Line protocol output file(s) used by --output-only, optionally split by measurement
+// and date. Every file stays open until the run finishes, so records can go back and
+// forth between them
+struct LineProtocolFile {
+    base: PathBuf,
+    split: Option<SplitPeriod>,
+    // One file per measurement (--split-files-by-measurement)
+    by_measurement: bool,
+    writers: HashMap<PathBuf, std::io::BufWriter<File>>,
+    lines: usize,
+}
+
+impl LineProtocolFile {
+    fn create(base: &Path, split: Option<SplitPeriod>, by_measurement: bool) -> std::result::Result<Self, CursedStatsError> {
+        let mut file = LineProtocolFile { base: base.to_path_buf(), split, by_measurement, writers: HashMap::new(), lines: 0 };
+        // Without splitting everything goes to the base path, which exists even without records
+        if split.is_none() && !by_measurement {
+            file.writers.insert(base.to_path_buf(), open_output_file(base)?);
+        }
+        Ok(file)
+    }
+    
+    // Write the points to their files, returning the number of bytes written
+    fn write(&mut self, points: &[Point]) -> std::result::Result<usize, CursedStatsError> {
+        let mut written = 0;
+        for point in points {
+            let path = self.path_for(point);
+            let writer = match self.writers.entry(path) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    debug!("Writing line protocol to {}", entry.key().display());
+                    let writer = open_output_file(entry.key())?;
+                    entry.insert(writer)
+                }
+            };
+            let line = point.to_line_protocol();
+            writeln!(writer, "{}", line)
+                .map_err(|cause| CursedStatsError::OutputIo { path: self.base.clone(), cause })?;
+            self.lines += 1;
+            written += line.len() + 1;
+        }
+        Ok(written)
+    }
+    
+    // File a point goes to: the base path, or `<stem>_<measurement>_<period>.<ext>` next to it
+    // with the parts that are split by, e.g. out_cpu_2024-01-15.lp
+    fn path_for(&self, point: &Point) -> PathBuf {
+        if self.split.is_none() && !self.by_measurement {
+            return self.base.clone();
+        }
+        let mut name = self.base.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        if self.by_measurement {
+            // Measurements may hold characters that aren't allowed in file names
+            name.push('_');
+            name.extend(point.measurement.chars().map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' }));
+        }
+        if let Some(split) = self.split {
+            let period = point.timestamp.and_then(|nanos| split.label(nanos)).unwrap_or_else(|| "undated".to_string());
+            name.push('_');
+            name.push_str(&period);
+        }
+        let extension = self.base.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_else(|| "lp".to_string());
+        self.base.with_file_name(format!("{}.{}", name, extension))
+    }
+    
+    fn flush(&mut self) -> std::result::Result<(), CursedStatsError> {
+        for (path, writer) in &mut self.writers {
+            writer.flush()
+                .map_err(|cause| CursedStatsError::OutputIo { path: path.clone(), cause })?;
+        }
+        Ok(())
+    }
+    
+    fn finish(&mut self) -> std::result::Result<(), CursedStatsError> {
+        self.flush()?;
+        if self.split.is_some() || self.by_measurement {
+            info!("Wrote {} lines of line protocol to {} files based on {}",
+                     self.lines, self.writers.len(), self.base.display());
+        } else {
+            info!("Wrote {} lines of line protocol to {}", self.lines, self.base.display());
+        }
+        Ok(())
+    }
+}
+
+// Line protocol files written by --output-lp, one per input file
+struct LineProtocolDir {
+    dir: PathBuf,
+    scan_dir: PathBuf,
+    gzip: bool,
+    // Open output per input file, several when files are written concurrently
+    open: HashMap<PathBuf, OpenLineProtocolFile>,
+    // Input each output file was written for, so no two inputs share one
+    outputs: HashMap<PathBuf, PathBuf>,
+    files: usize,
+    lines: usize,
+}
+
+struct OpenLineProtocolFile {
+    path: PathBuf,
+    writer: LineProtocolWriter,
+}
+
+enum LineProtocolWriter {
+    Plain(std::io::BufWriter<File>),
+    Gzip(GzEncoder<std::io::BufWriter<File>>),
+}
+
+impl LineProtocolDir {
+    fn create(dir: &Path, scan_dir: &Path, gzip: bool) -> std::result::Result<Self, CursedStatsError> {
+        std::fs::create_dir_all(dir)
+            .map_err(|cause| CursedStatsError::OutputIo { path: dir.to_path_buf(), cause })?;
+        Ok(LineProtocolDir {
+            dir: dir.to_path_buf(),
+            scan_dir: scan_dir.to_path_buf(),
+            gzip,
+            open: HashMap::new(),
+            outputs: HashMap::new(),
+            files: 0,
+            lines: 0,
+        })
+    }
+    
+    // Output path mirroring the input's path below the scan directory, e.g. a/b.csv -> <dir>/a/b.lp.
+    // Inputs that would share a file, such as b.csv and b.tsv or two b.csv files outside the
+    // scan directory, get a numbered one instead: <dir>/a/b-2.lp
+    fn output_path(&mut self, source: Option<&Path>) -> PathBuf {
+        let canonical_scan_dir = self.scan_dir.canonicalize().ok();
+        let relative = source
+            .and_then(|source| source.strip_prefix(&self.scan_dir).ok()
+                .or_else(|| canonical_scan_dir.as_deref().and_then(|dir| source.strip_prefix(dir).ok()))
+                .or_else(|| source.file_name().map(Path::new)))
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("import"));
+        let extension = if self.gzip { "lp.gz" } else { "lp" };
+        let stem = self.dir.join(relative).with_extension("");
+        let owner = source.map(Path::to_path_buf).unwrap_or_default();
+        let mut n = 1;
+        loop {
+            let mut path = stem.clone().into_os_string();
+            if n > 1 {
+                path.push(format!("-{}", n));
+            }
+            path.push(format!(".{}", extension));
+            let path = PathBuf::from(path);
+            if !matches!(self.outputs.get(&path), Some(claimed) if *claimed != owner) {
+                self.outputs.insert(path.clone(), owner);
+                return path;
+            }
+            n += 1;
+        }
+    }
+    
+    // Open the output file of an input, starting with comments that describe
+    // where the lines came from and their precision
+    fn open_file(&mut self, source: Option<&Path>) -> std::result::Result<OpenLineProtocolFile, CursedStatsError> {
+        let path = self.output_path(source);
+        let output_io = |cause| CursedStatsError::OutputIo { path: path.clone(), cause };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(output_io)?;
+        }
+        let file = std::io::BufWriter::new(File::create(&path).map_err(output_io)?);
+        let mut writer = if self.gzip {
+            LineProtocolWriter::Gzip(GzEncoder::new(file, Compression::default()))
+        } else {
+            LineProtocolWriter::Plain(file)
+        };
+        let source = source.map_or_else(|| "-".to_string(), |s| s.display().to_string());
+        write!(writer.inner(), "# source: {}\n# precision: ns (influx write --precision ns)\n", source)
+            .map_err(output_io)?;
+        Ok(OpenLineProtocolFile { path, writer })
+    }
+    
+    fn write(&mut self, source: Option<&Path>, batch: &[String]) -> std::result::Result<usize, CursedStatsError> {
+        let key = source.map(Path::to_path_buf).unwrap_or_default();
+        if !self.open.contains_key(&key) {
+            let file = self.open_file(source)?;
+            self.open.insert(key.clone(), file);
+            self.files += 1;
+        }
+        let file = self.open.get_mut(&key).expect("output file was just opened");
+        let mut sent = 0;
+        for line in batch {
+            writeln!(file.writer.inner(), "{}", line)
+                .map_err(|cause| CursedStatsError::OutputIo { path: file.path.clone(), cause })?;
+            sent += line.len() + 1;
+            self.lines += 1;
+        }
+        Ok(sent)
+    }
+    
+    // Flush and close the file of an input; gzip output is only valid once its trailer is written
+    fn close(&mut self, source: Option<&Path>) -> std::result::Result<(), CursedStatsError> {
+        let key = source.map(Path::to_path_buf).unwrap_or_default();
+        if let Some(OpenLineProtocolFile { path, writer }) = self.open.remove(&key) {
+            let output_io = |cause| CursedStatsError::OutputIo { path: path.clone(), cause };
+            match writer {
+                LineProtocolWriter::Plain(mut file) => file.flush().map_err(output_io)?,
+                LineProtocolWriter::Gzip(encoder) => encoder.finish().and_then(|mut file| file.flush()).map_err(output_io)?,
+            }
+        }
+        Ok(())
+    }
+    
+    fn finish(&mut self) -> std::result::Result<(), CursedStatsError> {
+        let sources: Vec<PathBuf> = self.open.keys().cloned().collect();
+        for source in sources {
+            self.close(Some(&source))?;
+        }
+        info!("Wrote {} lines of line protocol to {} files in {}", self.lines, self.files, self.dir.display());
+        Ok(())
+    }
+}
+
+impl LineProtocolWriter {
+    fn inner(&mut self) -> &mut dyn Write {
+        match self {
+            LineProtocolWriter::Plain(file) => file,
+            LineProtocolWriter::Gzip(encoder) => encoder,
+        }
+    }
+}
+
+// Line protocol printed to standard output by --stdout
+struct LineProtocolStdout {
+    lines: usize,
+    // Lines to print at most (--limit)
+    limit: Option<usize>,
+}
+
+impl LineProtocolStdout {
+    fn new(limit: Option<usize>) -> Self {
+        LineProtocolStdout { lines: 0, limit }
+    }
+    
+    // Number of lines that can still be printed
+    fn remaining(&self) -> usize {
+        self.limit.map_or(usize::MAX, |limit| limit.saturating_sub(self.lines))
+    }
+    
+    fn write(&mut self, body: &str) -> std::result::Result<usize, CursedStatsError> {
+        if body.is_empty() {
+            return Ok(0);
+        }
+        let output_io = |cause| CursedStatsError::OutputIo { path: PathBuf::from("<stdout>"), cause };
+        writeln!(std::io::stdout().lock(), "{}", body).map_err(output_io)?;
+        self.lines += body.lines().count();
+        if self.remaining() == 0 {
+            info!("Printed the {} points of --limit, the rest are not printed", self.lines);
+        }
+        Ok(body.len() + 1)
+    }
+    
+    fn flush(&mut self) -> std::result::Result<(), CursedStatsError> {
+        std::io::stdout().flush()
+            .map_err(|cause| CursedStatsError::OutputIo { path: PathBuf::from("<stdout>"), cause })
+    }
+}
+
+// Create or truncate an output file
+fn open_output_file(path: &Path) -> std::result::Result<std::io::BufWriter<File>, CursedStatsError> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|cause| CursedStatsError::OutputIo { path: path.to_path_buf(), cause })?;
+    Ok(std::io::BufWriter::new(file))
+}
+
+// A record that permanently failed to write, as stored in the dead-letter file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DeadLetterEntry {
+    source_file: String,
+    measurement: String,
+    timestamp: String,
+    fields: Fields,
+    line: Option<String>,
+    error: String,
+    // Server the write failed on, when mirroring to several
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl DeadLetterEntry {
+    fn new(source_file: &Path, measurement: &str, record: DynamicRecord, line: Option<String>, error: String) -> Self {
+        DeadLetterEntry {
+            source_file: source_file.to_string_lossy().to_string(),
+            measurement: measurement.to_string(),
+            timestamp: record.timestamp,
+            fields: record.fields,
+            line,
+            error,
+            target: None,
+            failed_at: chrono::Utc::now(),
+        }
+    }
+    
+    fn with_target(mut self, target: Option<String>) -> Self {
+        self.target = target;
+        self
+    }
+}
+
+// Append-only NDJSON dead-letter file, safe to share between writer tasks
+struct DeadLetterWriter {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl DeadLetterWriter {
+    fn open(path: &Path) -> std::result::Result<Self, CursedStatsError> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|cause| CursedStatsError::OutputIo { path: path.to_path_buf(), cause })?;
+        Ok(DeadLetterWriter { path: path.to_path_buf(), file: Mutex::new(file) })
+    }
+    
+    // Entries are serialized up front and appended in one write so concurrent
+    // writers never interleave partial lines
+    fn write(&self, entries: &[DeadLetterEntry]) -> std::result::Result<(), CursedStatsError> {
+        let output_io = |cause| CursedStatsError::OutputIo { path: self.path.clone(), cause };
+        let mut buffer = String::new();
+        for entry in entries {
+            buffer.push_str(&serde_json::to_string(entry).map_err(|e| output_io(e.into()))?);
+            buffer.push('\n');
+        }
+        
+        let mut file = self.file.lock().unwrap();
+        file.write_all(buffer.as_bytes()).map_err(output_io)
+    }
+}
+
+// A point read back from a dead-letter or line protocol file
+struct PendingLine {
+    point: Point,
+    // Original entry when read from a dead-letter file, rewritten if the retry fails too
+    entry: Option<DeadLetterEntry>,
+}
+
+// Write previously failed records back to InfluxDB
+async fn import_dead_letters(
+    dir: &Path,
+    sink: &BatchSink,
+    batch_size: usize,
+    write_quorum: usize,
+    dry_run: bool,
+    query_options: &QueryOptions,
+    dead_letter: Option<&DeadLetterWriter>,
+) -> Result<ImportStats> {
+    info!("Importing dead letters from {}", dir.display());
+    // Records failing again would be appended to a file still being replayed, or read back
+    // on the next run of the same command, so the output must live outside the input
+    if let Some(writer) = dead_letter {
+        let input = dir.canonicalize().with_context(|| format!("Failed to resolve {}", dir.display()))?;
+        let output = writer.path.canonicalize().with_context(|| format!("Failed to resolve {}", writer.path.display()))?;
+        if output.starts_with(&input) {
+            anyhow::bail!("--dead-letter {} is inside --import-dead-letters {}; write the records that fail again elsewhere",
+                  writer.path.display(), dir.display());
+        }
+    }
+    let mut stats = ImportStats { started: Some(tokio::time::Instant::now()), ..ImportStats::default() };
+    
+    for entry in WalkDir::new(dir).into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let pending = match read_dead_letter_file(path, query_options) {
+            Ok(Some(pending)) => pending,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Failed to read dead letters from {}: {:#}", path.display(), e);
+                stats.files_failed += 1;
+                continue;
+            }
+        };
+        info!("Retrying {} records from {}", pending.len(), path.display());
+        stats.files_found += 1;
+        stats.files_processed += 1;
+        stats.records_processed += pending.len();
+        
+        // Batches are written per measurement so errors name the right one
+        let mut by_measurement: BTreeMap<String, Vec<PendingLine>> = BTreeMap::new();
+        for line in pending {
+            by_measurement.entry(line.point.measurement.clone()).or_default().push(line);
+        }
+        
+        let mut dead_letters = Vec::new();
+        for (measurement, pending) in by_measurement {
+            for chunk in pending.chunks(batch_size) {
+                if dry_run {
+                    stats.successful_inserts += chunk.len();
+                    continue;
+                }
+                let batch: Vec<Point> = chunk.iter().map(|p| p.point.clone()).collect();
+                let mut accepted = vec![0; batch.len()];
+                let mut errors = vec![None; batch.len()];
+                for outcome in sink.write(None, batch).await {
+                    match outcome.result {
+                        Ok(written) => {
+                            stats.payload_bytes += written.payload_bytes;
+                            stats.wire_bytes += written.wire_bytes;
+                            if !outcome.required {
+                                for rejected in written.rejected {
+                                    warn!("Dead letter rejected by secondary target {}: {}", outcome.target, rejected.reason);
+                                }
+                                continue;
+                            }
+                            for count in &mut accepted {
+                                *count += 1;
+                            }
+                            for rejected in written.rejected {
+                                accepted[rejected.index] -= 1;
+                                errors[rejected.index] = Some(rejected.reason);
+                            }
+                        }
+                        Err(e) if !outcome.required => {
+                            warn!("Failed to write batch of {} dead letters to secondary target {}: {}", chunk.len(), outcome.target, e);
+                        }
+                        Err(e) => {
+                            error!("Failed to write batch of {} dead letters: {}", chunk.len(), e);
+                            for error in &mut errors {
+                                error.get_or_insert_with(|| e.to_string());
+                            }
+                        }
+                    }
+                }
+                
+                for ((pending, count), error) in chunk.iter().zip(accepted).zip(errors) {
+                    if count >= write_quorum {
+                        stats.successful_inserts += 1;
+                        continue;
+                    }
+                    stats.failed_inserts += 1;
+                    let error = error.unwrap_or_else(|| "not written to enough targets".to_string());
+                    let entry = match &pending.entry {
+                        Some(entry) => DeadLetterEntry {
+                            line: Some(pending.point.to_line_protocol()),
+                            error,
+                            failed_at: chrono::Utc::now(),
+                            ..entry.clone()
+                        },
+                        None => DeadLetterEntry {
+                            source_file: path.to_string_lossy().to_string(),
+                            measurement: measurement.clone(),
+                            timestamp: String::new(),
+                            fields: Fields::default(),
+                            line: Some(pending.point.to_line_protocol()),
+                            error,
+                            target: None,
+                            failed_at: chrono::Utc::now(),
+                        },
+                    };
+                    dead_letters.push(entry);
+                }
+            }
+        }
+        
+        // Records that still fail go back to the dead-letter file for another attempt later
+        if let Some(writer) = dead_letter.filter(|_| !dead_letters.is_empty()) {
+            match writer.write(&dead_letters) {
+                Ok(()) => stats.dead_lettered += dead_letters.len(),
+                Err(e) => error!("Failed to write dead letters: {}", e),
+            }
+        }
+    }
+    
+    if let Err(e) = sink.finish() {
+        error!("Failed to finish output: {}", e);
+    }
+    stats.failovers = sink.failovers();
+    stats.throttled = sink.throttled();
+    stats.lines_written = sink.lines_written();
+    stats.kafka_messages_sent = sink.messages_sent();
+    Ok(stats)
+}
+
+// Read the records of a dead-letter (.json/.ndjson/.jsonl) or line protocol (.lp/.txt) file,
+// optionally gzipped; other files are ignored
+fn read_dead_letter_file(path: &Path, query_options: &QueryOptions) -> Result<Option<Vec<PendingLine>>> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let (name, gzipped) = match name.strip_suffix(".gz") {
+        Some(inner) => (inner.to_string(), true),
+        None => (name, false),
+    };
+    let extension = Path::new(&name).extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let json = matches!(extension.as_str(), "json" | "ndjson" | "jsonl");
+    if !json && !matches!(extension.as_str(), "lp" | "txt") {
+        return Ok(None);
+    }
+    
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut contents = String::new();
+    let read = if gzipped {
+        flate2::read::GzDecoder::new(file).read_to_string(&mut contents)
+    } else {
+        std::io::BufReader::new(file).read_to_string(&mut contents)
+    };
+    read.with_context(|| format!("Failed to read {}", path.display()))?;
+    
+    let mut pending = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !json {
+            match Point::from_line_protocol(line) {
+                Some(point) => pending.push(PendingLine { point, entry: None }),
+                None => warn!("Skipping invalid line protocol at {}:{}", path.display(), number + 1),
+            }
+            continue;
+        }
+        
+        let entry: DeadLetterEntry = serde_json::from_str(line)
+            .with_context(|| format!("Invalid dead letter at {}:{}", path.display(), number + 1))?;
+        // Prefer the line protocol that was sent; rebuild the point from the row when the record never got that far
+        let point = match &entry.line {
+            Some(line) => Point::from_line_protocol(line),
+            None => {
+                let record = DynamicRecord { timestamp: entry.timestamp.clone(), timestamp_nanos: None, fields: entry.fields.clone() };
+                Some(record.into_point(&entry.measurement, query_options)).filter(|point| point.validate().is_ok())
+            }
+        };
+        match point {
+            Some(point) => pending.push(PendingLine { point, entry: Some(entry) }),
+            None => warn!("Skipping dead letter at {}:{}: no valid point", path.display(), number + 1),
+        }
+    }
+    Ok(Some(pending))
+}
+
+// Gzip-compress a request body
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+// Client for the InfluxDB 2.x HTTP API, writing line protocol to /api/v2/write
+#[derive(Clone)]
+struct V2Client {
+    http: reqwest::Client,
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+}
+
+// Error body returned by the InfluxDB 2.x API
+#[derive(Debug, Deserialize)]
+struct V2ErrorResponse {
+    code: String,
+    message: String,
+    #[serde(default)]
+    line: Option<u64>,
+}
+
+impl V2Client {
+    fn from_config(config: &TargetConfig, http: reqwest::Client) -> Result<Self> {
+        let token = config.token.clone()
+            .ok_or_else(|| anyhow!("--token is required for the InfluxDB 2.x API ({})", config.url))?;
+        let org = config.org.clone()
+            .ok_or_else(|| anyhow!("--org is required for the InfluxDB 2.x API ({})", config.url))?;
+        
+        Ok(V2Client {
+            http,
+            url: config.url.trim_end_matches('/').to_string(),
+            org,
+            bucket: config.bucket.clone().or_else(|| config.db.clone()).unwrap_or_default(),
+            token,
+        })
+    }
+    
+    // Count points with a Flux query, see WriteTarget::count_points
+    async fn count_points(&self, measurement: &str, range: (i64, i64), source_file: Option<&str>) -> Result<usize> {
+        let string = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut filter = format!("r._measurement == {}", string(measurement));
+        if let Some(source_file) = source_file {
+            filter.push_str(&format!(" and r.source_file == {}", string(source_file)));
+        }
+        let flux = format!(
+            "from(bucket: {}) |> range(start: time(v: {}), stop: time(v: {})) |> filter(fn: (r) => {}) \
+             |> group(columns: [\"_field\"]) |> count() |> group() |> max()",
+            string(&self.bucket), range.0, range.1 + 1, filter
+        );
+        
+        let response = self.http.post(format!("{}/api/v2/query", self.url))
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "application/vnd.flux")
+            .header("Accept", "application/csv")
+            .query(&[("org", self.org.as_str())])
+            .body(flux)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Query failed: {}", describe_connection_error(&e)))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!("Query failed: {}", write_error(status, &body)));
+        }
+        
+        // The result is a small CSV table with the count in the _value column
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body.as_bytes());
+        let Some(column) = reader.headers()?.iter().position(|h| h == "_value") else {
+            return Ok(0);
+        };
+        let count = match reader.records().next() {
+            Some(row) => row?.get(column).and_then(|v| v.parse().ok()).unwrap_or(0),
+            None => 0,
+        };
+        Ok(count)
+    }
+    
+    // Timestamp of the latest point of a measurement, see WriteTarget::last_timestamp
+    async fn last_timestamp(&self, measurement: &str) -> Result<Option<i64>> {
+        let string = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let flux = format!(
+            "from(bucket: {}) |> range(start: 0) |> filter(fn: (r) => r._measurement == {}) \
+             |> keep(columns: [\"_time\"]) |> group() |> sort(columns: [\"_time\"], desc: true) |> limit(n: 1)",
+            string(&self.bucket), string(measurement)
+        );
+        
+        let response = self.http.post(format!("{}/api/v2/query", self.url))
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "application/vnd.flux")
+            .header("Accept", "application/csv")
+            .query(&[("org", self.org.as_str())])
+            .body(flux)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Query failed: {}", describe_connection_error(&e)))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!("Query failed: {}", write_error(status, &body)));
+        }
+        
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(body.as_bytes());
+        let Some(column) = reader.headers()?.iter().position(|h| h == "_time") else {
+            return Ok(None);
+        };
+        match reader.records().next() {
+            Some(row) => match row?.get(column) {
+                Some(time) => Ok(parse_timestamp(time)?.timestamp_nanos_opt()),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+    
+    // Check that the token is valid and the bucket exists
+    async fn check_connection(&self) -> Result<()> {
+        let response = self.http.get(format!("{}/api/v2/buckets", self.url))
+            .header("Authorization", format!("Token {}", self.token))
+            .query(&[("org", self.org.as_str()), ("name", self.bucket.as_str())])
+            .send()
+            .await
+            .map_err(|e| anyhow!("InfluxDB connectivity check failed: {}", describe_connection_error(&e)))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!("InfluxDB connectivity check failed: {}", write_error(status, &body)));
+        }
+        
+        let buckets: serde_json::Value = serde_json::from_str(&body)
+            .context("Failed to parse InfluxDB bucket list")?;
+        if buckets["buckets"].as_array().map_or(true, |b| b.is_empty()) {
+            return Err(anyhow!("Bucket {} does not exist in org {}", self.bucket, self.org));
+        }
+        
+        Ok(())
+    }
+    
+    // Send an authenticated GET request and parse the JSON response
+    async fn get_json(&self, path: &str, query: &[(&str, &str)]) -> Result<serde_json::Value> {
+        let response = self.http.get(format!("{}{}", self.url, path))
+            .header("Authorization", format!("Token {}", self.token))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Request to {} failed: {}", path, describe_connection_error(&e)))?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(anyhow!("Request to {} failed: {}", path, write_error(status, &body)));
+        }
+        serde_json::from_str(&body).with_context(|| format!("Failed to parse response from {}", path))
+    }
+    
+    // Create the bucket if it doesn't exist yet
+    async fn create_bucket(&self) -> Result<()> {
+        let buckets = self.get_json("/api/v2/buckets", &[("org", self.org.as_str()), ("name", self.bucket.as_str())]).await?;
+        if buckets["buckets"].as_array().map_or(false, |b| !b.is_empty()) {
+            info!("Bucket {} already exists", self.bucket);
+            return Ok(());
+        }
+        
+        // Buckets are created by organization ID, not name
+        let orgs = self.get_json("/api/v2/orgs", &[("org", self.org.as_str())]).await?;
+        let org_id = orgs["orgs"][0]["id"].as_str()
+            .ok_or_else(|| anyhow!("Organization {} does not exist", self.org))?;
+        
+        let payload = serde_json::json!({
+            "orgID": org_id,
+            "name": self.bucket,
+            "retentionRules": [],
+        });
+        let response = self.http.post(format!("{}/api/v2/buckets", self.url))
+            .header("Authorization", format!("Token {}", self.token))
+            .header("Content-Type", "application/json")
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to create bucket {}: {}", self.bucket, describe_connection_error(&e)))?;
+        
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if status.is_success() {
+            info!("Created bucket {} in org {}", self.bucket, self.org);
+            Ok(())
+        } else if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY && body.contains("already exists") {
+            // Another importer created it in the meantime
+            info!("Bucket {} already exists", self.bucket);
+            Ok(())
+        } else if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            Err(anyhow!(
+                "Bucket {} does not exist and the token is not allowed to create it; \
+                 create it manually or pass --no-create-db",
+                self.bucket
+            ))
+        } else {
+            Err(anyhow!("Failed to create bucket {}: {}", self.bucket, write_error(status, &body)))
+        }
+    }
+    
+}
+
+// Error body returned by the InfluxDB 1.x API
+#[derive(Debug, Deserialize)]
+struct V1ErrorResponse {
+    error: String,
+}
+
+// Connection settings for one InfluxDB server, from the command line or a --targets file:
+//
+//   [[target]]
+//   url = "http://old-influx:8086"
+//   db = "cursed_stats"
+//   username = "importer"
+//   password = "..."
+//
+//   [[target]]
+//   url = "http://new-influx:8086"
+//   api_version = "2"
+//   token = "..."
+//   org = "cursed"
+//   bucket = "cursed_stats"
+#[derive(Debug, Clone, Deserialize)]
+struct TargetConfig {
+    url: String,
+    // Database (1.x) and default bucket (2.x); falls back to --db-name
+    db: Option<String>,
+    retention_policy: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+    org: Option<String>,
+    bucket: Option<String>,
+    api_version: Option<ApiVersion>,
+    kind: Option<TargetKind>,
+    // VictoriaMetrics extra_label query parameters, as NAME=VALUE
+    #[serde(default)]
+    extra_labels: Vec<String>,
+    // Best-effort target whose failures don't fail records (see --secondary-url)
+    #[serde(default)]
+    secondary: bool,
+}
+
+impl TargetConfig {
+    // The write API: 1.x for VictoriaMetrics, else as configured or 2.x when a token is set
+    fn api_version(&self) -> ApiVersion {
+        match self.kind.unwrap_or_default() {
+            // The 1.x client is only kept for its endpoint; no InfluxDB API is used
+            TargetKind::Victoriametrics => ApiVersion::V1,
+            TargetKind::Influxdb => self.api_version.unwrap_or(if self.token.is_some() { ApiVersion::V2 } else { ApiVersion::V1 }),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TargetsFile {
+    #[serde(default)]
+    target: Vec<TargetConfig>,
+}
+
+// Targets from the --targets file, or one per --url sharing the other connection options.
+// Targets in the file fall back to --db-name, and 1.x targets to --retention-policy,
+// --username and --password, for settings they leave out
+fn load_targets(args: &ImportArgs) -> Result<Vec<TargetConfig>> {
+    let password = resolve_password(args)?;
+    if let Some(path) = &args.targets {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read targets file {}", path.display()))?;
+        let file: TargetsFile = toml::from_str(&contents)
+            .with_context(|| format!("Invalid targets file {}", path.display()))?;
+        if file.target.is_empty() {
+            return Err(anyhow!("Targets file {} doesn't list any [[target]]", path.display()));
+        }
+        return Ok(file.target.into_iter()
+            .map(|t| {
+                let v1 = t.api_version() == ApiVersion::V1;
+                TargetConfig {
+                    db: t.db.or_else(|| Some(args.db_name.clone())),
+                    retention_policy: t.retention_policy.or_else(|| args.retention_policy.clone().filter(|_| v1)),
+                    username: t.username.or_else(|| args.username.clone().filter(|_| v1)),
+                    password: t.password.or_else(|| password.clone().filter(|_| v1)),
+                    ..t
+                }
+            })
+            .collect());
+    }
+    
+    let urls = args.url.iter().map(|url| (url, false))
+        .chain(args.secondary_url.iter().map(|url| (url, true)));
+    Ok(urls.map(|(url, secondary)| TargetConfig {
+        url: url.clone(),
+        db: Some(args.db_name.clone()),
+        retention_policy: args.retention_policy.clone(),
+        username: args.username.clone(),
+        password: password.clone(),
+        token: args.token.clone(),
+        org: args.org.clone(),
+        bucket: args.bucket.clone(),
+        api_version: args.api_version,
+        kind: Some(args.target_kind),
+        extra_labels: args.extra_label.clone(),
+        secondary,
+    }).collect())
+}
+
+// A configured InfluxDB server: the client used for pre-flight checks and its batch writer
+struct InfluxTarget {
+    config: TargetConfig,
+    db_name: String,
+    api_version: ApiVersion,
+    kind: TargetKind,
+    // Failover endpoints from a comma-separated URL, in order of preference
+    endpoints: Vec<String>,
+    retention_policy: Option<String>,
+    target: WriteTarget,
+    writer: LineWriter,
+    http: reqwest::Client,
+    skip_preflight: bool,
+    create_database: bool,
+}
+
+impl InfluxTarget {
+    // Build the client and writer for the target's API version
+    fn connect(config: &TargetConfig, args: &ImportArgs, http: reqwest::Client) -> Result<Self> {
+        let db_name = config.db.clone().unwrap_or_else(|| args.db_name.clone());
+        let kind = config.kind.unwrap_or_default();
+        let api_version = config.api_version();
+        let endpoints: Vec<String> = config.url
+            .split(',')
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        if endpoints.is_empty() {
+            return Err(anyhow!("No InfluxDB URL given for target '{}'", config.url));
+        }
+        if endpoints.len() > 1 {
+            info!("Failover endpoints: {}", endpoints.join(", "));
+        }
+        
+        let target = Self::client(config, &db_name, api_version, &endpoints[0], &http)?;
+        let writer_http = http.clone();
+        let writer = match &target {
+            _ if kind == TargetKind::Victoriametrics => {
+                info!("Starting import from {} to VictoriaMetrics at {}", args.scan_dir.display(), config.url);
+                // Without databases, extra labels are the way to tell imports apart
+                let mut params = vec![("precision", "ns".to_string())];
+                params.extend(config.extra_labels.iter().map(|label| ("extra_label", label.clone())));
+                let auth_header = match (&config.token, &config.username) {
+                    (Some(token), _) => Some(format!("Bearer {}", token)),
+                    (None, Some(username)) => {
+                        let credentials = format!("{}:{}", username, config.password.as_deref().unwrap_or_default());
+                        Some(format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(credentials)))
+                    }
+                    (None, None) => None,
+                };
+                LineWriter::new(config, &endpoints, "/write", params, auth_header, args, writer_http)
+            }
+            WriteTarget::V1(_) => {
+                info!("Starting import from {} to database {}{} at {}", 
+                         args.scan_dir.display(), db_name, describe_retention_policy(&config.retention_policy), config.url);
+                
+                let mut params = vec![("db", db_name.clone()), ("precision", "ns".to_string())];
+                if let Some(rp) = &config.retention_policy {
+                    params.push(("rp", rp.clone()));
+                }
+                if let Some(username) = &config.username {
+                    params.push(("u", username.clone()));
+                    params.push(("p", config.password.clone().unwrap_or_default()));
+                }
+                LineWriter::new(config, &endpoints, "/write", params, None, args, writer_http)
+            }
+            WriteTarget::V2(client) => {
+                info!("Starting import from {} to bucket {} (org {}) at {}", 
+                         args.scan_dir.display(), client.bucket, client.org, config.url);
+                let params = vec![
+                    ("org", client.org.clone()),
+                    ("bucket", client.bucket.clone()),
+                    ("precision", "ns".to_string()),
+                ];
+                let auth_header = Some(format!("Token {}", client.token));
+                LineWriter::new(config, &endpoints, "/api/v2/write", params, auth_header, args, writer_http)
+            }
+        };
+        
+        Ok(InfluxTarget {
+            config: config.clone(),
+            db_name,
+            api_version,
+            kind,
+            endpoints,
+            retention_policy: config.retention_policy.clone(),
+            target,
+            writer,
+            http,
+            skip_preflight: args.skip_preflight,
+            create_database: !args.no_create_db,
+        })
+    }
+    
+    // Build the client used for pre-flight checks against one endpoint
+    fn client(config: &TargetConfig, db_name: &str, api_version: ApiVersion, url: &str, http: &reqwest::Client) -> Result<WriteTarget> {
+        match api_version {
+            ApiVersion::V1 => {
+                // Build the InfluxDB client, with credentials if configured
+                let mut client = Client::new(url, db_name)
+                    .with_http_client(http.clone());
+                if let Some(username) = &config.username {
+                    client = client.with_auth(username, config.password.as_deref().unwrap_or_default());
+                }
+                Ok(WriteTarget::V1(client))
+            }
+            ApiVersion::V2 => {
+                if config.retention_policy.is_some() {
+                    return Err(anyhow!("--retention-policy only applies to the InfluxDB 1.x API; 2.x writes go to --bucket"));
+                }
+                let mut client = V2Client::from_config(config, http.clone())?;
+                client.url = url.to_string();
+                if client.bucket.is_empty() {
+                    client.bucket = db_name.to_string();
+                }
+                Ok(WriteTarget::V2(client))
+            }
+        }
+    }
+    
+    // Point both the pre-flight client and the writer at another endpoint
+    fn use_endpoint(&mut self, index: usize) -> Result<()> {
+        warn!("Preferred endpoint {} is unavailable, starting on {}", self.endpoints[0], self.endpoints[index]);
+        self.target = Self::client(&self.config, &self.db_name, self.api_version, &self.endpoints[index], &self.http)?;
+        self.writer.active.store(index, Ordering::SeqCst);
+        self.writer.failovers.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+// Index of the first endpoint that answers /ping, warning about the ones that don't
+async fn first_reachable_endpoint(http: &reqwest::Client, endpoints: &[String], kind: TargetKind) -> Result<usize> {
+    let mut last_error = None;
+    for (index, endpoint) in endpoints.iter().enumerate() {
+        match ping_server(http, endpoint, kind.health_path()).await {
+            Ok(()) => return Ok(index),
+            Err(e) if index + 1 < endpoints.len() => warn!("{}", e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("No InfluxDB endpoints configured")))
+}
+
+// Map a failed API response onto the influxdb error type, keeping the server's detail
+fn write_error(status: reqwest::StatusCode, body: &str) -> influxdb::Error {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED => return influxdb::Error::AuthenticationError,
+        reqwest::StatusCode::FORBIDDEN => return influxdb::Error::AuthorizationError,
+        _ => {}
+    }
+    
+    let error = if let Ok(e) = serde_json::from_str::<V2ErrorResponse>(body) {
+        match e.line {
+            Some(line) => format!("{} (HTTP {}, line {}): {}", e.code, status.as_u16(), line, e.message),
+            None => format!("{} (HTTP {}): {}", e.code, status.as_u16(), e.message),
+        }
+    } else if let Ok(e) = serde_json::from_str::<V1ErrorResponse>(body) {
+        format!("HTTP {}: {}", status.as_u16(), e.error)
+    } else {
+        format!("HTTP {}: {}", status.as_u16(), body)
+    };
+    influxdb::Error::DatabaseError { error }
+}
+
+// Custom logger that dispatches to both console and file
+struct LogDispatcher {
+    console: pretty_env_logger::env_logger::Logger,
+    file: pretty_env_logger::env_logger::Logger,
+}
+
+impl log::Log for LogDispatcher {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.console.enabled(metadata) || self.file.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.console.log(record);
+        self.file.log(record);
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        self.file.flush();
+    }
+}
+
+// Options that control how input files are turned into records
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub normalize_keys: Option<KeyCase>,
+    // Columns holding JSON objects to flatten into prefixed fields
+    pub json_columns: std::collections::HashSet<String>,
+    pub validations: Vec<ColumnRule>,
+    // Computed fields (--field-math), added before validation
+    pub field_math: Vec<FieldMath>,
+    pub transform: Option<Arc<TransformScript>>,
+    // Columns classified by position rather than by name
+    pub timestamp_index: Option<usize>,
+    // Files start with data rather than a header row (--no-header)
+    pub no_header: bool,
+    pub tag_indices: Vec<usize>,
+    pub field_indices: Vec<usize>,
+    // Capacity of the buffer files are read through (--read-buffer-size)
+    pub read_buffer_size: Option<usize>,
+}
+
+impl ParseOptions {
+    pub fn from_args(args: &ImportArgs) -> Result<Self> {
+        let normalize = |c: &String| args.normalize_keys.map_or_else(|| c.clone(), |case| case.apply(c));
+        let transform = match &args.transform_script {
+            Some(path) => Some(Arc::new(TransformScript::load(path)?)),
+            None => None,
+        };
+        if let Some(index) = args.tag_indices.iter().find(|i| args.field_indices.contains(i)) {
+            return Err(anyhow!("Column {} is listed in both --tag-indices and --field-indices", index));
+        }
+        if let Some(index) = args.timestamp_index.filter(|i| args.tag_indices.contains(i) || args.field_indices.contains(i)) {
+            return Err(anyhow!("Column {} is the --timestamp-index and can't also be a tag or field", index));
+        }
+        Ok(ParseOptions {
+            normalize_keys: args.normalize_keys,
+            json_columns: args.json_columns.iter().map(normalize).collect(),
+            validations: args.validate.iter().map(|r| ColumnRule { column: normalize(&r.column), rule: r.rule.clone() }).collect(),
+            field_math: args.field_math.iter().map(|m| FieldMath { column: normalize(&m.column), expression: m.expression.clone() }).collect(),
+            transform,
+            timestamp_index: args.timestamp_index,
+            no_header: args.no_header,
+            tag_indices: args.tag_indices.clone(),
+            field_indices: args.field_indices.clone(),
+            read_buffer_size: args.read_buffer_size,
+        })
+    }
+    
+    // CSV reader over the input, treating the first row as data with --no-header
+    fn csv_reader<R: Read>(&self, input: R) -> Reader<R> {
+        csv::ReaderBuilder::new().has_headers(!self.no_header).from_reader(input)
+    }
+    
+    // Whether the column at `index` named `header` holds the record timestamp
+    fn is_timestamp(&self, index: usize, header: &str) -> bool {
+        match self.timestamp_index {
+            Some(timestamp_index) => index == timestamp_index,
+            None => header == "timestamp",
+        }
+    }
+    
+    // Types of the columns classified by --tag-indices/--field-indices, keyed by the
+    // file's (normalized) header names
+    fn positional_types(&self, path: &Path, headers: &[Option<Arc<str>>]) -> HashMap<String, ColumnType> {
+        let mut types = HashMap::new();
+        let indices = self.tag_indices.iter().map(|&i| (i, ColumnType::Tag))
+            .chain(self.field_indices.iter().map(|&i| (i, ColumnType::Float)));
+        for (index, column_type) in indices {
+            match headers.get(index) {
+                Some(Some(header)) => {
+                    types.insert(header.to_string(), column_type);
+                }
+                Some(None) => {}
+                None => warn!("Column index {} is out of range for {} ({} columns)", index, path.display(), headers.len()),
+            }
+        }
+        if self.timestamp_index.map_or(false, |i| i >= headers.len()) {
+            warn!("--timestamp-index is out of range for {} ({} columns), every record lacks a timestamp",
+                     path.display(), headers.len());
+        }
+        types
+    }
+    
+    // Run the parsed records of a file through the --transform-script, if any
+    fn transform(&self, path: &Path, records: Vec<DynamicRecord>) -> std::result::Result<Vec<DynamicRecord>, CursedStatsError> {
+        match &self.transform {
+            Some(script) => script.apply(path, records),
+            None => Ok(records),
+        }
+    }
+    
+    // Add the --field-math fields to a record, returning the number that couldn't be computed
+    fn compute_fields(&self, record: &mut DynamicRecord) -> usize {
+        let mut errors = 0;
+        for math in &self.field_math {
+            match math.eval(&record.fields) {
+                Some(value) => record.fields.insert(math.column.as_str(), value.to_string()),
+                None => errors += 1,
+            }
+        }
+        errors
+    }
+    
+    // Check a record against the --validate rules, warning about the first one it fails
+    fn validate(&self, path: &Path, record: &DynamicRecord) -> bool {
+        for ColumnRule { column, rule } in &self.validations {
+            let value = if column == "timestamp" { Some(&record.timestamp) } else { record.fields.get(column) };
+            let value = value.map_or("", String::as_str);
+            if !rule.check(value) {
+                warn!("Skipping record in {}: column '{}' fails {} (value '{}')", path.display(), column, rule, value);
+                return false;
+            }
+        }
+        true
+    }
+    
+    // Store a cell, expanding JSON objects in --json-columns into `<column>_<key>` fields
+    fn insert_field(&self, fields: &mut Fields, column: &Arc<str>, value: String) {
+        if self.json_columns.contains(&**column) {
+            if let Ok(serde_json::Value::Object(object)) = serde_json::from_str(&value) {
+                self.flatten_json(fields, column, object);
+                return;
+            }
+        }
+        // Non-object or invalid JSON keeps the raw string
+        fields.push(Arc::clone(column), value);
+    }
+    
+    fn flatten_json(&self, fields: &mut Fields, prefix: &str, object: serde_json::Map<String, serde_json::Value>) {
+        for (key, value) in object {
+            let key = format!("{}_{}", prefix, key);
+            let key = self.normalize_keys.map_or_else(|| key.clone(), |case| case.apply(&key));
+            match value {
+                serde_json::Value::Object(nested) => self.flatten_json(fields, &key, nested),
+                serde_json::Value::Null => {
+                    fields.insert(key, String::new());
+                }
+                serde_json::Value::String(s) => {
+                    fields.insert(key, s);
+                }
+                // Numbers and booleans keep their JSON spelling; arrays are stored as JSON text
+                other => {
+                    fields.insert(key, other.to_string());
+                }
+            }
+        }
+    }
+}
+
+// Records parsed from a CSV file along with per-file parse counters
+pub struct ParseOutput {
+    pub records: Vec<DynamicRecord>,
+    pub skipped_no_timestamp: usize,
+    pub validation_failures: usize,
+    // --field-math fields that couldn't be computed, one per expression and row
+    pub math_eval_errors: usize,
+    // Types of the columns classified by position, by the file's column names
+    pub column_types: HashMap<String, ColumnType>,
+    // No data rows at all: a zero-byte or header-only file
+    pub empty: bool,
+}
+
+// A point of the _import_runs audit log (--write-run-metadata); the finished point carries
+// the outcome of the run
+fn run_metadata_point(status: &str, scan_dir: &Path, stats: Option<&ImportStats>, clock: &Clock) -> Point {
+    let mut point = Point::new("_import_runs", clock.now().timestamp_nanos_opt());
+    point.tags.insert("host".to_string(), gethostname::gethostname().to_string_lossy().to_string());
+    point.tags.insert("importer_version".to_string(), env!("CARGO_PKG_VERSION").to_string());
+    point.fields.insert("status".to_string(), FieldValue::String(status.to_string()));
+    point.fields.insert("scan_dir".to_string(), FieldValue::String(scan_dir.to_string_lossy().to_string()));
+    if let Some(stats) = stats {
+        point.fields.insert("files_processed".to_string(), FieldValue::Integer(stats.files_processed as i64));
+        point.fields.insert("records_written".to_string(), FieldValue::Integer(stats.successful_inserts as i64));
+    }
+    point
+}
+
+// Failing to record the run is logged but doesn't fail the import
+async fn write_run_metadata(sink: &BatchSink, point: Point) {
+    for outcome in sink.write(None, vec![point]).await {
+        match outcome.result {
+            Ok(written) if written.rejected.is_empty() => {}
+            Ok(_) => warn!("Run metadata rejected by {}", outcome.target),
+            Err(e) => error!("Failed to write run metadata to {}: {}", outcome.target, e),
+        }
+    }
+}
+
+// Helper function to parse CSV files with dynamic columns
+pub fn parse_csv_dynamic(path: PathBuf, options: &ParseOptions) -> std::result::Result<ParseOutput, CursedStatsError> {
+    let file = File::open(&path).map_err(|e| CursedStatsError::csv(&path, e.into()))?;
+    let size = file.metadata().map_or(0, |m| m.len());
+    let started = std::time::Instant::now();
+    let output = match options.read_buffer_size {
+        Some(capacity) => parse_csv_reader(&path, options.csv_reader(std::io::BufReader::with_capacity(capacity, file)), options)?,
+        None => parse_csv_reader(&path, options.csv_reader(file), options)?,
+    };
+    let seconds = started.elapsed().as_secs_f64();
+    debug!("Read {} ({:.2} MB) in {:.3}s, {:.1} MB/s", path.display(), size as f64 / 1_000_000.0, seconds,
+              size as f64 / 1_000_000.0 / seconds.max(f64::EPSILON));
+    Ok(output)
+}
+
+// Parse CSV from any reader; the path only labels errors and warnings
+fn parse_csv_reader<R: Read>(
+    path: &Path,
+    mut reader: Reader<R>,
+    options: &ParseOptions,
+) -> std::result::Result<ParseOutput, CursedStatsError> {
+    let mut records = Vec::new();
+    let mut skipped_no_timestamp = 0;
+    let mut validation_failures = 0;
+    let mut math_eval_errors = 0;
+    let mut rows = 0;
+    
+    // Get headers first; a zero-byte file has an empty header row and no records. Without a
+    // header row this is the first record, which is still read as data
+    let headers = reader.headers().map_err(|e| CursedStatsError::csv(path, e))?.clone();
+    let headers = if options.no_header {
+        (0..headers.len()).map(|i| Some(Arc::from(format!("column_{}", i)))).collect()
+    } else {
+        normalize_headers(path, headers.iter(), options.normalize_keys)
+    };
+    let column_types = options.positional_types(path, &headers);
+    
+    // Process each record manually, reusing one row buffer
+    let mut csv_record = csv::StringRecord::new();
+    while reader.read_record(&mut csv_record).map_err(|e| CursedStatsError::csv(path, e))? {
+        rows += 1;
+        let mut record = DynamicRecord {
+            timestamp: String::new(),
+            timestamp_nanos: None,
+            fields: Fields::with_capacity(headers.len()),
+        };
+        
+        // Process each field
+        for (i, field) in csv_record.iter().enumerate() {
+            if let Some(Some(header)) = headers.get(i) {
+                if options.is_timestamp(i, header) {
+                    record.timestamp = field.to_string();
+                } else {
+                    options.insert_field(&mut record.fields, header, field.to_string());
+                }
+            }
+        }
+        
+        if record.timestamp.is_empty() {
+            debug!("Skipping record without timestamp");
+            skipped_no_timestamp += 1;
+            continue;
+        }
+        math_eval_errors += options.compute_fields(&mut record);
+        if !options.validate(path, &record) {
+            validation_failures += 1;
+        } else {
+            records.push(record);
+        }
+    }
+    
+    let records = options.transform(path, records)?;
+    Ok(ParseOutput { records, skipped_no_timestamp, validation_failures, math_eval_errors, column_types, empty: rows == 0 })
+}
+
+// Normalize the header row; columns whose normalized name collides with an
+// earlier column are dropped (None) so the first one wins. Without normalization a
+// repeated name keeps its last column, as a map of the row always did
+fn normalize_headers<'a>(
+    path: &Path,
+    headers: impl Iterator<Item = &'a str>,
+    normalize_keys: Option<KeyCase>,
+) -> Vec<Option<Arc<str>>> {
+    let Some(case) = normalize_keys else {
+        let mut headers: Vec<Option<Arc<str>>> = headers.map(|header| Some(Arc::from(header))).collect();
+        let mut last: HashMap<Arc<str>, usize> = HashMap::new();
+        for i in 0..headers.len() {
+            let Some(header) = headers[i].clone() else {
+                continue;
+            };
+            if let Some(earlier) = last.insert(header, i) {
+                headers[earlier] = None;
+            }
+        }
+        return headers;
+    };
+    let mut seen: HashMap<String, String> = HashMap::new();
+    headers
+        .map(|header| {
+            let key = case.apply(header);
+            if let Some(first) = seen.get(&key) {
+                warn!("Column '{}' in {} normalizes to '{}' like column '{}', keeping the first",
+                         header, path.display(), key, first);
+                return None;
+            }
+            seen.insert(key.clone(), header.to_string());
+            Some(Arc::from(key))
+        })
+        .collect()
+}
+
+// Parse an Arrow IPC / Feather v2 file into records. The "timestamp" column, or else
+// the first column of Arrow timestamp type, becomes the record timestamp with full
+// nanosecond precision; every other column is rendered as a string so the usual
+// field/tag inference applies
+pub fn parse_arrow(path: &Path, options: &ParseOptions) -> std::result::Result<ParseOutput, CursedStatsError> {
+    use arrow::array::Array;
+    use arrow::datatypes::DataType;
+    use arrow::util::display::array_value_to_string;
+    
+    let arrow_err = |source| CursedStatsError::ArrowParse { path: path.to_path_buf(), source };
+    let file = File::open(path).map_err(|e| arrow_err(e.into()))?;
+    let reader = arrow::ipc::reader::FileReader::try_new(file, None).map_err(arrow_err)?;
+    
+    let schema = reader.schema();
+    let headers = normalize_headers(path, schema.fields().iter().map(|f| f.name().as_str()), options.normalize_keys);
+    let timestamp_index = options.timestamp_index
+        .or_else(|| headers.iter().position(|h| h.as_deref() == Some("timestamp")))
+        .or_else(|| schema.fields().iter().position(|f| matches!(f.data_type(), DataType::Timestamp(_, _))));
+    let column_types = options.positional_types(path, &headers);
+    
+    let mut records = Vec::new();
+    let mut skipped_no_timestamp = 0;
+    let mut validation_failures = 0;
+    let mut math_eval_errors = 0;
+    let mut rows = 0;
+    for batch in reader {
+        let batch = batch.map_err(arrow_err)?;
+        rows += batch.num_rows();
+        
+        // Temporal columns give the nanoseconds directly, with the text only shown in
+        // messages; anything else is read as text and parsed like a CSV cell
+        let timestamps: Vec<(String, Option<i64>)> = match timestamp_index.map(|i| batch.column(i)) {
+            Some(column) if matches!(column.data_type(), DataType::Timestamp(_, _) | DataType::Date32 | DataType::Date64) => {
+                (0..batch.num_rows())
+                    .map(|row| match arrow_nanos(column, row) {
+                        Some(nanos) => (chrono::DateTime::from_timestamp_nanos(nanos).to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true), Some(nanos)),
+                        None => (String::new(), None),
+                    })
+                    .collect()
+            }
+            Some(column) => (0..batch.num_rows())
+                .map(|row| (if column.is_valid(row) { array_value_to_string(column, row).unwrap_or_default() } else { String::new() }, None))
+                .collect(),
+            None => vec![(String::new(), None); batch.num_rows()],
+        };
+        
+        for (row, (timestamp, timestamp_nanos)) in timestamps.into_iter().enumerate() {
+            if timestamp.is_empty() {
+                debug!("Skipping record without timestamp");
+                skipped_no_timestamp += 1;
+                continue;
+            }
+            let mut record = DynamicRecord { timestamp, timestamp_nanos, fields: Fields::with_capacity(headers.len()) };
+            for (i, column) in batch.columns().iter().enumerate() {
+                let Some(Some(header)) = headers.get(i).filter(|_| Some(i) != timestamp_index) else {
+                    continue;
+                };
+                // Nulls become empty cells, as in a CSV
+                let value = if column.is_valid(row) {
+                    array_value_to_string(column, row).map_err(arrow_err)?
+                } else {
+                    String::new()
+                };
+                options.insert_field(&mut record.fields, header, value);
+            }
+            math_eval_errors += options.compute_fields(&mut record);
+            if !options.validate(path, &record) {
+                validation_failures += 1;
+                continue;
+            }
+            records.push(record);
+        }
+    }
+    
+    let records = options.transform(path, records)?;
+    Ok(ParseOutput { records, skipped_no_timestamp, validation_failures, math_eval_errors, column_types, empty: rows == 0 })
+}
+
+// Nanoseconds since the epoch of a cell of an Arrow timestamp or date column, read from
+// its native value; None for nulls and times outside the range of nanosecond timestamps
+fn arrow_nanos(column: &dyn arrow::array::Array, row: usize) -> Option<i64> {
+    use arrow::array::{Array, AsArray};
+    use arrow::datatypes::{
+        DataType, Date32Type, Date64Type, TimeUnit, TimestampMicrosecondType, TimestampMillisecondType,
+        TimestampNanosecondType, TimestampSecondType,
+    };
+    
+    if column.is_null(row) {
+        return None;
+    }
+    match column.data_type() {
+        DataType::Timestamp(TimeUnit::Second, _) => column.as_primitive::<TimestampSecondType>().value(row).checked_mul(1_000_000_000),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => column.as_primitive::<TimestampMillisecondType>().value(row).checked_mul(1_000_000),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => column.as_primitive::<TimestampMicrosecondType>().value(row).checked_mul(1_000),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => Some(column.as_primitive::<TimestampNanosecondType>().value(row)),
+        DataType::Date32 => i64::from(column.as_primitive::<Date32Type>().value(row)).checked_mul(86_400_000_000_000),
+        DataType::Date64 => column.as_primitive::<Date64Type>().value(row).checked_mul(1_000_000),
+        _ => None,
+    }
+}
+
+// A Lua script with a `transform(row)` function applied to every parsed row
+#[derive(Debug)]
+pub struct TransformScript {
+    path: PathBuf,
+    source: String,
+}
+
+impl TransformScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read transform script {}", path.display()))?;
+        let script = TransformScript { path: path.to_path_buf(), source };
+        // Report syntax errors and a missing transform function before any file is parsed
+        script.function()?;
+        info!("Loaded transform script {}", path.display());
+        Ok(script)
+    }
+    
+    fn error(&self, e: mlua::Error) -> CursedStatsError {
+        CursedStatsError::Transform { path: self.path.clone(), message: e.to_string() }
+    }
+    
+    // A fresh interpreter with the script loaded; Lua states aren't shared between parser tasks
+    fn function(&self) -> std::result::Result<(mlua::Lua, mlua::RegistryKey), CursedStatsError> {
+        let lua = mlua::Lua::new();
+        lua.load(self.source.as_str())
+            .set_name(self.path.to_string_lossy())
+            .exec()
+            .map_err(|e| self.error(e))?;
+        let transform: mlua::Function = lua.globals().get("transform").map_err(|e| self.error(e))?;
+        let key = lua.create_registry_value(transform).map_err(|e| self.error(e))?;
+        Ok((lua, key))
+    }
+    
+    fn apply(&self, path: &Path, records: Vec<DynamicRecord>) -> std::result::Result<Vec<DynamicRecord>, CursedStatsError> {
+        let (lua, key) = self.function()?;
+        let transform: mlua::Function = lua.registry_value(&key).map_err(|e| self.error(e))?;
+        
+        let mut transformed = Vec::with_capacity(records.len());
+        for (index, record) in records.into_iter().enumerate() {
+            let row = lua.create_table().map_err(|e| self.error(e))?;
+            row.set("timestamp", record.timestamp.as_str()).map_err(|e| self.error(e))?;
+            for (column, value) in record.fields.iter() {
+                row.set(column, value.as_str()).map_err(|e| self.error(e))?;
+            }
+            
+            let skip = |reason: String| warn!("Skipping row {} of {}: {}", index + 1, path.display(), reason);
+            match transform.call::<_, mlua::Value>(row) {
+                Ok(mlua::Value::Table(table)) => match table_to_record(table, record.timestamp, record.timestamp_nanos) {
+                    Ok(record) => transformed.push(record),
+                    Err(reason) => skip(reason),
+                },
+                Ok(other) => skip(format!("transform returned {} instead of a table", other.type_name())),
+                Err(e) => skip(format!("transform failed: {}", e)),
+            }
+        }
+        Ok(transformed)
+    }
+}
+
+// Convert a table returned by a transform script back into a record; the original
+// timestamp is kept unless the table sets another one
+fn table_to_record(table: mlua::Table, timestamp: String, timestamp_nanos: Option<i64>) -> std::result::Result<DynamicRecord, String> {
+    let mut record = DynamicRecord { timestamp, timestamp_nanos, fields: Fields::default() };
+    for pair in table.pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = pair.map_err(|e| e.to_string())?;
+        let key = match key {
+            mlua::Value::String(s) => s.to_str().map_err(|e| e.to_string())?.to_string(),
+            other => return Err(format!("column names must be strings, got {}", other.type_name())),
+        };
+        let value = match value {
+            mlua::Value::String(s) => s.to_str().map_err(|e| e.to_string())?.to_string(),
+            mlua::Value::Integer(i) => i.to_string(),
+            mlua::Value::Number(n) => n.to_string(),
+            mlua::Value::Boolean(b) => b.to_string(),
+            other => return Err(format!("column {} holds a {}", key, other.type_name())),
+        };
+        // Keys of a Lua table are distinct
+        if key == "timestamp" {
+            if value != record.timestamp {
+                record.timestamp_nanos = None;
+            }
+            record.timestamp = value;
+        } else {
+            record.fields.push(Arc::from(key), value);
+        }
+    }
+    Ok(record)
+}
+
+// Format an age as a short human readable string, e.g. "3 days"
+fn format_age(age: chrono::Duration) -> String {
+    let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+    if age.num_days() > 0 {
+        plural(age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        plural(age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        plural(age.num_minutes(), "minute")
+    } else {
+        plural(age.num_seconds().max(0), "second")
+    }
+}
+
+// Hash algorithm used to detect changed files (--hash-algo)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum HashAlgo {
+    Sha256,
+    // The default: hashing at several GB/s per core it keeps up with reading the file from
+    // disk, and unlike the 64-bit xxHash variants it can't collide by chance or by design,
+    // so a changed file is never taken for an unchanged one
+    #[default]
+    Blake3,
+    Xxhash64,
+    Xxh3,
+}
+
+impl HashAlgo {
+    // Algorithm of cache entries written before the algorithm was recorded
+    fn legacy() -> Self {
+        HashAlgo::Sha256
+    }
+    
+    fn hasher(&self) -> &'static dyn FileHasher {
+        match self {
+            HashAlgo::Sha256 => &Sha256Hasher,
+            HashAlgo::Blake3 => &Blake3Hasher,
+            HashAlgo::Xxhash64 => &Xxhash64Hasher,
+            HashAlgo::Xxh3 => &Xxh3Hasher,
+        }
+    }
+}
+
+// Size of the chunks files are hashed in, so memory use doesn't grow with the file
+const HASH_CHUNK_SIZE: usize = 1 << 20;
+
+// Content hash of an input file, as a hex string
+trait FileHasher: Sync {
+    fn hash(&self, path: &Path) -> std::result::Result<String, CursedStatsError> {
+        self.hash_file(path, None)
+    }
+    
+    // Hash a file, reading it through a buffer of the given size (--read-buffer-size) when
+    // that is larger than a chunk
+    fn hash_file(&self, path: &Path, buffer_size: Option<usize>) -> std::result::Result<String, CursedStatsError> {
+        let hash_io = |cause| CursedStatsError::HashIo { path: path.to_path_buf(), cause };
+        let mut file = File::open(path).map_err(hash_io)?;
+        let hashed = match buffer_size {
+            // A smaller buffer would only add a copy, as reads of a whole chunk bypass it
+            Some(capacity) if capacity > HASH_CHUNK_SIZE => self.hash_reader(&mut std::io::BufReader::with_capacity(capacity, file)),
+            _ => self.hash_reader(&mut file),
+        };
+        hashed.map_err(hash_io)
+    }
+    
+    fn digest(&self, mut bytes: &[u8]) -> String {
+        self.hash_reader(&mut bytes).expect("reading from memory can't fail")
+    }
+    
+    // Hash everything the reader yields, a chunk at a time
+    fn hash_reader(&self, reader: &mut dyn Read) -> std::io::Result<String>;
+}
+
+// Feed the reader to `update` in chunks of HASH_CHUNK_SIZE
+fn for_each_chunk(reader: &mut dyn Read, mut update: impl FnMut(&[u8])) -> std::io::Result<()> {
+    let mut buffer = vec![0; HASH_CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => update(&buffer[..read]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+struct Sha256Hasher;
+
+impl FileHasher for Sha256Hasher {
+    fn hash_reader(&self, reader: &mut dyn Read) -> std::io::Result<String> {
+        let mut hasher = Sha256::new();
+        for_each_chunk(reader, |chunk| hasher.update(chunk))?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+struct Blake3Hasher;
+
+impl FileHasher for Blake3Hasher {
+    fn hash_reader(&self, reader: &mut dyn Read) -> std::io::Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        for_each_chunk(reader, |chunk| {
+            hasher.update(chunk);
+        })?;
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+struct Xxhash64Hasher;
+
+impl FileHasher for Xxhash64Hasher {
+    fn hash_reader(&self, reader: &mut dyn Read) -> std::io::Result<String> {
+        let mut hasher = xxhash_rust::xxh64::Xxh64::new(0);
+        for_each_chunk(reader, |chunk| hasher.update(chunk))?;
+        Ok(format!("{:016x}", hasher.digest()))
+    }
+}
+
+struct Xxh3Hasher;
+
+impl FileHasher for Xxh3Hasher {
+    fn hash_reader(&self, reader: &mut dyn Read) -> std::io::Result<String> {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for_each_chunk(reader, |chunk| hasher.update(chunk))?;
+        Ok(format!("{:016x}", hasher.digest()))
+    }
+}
+
+// Parse CSV read from standard input as one file, cached under `stdin:<hash>`
+async fn import_stdin(
+    input: Vec<u8>,
+    options: &ParseOptions,
+    cache: &Mutex<HashMap<String, FileMetadata>>,
+    cache_destination: Option<&str>,
+    force: bool,
+    stats: &Mutex<ImportStats>,
+    record_tx: mpsc::Sender<ParsedFile>,
+) {
+    // Keyed by the SHA-256 of the data whatever the --hash-algo, so the key stays stable
+    let hash = Sha256Hasher.digest(&input);
+    let key = format!("stdin:{}", hash);
+    stats.lock().unwrap().files_found += 1;
+    
+    // Skip data that was already imported, unless forced
+    if !force {
+        let cached = cache.lock().unwrap().get(&cache_key(&key, cache_destination)).filter(|m| !m.in_progress).map(|m| m.last_processed);
+        if let Some(last_processed) = cached {
+            let age = chrono::Utc::now() - last_processed;
+            info!("Skipping standard input: identical data imported {} ago", format_age(age));
+            let mut stats = stats.lock().unwrap();
+            stats.files_skipped += 1;
+            stats.cache.record_skip(age);
+            return;
+        }
+    }
+    
+    stats.lock().unwrap().files_processed += 1;
+    let path = PathBuf::from(&key);
+    let started = tokio::time::Instant::now();
+    match parse_csv_reader(&path, options.csv_reader(input.as_slice()), options) {
+        Ok(ParseOutput { records, skipped_no_timestamp, validation_failures, math_eval_errors, column_types, empty }) => {
+            {
+                let mut stats = stats.lock().unwrap();
+                stats.records_processed += records.len();
+                stats.bytes_read += input.len();
+                stats.records_skipped_no_timestamp += skipped_no_timestamp;
+                stats.validation_failures += validation_failures;
+                stats.math_eval_errors += math_eval_errors;
+                stats.empty_files += empty as usize;
+            }
+            if empty {
+                info!("Standard input is empty (no data rows)");
+            } else {
+                info!("Parsed {} records from standard input", records.len());
+            }
+            let parsed = ParsedFile { records, path, hash, hash_algo: HashAlgo::Sha256, signature: None, metadata: SidecarMetadata::default(), column_types, started };
+            if let Err(e) = record_tx.send(parsed).await {
+                error!("Failed to send records: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to parse CSV from standard input: {}", e),
+    }
+}
+
+// The --notify-webhook payload sent once the import has finished
+fn completion_event(stats: &ImportStats, completed: bool, dry_run: bool) -> serde_json::Value {
+    let mut event = stats.to_json(dry_run);
+    event["status"] = stats.status(completed).into();
+    event
+}
+
+// POST an event to the --notify-webhook URL; failures are only logged so that they
+// never change the outcome of the import
+async fn notify_webhook(url: &str, event: serde_json::Value) {
+    let status = event["status"].as_str().unwrap_or_default().to_string();
+    let response = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map(|client| client.post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(event.to_string()));
+    let sent = match response {
+        Ok(request) => request.send().await.and_then(|r| r.error_for_status()),
+        Err(e) => Err(e),
+    };
+    match sent {
+        Ok(_) => info!("Sent {} event to {}", status, url),
+        Err(e) => warn!("Failed to send {} event to {}: {}", status, url, e),
+    }
+}
+
+// Write the final statistics to the --summary-json file
+fn write_summary_json(path: &Path, stats: &ImportStats, dry_run: bool) {
+    let written = serde_json::to_vec_pretty(&stats.to_json(dry_run))
+        .map_err(std::io::Error::from)
+        .and_then(|json| std::fs::write(path, json));
+    match written {
+        Ok(()) => info!("Wrote statistics to {}", path.display()),
+        Err(e) => error!("Failed to write statistics to {}: {}", path.display(), e),
+    }
+}
+
+fn cache_mismatch(migrate_cache: bool, clear_cache_on_version_mismatch: bool) -> CacheMismatch {
+    if migrate_cache {
+        CacheMismatch::Migrate
+    } else if clear_cache_on_version_mismatch {
+        CacheMismatch::Clear
+    } else {
+        CacheMismatch::Fail
+    }
+}
+
+fn run_cache_command(file: &CacheFileArgs, command: &CacheCommand) -> Result<i32> {
+    if matches!(command, CacheCommand::Migrate { .. }) && file.cache_backend != CacheBackend::Json {
+        anyhow::bail!("cache migrate reads a JSON cache; leave out --cache-backend");
+    }
+    let cache_store = open_cache_store(file.cache_backend, file.cache_file.as_deref(), file.cache_shard_count);
+    let mut cache = cache_store.load(cache_mismatch(file.migrate_cache, file.clear_cache_on_version_mismatch))?;
+    canonicalize_cache_keys(&mut cache);
+    match command {
+        CacheCommand::List { filter, format } => {
+            let mut entries: Vec<CacheListing> = cache.iter()
+                .filter(|(_, metadata)| filter.as_ref().map_or(true, |pattern| pattern.matches(&metadata.path)))
+                .map(|(key, metadata)| CacheListing::new(key, metadata, None))
+                .collect();
+            entries.sort_by(|a, b| (&a.path, &a.key).cmp(&(&b.path, &b.key)));
+            print_cache_listing(&entries, *format)?;
+        }
+        CacheCommand::Show { path, format } => {
+            // Every entry of the file, one per destination with --cache-key destination
+            let path = canonical_path(path);
+            let mut entries: Vec<CacheListing> = cache.iter()
+                .filter(|(_, metadata)| metadata.path == path)
+                .map(|(key, metadata)| CacheListing::new(key, metadata, Some(disk_state(metadata))))
+                .collect();
+            if entries.is_empty() {
+                anyhow::bail!("{} has no entry in {}", path, cache_store.location().display());
+            }
+            entries.sort_by(|a, b| a.key.cmp(&b.key));
+            print_cache_listing(&entries, *format)?;
+        }
+        CacheCommand::Invalidate { targets } => {
+            let removed = invalidate_entries(&mut cache, targets)?;
+            for key in &removed {
+                println!("Invalidated {}", key);
+            }
+            if !removed.is_empty() {
+                cache_store.save_map(&cache)?;
+            }
+            println!("Removed {} cache entries, {} left", removed.len(), cache.len());
+        }
+        CacheCommand::Prune { older_than } => {
+            let pruned = prune_cache(&mut cache, &cache_base_dir(cache_store.as_ref()), *older_than, chrono::Utc::now());
+            if pruned > 0 {
+                cache_store.save_map(&cache)?;
+            }
+            println!("Removed {} cache entries, {} left", pruned, cache.len());
+        }
+        CacheCommand::Migrate { to } => {
+            sqlite_cache::SqliteCache::new(to).save_map(&cache)?;
+            println!("Copied {} cache entries from {} to {}", cache.len(), cache_store.location().display(), to.display());
+        }
+    }
+    Ok(0)
+}
+
+// A cache entry as printed by `cache list` and `cache show`
+#[derive(Debug, Serialize)]
+struct CacheListing {
+    path: String,
+    key: String,
+    last_processed: String,
+    records: usize,
+    successful: usize,
+    failed: usize,
+    in_progress: bool,
+    hash_algo: HashAlgo,
+    hash_prefix: String,
+    // Only for `cache show`: matches, changed or missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_disk: Option<&'static str>,
+}
+
+impl CacheListing {
+    fn new(key: &str, metadata: &FileMetadata, on_disk: Option<&'static str>) -> Self {
+        CacheListing {
+            path: metadata.path.clone(),
+            key: key.to_string(),
+            last_processed: metadata.last_processed.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            records: metadata.records_count,
+            successful: metadata.successful_count,
+            failed: metadata.failed_count,
+            in_progress: metadata.in_progress,
+            hash_algo: metadata.hash_algo,
+            hash_prefix: metadata.hash.chars().take(12).collect(),
+            on_disk,
+        }
+    }
+}
+
+// Whether the file of a cache entry still has the hash it was imported with
+fn disk_state(metadata: &FileMetadata) -> &'static str {
+    match metadata.hash_algo.hasher().hash(Path::new(&metadata.path)) {
+        Ok(hash) if hash == metadata.hash => "matches",
+        Ok(_) => "changed",
+        Err(_) => "missing",
+    }
+}
+
+fn print_cache_listing(entries: &[CacheListing], format: ListFormat) -> Result<()> {
+    match format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(entries)?),
+        ListFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for entry in entries {
+                writer.serialize(entry)?;
+            }
+            writer.flush()?;
+        }
+        ListFormat::Table => {
+            let width = entries.iter().map(|e| e.path.chars().count()).max().unwrap_or(0).max(4);
+            let on_disk = entries.iter().any(|e| e.on_disk.is_some());
+            println!("{:<width$}  {:<20}  {:>9}  {:>9}  {:>6}  {:<12}{}", "PATH", "LAST PROCESSED", "RECORDS",
+                     "WRITTEN", "FAILED", "HASH", if on_disk { "  ON DISK" } else { "" }, width = width);
+            for e in entries {
+                let hash = if e.in_progress { "in progress".to_string() } else { e.hash_prefix.clone() };
+                println!("{:<width$}  {:<20}  {:>9}  {:>9}  {:>6}  {:<12}{}", e.path, e.last_processed, e.records,
+                         e.successful, e.failed, hash, e.on_disk.map_or(String::new(), |s| format!("  {}", s)), width = width);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Remove the entries of the given files, and of the files matching the arguments that aren't
+// existing paths as globs, returning the removed keys sorted
+fn invalidate_entries(cache: &mut HashMap<String, FileMetadata>, targets: &[String]) -> Result<Vec<String>> {
+    let mut paths = HashSet::new();
+    let mut patterns = Vec::new();
+    for target in targets {
+        if Path::new(target).exists() {
+            paths.insert(canonical_path(Path::new(target)));
+        } else {
+            patterns.push(glob::Pattern::new(target).with_context(|| format!("Invalid glob '{}'", target))?);
+        }
+    }
+    let mut removed = Vec::new();
+    cache.retain(|key, metadata| {
+        let matched = paths.contains(&metadata.path) || patterns.iter().any(|pattern| pattern.matches(&metadata.path));
+        if matched {
+            removed.push(key.clone());
+        }
+        !matched
+    });
+    removed.sort();
+    Ok(removed)
+}
+
+// Remove entries of files that no longer exist, and with a threshold those last imported
+// longer ago than it. Relative paths, from caches written before keys were canonicalized,
+// are looked up next to the cache (`base`) rather than in the working directory. Data read
+// from standard input has no file and only expires by age
+fn prune_cache(cache: &mut HashMap<String, FileMetadata>, base: &Path, older_than: Option<chrono::Duration>, now: chrono::DateTime<chrono::Utc>) -> usize {
+    let before = cache.len();
+    cache.retain(|key, metadata| {
+        if older_than.is_some_and(|age| now - metadata.last_processed > age) {
+            debug!("Pruning cache entry {}: last imported {} ago", key, format_age(now - metadata.last_processed));
+            return false;
+        }
+        if metadata.path.starts_with("stdin:") || base.join(&metadata.path).exists() {
+            return true;
+        }
+        debug!("Pruning cache entry {}: {} no longer exists", key, metadata.path);
+        false
+    });
+    before - cache.len()
+}
+
+// Directory holding the cache, which relative entry paths are resolved against
+fn cache_base_dir(cache_store: &dyn CacheStore) -> PathBuf {
+    let location = cache_store.location();
+    let base = location.parent().unwrap_or(location);
+    std::fs::canonicalize(base).unwrap_or_else(|_| base.to_path_buf())
+}
+
+// Parse an age such as 90d, 12h, 30m or 45s; ages too large for a Duration are rejected
+fn parse_age(s: &str) -> Result<chrono::Duration, String> {
+    let s = s.trim();
+    let invalid = || format!("invalid age '{}', expected a number followed by d, h, m or s", s);
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?);
+    let number = number.parse::<i64>().map_err(|_| invalid())?;
+    let age = match unit {
+        "d" => chrono::Duration::try_days(number),
+        "h" => chrono::Duration::try_hours(number),
+        "m" => chrono::Duration::try_minutes(number),
+        "s" => chrono::Duration::try_seconds(number),
+        _ => return Err(invalid()),
+    };
+    age.ok_or_else(|| format!("invalid age '{}': too large", s))
+}
+
+// Where the cache is kept between runs. The entries are worked on in memory: a store loads
+// them all at startup and writes changed ones back, and is safe to save from several writer
+// tasks at once
+trait CacheStore: Send + Sync {
+    // The cache file or database, for messages
+    fn location(&self) -> &Path;
+    
+    fn load(&self, on_mismatch: CacheMismatch) -> std::result::Result<HashMap<String, FileMetadata>, CursedStatsError>;
+    
+    // Write back the entries under these keys, e.g. after a file was imported; keys no longer
+    // in the cache are removed from the store
+    fn save_entries(&self, cache: &Mutex<HashMap<String, FileMetadata>>, keys: &[String]) -> std::result::Result<(), CursedStatsError>;
+    
+    // Write back anything save_entries left unsaved, once the import completes
+    fn flush(&self, cache: &Mutex<HashMap<String, FileMetadata>>) -> std::result::Result<(), CursedStatsError>;
+    
+    // Replace the stored entries with a cache nothing else is writing, e.g. at startup or
+    // from a cache command
+    fn save_map(&self, cache: &HashMap<String, FileMetadata>) -> std::result::Result<(), CursedStatsError>;
+}
+
+// Open the cache at --cache-file, or at the default file of the backend
+fn open_cache_store(backend: CacheBackend, cache_file: Option<&Path>, shards: u32) -> Arc<dyn CacheStore> {
+    let path = cache_file.unwrap_or(backend.default_file());
+    match backend {
+        CacheBackend::Json => Arc::new(CacheFiles::new(path, shards)),
+        CacheBackend::Sqlite => {
+            if shards > 1 {
+                warn!("Ignoring --cache-shard-count: it only applies to the JSON cache");
+            }
+            Arc::new(sqlite_cache::SqliteCache::new(path))
+        }
+    }
+}
+
+// The file or files the cache is stored in. With --cache-shard-count n > 1 each entry lives in
+// <cache-file>.shard_<i>.json, i chosen by the FxHash of its file path, and every shard is
+// loaded and saved on its own
+#[derive(Debug, Clone)]
+struct CacheFiles {
+    path: PathBuf,
+    shards: usize,
+    // Copy of the entries of each shard, so a shard is gathered and written without holding
+    // the cache lock. Held while the shard is written, and always taken before the cache itself
+    shard_entries: Arc<Vec<Mutex<HashMap<String, FileMetadata>>>>,
+    // Whether the copies hold every entry of the cache, i.e. after a load or a full save
+    in_sync: Arc<AtomicBool>,
+    // Set when cache files of another shard count were loaded, so the next save rewrites
+    // every shard and removes them
+    relayout: Arc<AtomicBool>,
+}
+
+impl CacheFiles {
+    fn new(path: &Path, shards: u32) -> Self {
+        let shards = shards.max(1) as usize;
+        CacheFiles {
+            path: path.to_path_buf(),
+            shards,
+            shard_entries: Arc::new((0..shards).map(|_| Mutex::new(HashMap::new())).collect()),
+            in_sync: Arc::new(AtomicBool::new(false)),
+            relayout: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    
+    fn shard_path(&self, shard: usize) -> PathBuf {
+        PathBuf::from(format!("{}.shard_{}.json", self.path.display(), shard))
+    }
+    
+    // The file a shard is saved to; a single shard is the cache file itself
+    fn shard_file(&self, shard: usize) -> PathBuf {
+        if self.shards == 1 {
+            self.path.clone()
+        } else {
+            self.shard_path(shard)
+        }
+    }
+    
+    fn shard_of(&self, path: &str) -> usize {
+        use std::hash::Hasher;
+        let mut hasher = rustc_hash::FxHasher::default();
+        hasher.write(path.as_bytes());
+        (hasher.finish() % self.shards as u64) as usize
+    }
+    
+    // Cache files of the current layout, followed by any left by another shard count: the
+    // single file when sharded, and every shard file present
+    fn existing_files(&self) -> Vec<(PathBuf, bool)> {
+        let mut files = vec![(self.path.clone(), self.shards == 1)];
+        let mut shard = 0;
+        while shard < self.shards || self.shard_path(shard).exists() {
+            files.push((self.shard_path(shard), self.shards > 1 && shard < self.shards));
+            shard += 1;
+        }
+        files.into_iter().filter(|(path, _)| path.exists()).collect()
+    }
+    
+    fn lock_shards(&self) -> Vec<std::sync::MutexGuard<'_, HashMap<String, FileMetadata>>> {
+        self.shard_entries.iter().map(|entries| entries.lock().unwrap_or_else(std::sync::PoisonError::into_inner)).collect()
+    }
+    
+    // Replace the copies of every shard with the entries of the cache, then write them all and
+    // remove the files of other layouts
+    fn save_all(
+        &self,
+        shards: &mut [std::sync::MutexGuard<'_, HashMap<String, FileMetadata>>],
+        cache: &HashMap<String, FileMetadata>,
+    ) -> std::result::Result<(), CursedStatsError> {
+        for entries in shards.iter_mut() {
+            entries.clear();
+        }
+        for (key, metadata) in cache {
+            shards[self.shard_of(&metadata.path)].insert(key.clone(), metadata.clone());
+        }
+        self.in_sync.store(true, Ordering::SeqCst);
+        for (shard, entries) in shards.iter().enumerate() {
+            save_cache(&self.shard_file(shard), entries)?;
+        }
+        for (path, current) in self.existing_files() {
+            if !current {
+                std::fs::remove_file(&path).map_err(|cause| CursedStatsError::CacheIo { path, cause })?;
+            }
+        }
+        self.relayout.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl CacheStore for CacheFiles {
+    fn location(&self) -> &Path {
+        &self.path
+    }
+    
+    // Load every cache file into one map, whatever shard count wrote it: a single-file cache
+    // is split up when sharding, and shards are merged when the count is lowered or sharding
+    // is turned off. The files are rewritten in the current layout on the next save
+    fn load(&self, on_mismatch: CacheMismatch) -> std::result::Result<HashMap<String, FileMetadata>, CursedStatsError> {
+        let mut cache: HashMap<String, FileMetadata> = HashMap::new();
+        let mut other_layout = Vec::new();
+        for (path, current) in self.existing_files() {
+            for (key, metadata) in load_cache(&path, on_mismatch)? {
+                // An entry found twice after an interrupted re-shard keeps its latest import
+                match cache.get(&key) {
+                    Some(existing) if existing.last_processed >= metadata.last_processed => {}
+                    _ => {
+                        cache.insert(key, metadata);
+                    }
+                }
+            }
+            if !current {
+                other_layout.push(path.display().to_string());
+            }
+        }
+        if !other_layout.is_empty() {
+            info!("Merging cache files {} into {} shard(s)", other_layout.join(", "), self.shards);
+            self.relayout.store(true, Ordering::SeqCst);
+        }
+        
+        let mut shards = self.lock_shards();
+        for entries in shards.iter_mut() {
+            entries.clear();
+        }
+        for (key, metadata) in &cache {
+            shards[self.shard_of(&metadata.path)].insert(key.clone(), metadata.clone());
+        }
+        self.in_sync.store(true, Ordering::SeqCst);
+        Ok(cache)
+    }
+    
+    // Save the shards holding these entries, copying only those entries from the cache. An
+    // entry that's gone, e.g. the placeholder of a new file that failed, may still be in any
+    // shard, so then every shard is saved
+    fn save_entries(&self, cache: &Mutex<HashMap<String, FileMetadata>>, keys: &[String]) -> std::result::Result<(), CursedStatsError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if !self.in_sync.load(Ordering::SeqCst) || self.relayout.load(Ordering::SeqCst) {
+            return self.flush(cache);
+        }
+        let shards: Option<BTreeSet<usize>> = {
+            let cache = cache.lock().unwrap();
+            keys.iter().map(|key| cache.get(key).map(|metadata| self.shard_of(&metadata.path))).collect()
+        };
+        let shards: Vec<usize> = match shards {
+            Some(shards) => shards.into_iter().collect(),
+            None => (0..self.shards).collect(),
+        };
+        
+        // In shard order, like every other writer
+        let mut locked: Vec<_> = shards.iter()
+            .map(|&shard| (shard, self.shard_entries[shard].lock().unwrap_or_else(std::sync::PoisonError::into_inner)))
+            .collect();
+        {
+            let cache = cache.lock().unwrap();
+            for key in keys {
+                for (_, entries) in locked.iter_mut() {
+                    entries.remove(key);
+                }
+                if let Some(metadata) = cache.get(key) {
+                    let shard = self.shard_of(&metadata.path);
+                    if let Some((_, entries)) = locked.iter_mut().find(|(locked_shard, _)| *locked_shard == shard) {
+                        entries.insert(key.clone(), metadata.clone());
+                    }
+                }
+            }
+        }
+        for (shard, entries) in &locked {
+            save_cache(&self.shard_file(*shard), entries)?;
+        }
+        Ok(())
+    }
+    
+    // Save every shard from the whole cache
+    fn flush(&self, cache: &Mutex<HashMap<String, FileMetadata>>) -> std::result::Result<(), CursedStatsError> {
+        let mut shards = self.lock_shards();
+        let cache = cache.lock().unwrap().clone();
+        self.save_all(&mut shards, &cache)
+    }
+    
+    fn save_map(&self, cache: &HashMap<String, FileMetadata>) -> std::result::Result<(), CursedStatsError> {
+        let mut shards = self.lock_shards();
+        self.save_all(&mut shards, cache)
+    }
+}
+
+// Load cache from file
+fn load_cache(path: &PathBuf, on_mismatch: CacheMismatch) -> std::result::Result<HashMap<String, FileMetadata>, CursedStatsError> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    
+    let cache_io = |cause| CursedStatsError::CacheIo { path: path.clone(), cause };
+    let file = File::open(path).map_err(cache_io)?;
+    let value: serde_json::Value = match serde_json::from_reader(std::io::BufReader::new(file)) {
+        Ok(value) => value,
+        Err(e) => return set_aside_corrupt_cache(path, e),
+    };
+    
+    // Caches written before versioning are a bare map of entries; their fields are
+    // all still readable, so they load as the current version
+    let (found, written_by, entries) = if value.get("schema_version").is_some() {
+        let cache: CacheFile<serde_json::Value> = match serde_json::from_value(value) {
+            Ok(cache) => cache,
+            Err(e) => return set_aside_corrupt_cache(path, e),
+        };
+        (cache.schema_version, cache.importer_version, cache.entries)
+    } else {
+        (CACHE_SCHEMA_VERSION, "unknown".to_string(), value)
+    };
+    if !check_cache_version(path, found, written_by, on_mismatch)? {
+        return Ok(HashMap::new());
+    }
+    
+    serde_json::from_value(entries).or_else(|e| set_aside_corrupt_cache(path, e))
+}
+
+// Decide what to do with a cache written with schema version `found`: false when its entries
+// are to be dropped, an error when it can't be used
+fn check_cache_version(path: &Path, found: u32, written_by: String, on_mismatch: CacheMismatch) -> std::result::Result<bool, CursedStatsError> {
+    if found == CACHE_SCHEMA_VERSION {
+        return Ok(true);
+    }
+    match on_mismatch {
+        CacheMismatch::Fail => {
+            Err(CursedStatsError::CacheVersion { path: path.to_path_buf(), found, expected: CACHE_SCHEMA_VERSION, written_by })
+        }
+        CacheMismatch::Clear => {
+            warn!("Cache {} has schema version {} (importer {}), expected {}; starting with an empty cache",
+                     path.display(), found, written_by, CACHE_SCHEMA_VERSION);
+            Ok(false)
+        }
+        CacheMismatch::Migrate => {
+            warn!("Migrating cache {} from schema version {} (importer {}) to {}",
+                     path.display(), found, written_by, CACHE_SCHEMA_VERSION);
+            Ok(true)
+        }
+    }
+}
+
+// Move a cache that isn't valid JSON, e.g. one cut short by a crash, to
+// <path>.corrupt-<timestamp> and start with an empty cache
+fn set_aside_corrupt_cache(path: &Path, cause: serde_json::Error) -> std::result::Result<HashMap<String, FileMetadata>, CursedStatsError> {
+    let backup = PathBuf::from(format!("{}.corrupt-{}", path.display(), chrono::Utc::now().format("%Y%m%dT%H%M%SZ")));
+    std::fs::rename(path, &backup).map_err(|cause| CursedStatsError::CacheIo { path: path.to_path_buf(), cause })?;
+    warn!("Cache {} is corrupt ({}); moved it to {} and starting with an empty cache", path.display(), cause, backup.display());
+    Ok(HashMap::new())
+}
+
+// Parse a positive number of bytes, optionally with a binary K, M or G suffix such as "64K"
+fn parse_byte_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    match number.parse::<usize>().ok().and_then(|n| n.checked_mul(multiplier)) {
+        Some(bytes) if bytes > 0 => Ok(bytes),
+        _ => Err(format!("invalid size '{}', expected a positive number of bytes such as 65536, 64K or 4M", s)),
+    }
+}
+
+// Parse a non-negative number of hours such as "1" or "0.5", up to a century so that the
+// value always converts to a Duration
+fn parse_hours(s: &str) -> Result<f64, String> {
+    const MAX_HOURS: f64 = 100.0 * 365.25 * 24.0;
+    match s.trim().parse::<f64>() {
+        Ok(hours) if hours.is_finite() && (0.0..=MAX_HOURS).contains(&hours) => Ok(hours),
+        _ => Err(format!("invalid number of hours '{}', expected a number from 0 to {}", s, MAX_HOURS)),
+    }
+}
+
+fn hours(hours: f64) -> std::time::Duration {
+    std::time::Duration::from_secs_f64(hours * 3600.0)
+}
+
+// Time since a file was last modified; zero for modification times in the future
+fn file_age(path: &Path) -> Option<std::time::Duration> {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    Some(modified.elapsed().unwrap_or_default())
+}
+
+// A file on its way from the scanner to the parser
+#[derive(Debug, PartialEq)]
+struct QueuedFile {
+    path: PathBuf,
+    // Hash under the current --hash-algo, with the size and mtime read before it, when the
+    // scanner computed it for the cache check
+    hashed: Option<(String, Option<FileSignature>)>,
+}
+
+// How long the scanner waits on a full file queue before warning that the parser stage is stalled
+const QUEUE_STALL_WARNING: std::time::Duration = std::time::Duration::from_secs(60);
+
+// Hand a file to the parser stage. A full queue is waited out, warning while the parser
+// stage makes no progress; returns false only once the parser stage has stopped, as
+// nothing more can be queued then
+async fn queue_file(file_tx: &mpsc::Sender<QueuedFile>, mut file: QueuedFile, stats: &Mutex<ImportStats>) -> bool {
+    loop {
+        match file_tx.send_timeout(file, QUEUE_STALL_WARNING).await {
+            Ok(()) => return true,
+            Err(mpsc::error::SendTimeoutError::Timeout(returned)) => {
+                warn!("The parser stage has not accepted {} for {:?}, still waiting", returned.path.display(), QUEUE_STALL_WARNING);
+                file = returned;
+            }
+            Err(mpsc::error::SendTimeoutError::Closed(returned)) => {
+                error!("Failed to queue {}: the parser stage has stopped", returned.path.display());
+                stats.lock().unwrap().files_send_errors += 1;
+                return false;
+            }
+        }
+    }
+}
+
+// Mark a file as queued for import, adding a placeholder entry for new files
+fn mark_in_progress(cache: &mut HashMap<String, FileMetadata>, key: &str, path: &str) {
+    cache.entry(key.to_string())
+        .and_modify(|m| m.in_progress = true)
+        .or_insert_with(|| FileMetadata {
+            path: path.to_string(),
+            hash: String::new(),
+            last_processed: chrono::Utc::now(),
+            records_count: 0,
+            successful_count: 0,
+            failed_count: 0,
+            in_progress: true,
+            hash_algo: HashAlgo::default(),
+            size: None,
+            mtime: None,
+            max_imported_timestamp: None,
+        });
+}
+
+// Upgrade the entry of an unchanged file in place: record its current size and mtime so
+// later runs skip it without hashing, and store its hash under the current --hash-algo
+// when given one
+fn refresh_entry(cache: &Mutex<HashMap<String, FileMetadata>>, key: &str, path: &Path, signature: Option<FileSignature>, rehash: Option<HashAlgo>) {
+    let rehashed = rehash.and_then(|hash_algo| match hash_algo.hasher().hash(path) {
+        Ok(hash) => Some((hash, hash_algo)),
+        Err(e) => {
+            warn!("Failed to re-hash {}: {}", path.display(), e);
+            None
+        }
+    });
+    if let Some(metadata) = cache.lock().unwrap().get_mut(key) {
+        if let Some((hash, hash_algo)) = rehashed {
+            debug!("Re-hashed {} with {:?}", path.display(), hash_algo);
+            metadata.hash = hash;
+            metadata.hash_algo = hash_algo;
+        }
+        metadata.size = signature.map(|(size, _)| size);
+        metadata.mtime = signature.map(|(_, mtime)| mtime);
+    }
+}
+
+// Clear the in-progress marker of a file that wasn't cached, dropping placeholder entries
+fn clear_in_progress(cache: &mut HashMap<String, FileMetadata>, path: &str) {
+    if cache.get(path).map_or(false, |m| m.hash.is_empty()) {
+        cache.remove(path);
+    } else if let Some(metadata) = cache.get_mut(path) {
+        metadata.in_progress = false;
+    }
+}
+
+// Move an imported file to <dest>/<path relative to the scan directory>, falling back
+// to copy and delete when the destination is on another filesystem
+fn archive_file(path: &Path, scan_dir: &Path, dest: &Path) -> Result<PathBuf> {
+    let relative = match path.strip_prefix(scan_dir) {
+        Ok(relative) => relative,
+        Err(_) => Path::new(path.file_name().context("File has no name")?),
+    };
+    let archived = dest.join(relative);
+    if let Some(parent) = archived.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    if std::fs::rename(path, &archived).is_err() {
+        std::fs::copy(path, &archived)
+            .with_context(|| format!("Failed to copy to {}", archived.display()))?;
+        std::fs::remove_file(path)?;
+    }
+    Ok(archived)
+}
+
+// Save cache to file
+fn save_cache(path: &PathBuf, cache: &HashMap<String, FileMetadata>) -> std::result::Result<(), CursedStatsError> {
+    let cache_io = |cause| CursedStatsError::CacheIo { path: path.clone(), cause };
+    let cache = CacheFile {
+        schema_version: CACHE_SCHEMA_VERSION,
+        importer_version: env!("CARGO_PKG_VERSION").to_string(),
+        entries: cache,
+    };
+    
+    // Write a temporary file next to the cache and rename it over the cache, so a crash
+    // mid-write leaves the previous cache intact instead of a truncated one
+    let file_name = path.file_name().map_or_else(|| "cache".into(), |name| name.to_string_lossy());
+    let temp = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    let written = File::create(&temp).and_then(|file| {
+        let mut writer = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, &cache)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| std::fs::rename(&temp, path)) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(cache_io(e));
+    }
+    
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb::Query;
+    
+    const TIMESTAMP: &str = "2024-01-15T10:30:00Z";
+    const NANOS: i64 = 1_705_314_600_000_000_000;
+    
+    fn record(timestamp: &str, fields: &[(&str, &str)]) -> DynamicRecord {
+        DynamicRecord {
+            timestamp: timestamp.to_string(),
+            timestamp_nanos: None,
+            fields: fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+    
+    fn line_protocol(record: DynamicRecord, options: &QueryOptions) -> String {
+        record.into_query_with("sensors", options).build().unwrap().get()
+    }
+    
+    fn fixed_time() -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+    
+    #[test]
+    fn numeric_values_become_float_fields() {
+        let query = record(TIMESTAMP, &[("temperature", "21.5"), ("count", "42"), ("offset", "-3e2")]).into_query("sensors");
+        assert_eq!(
+            query.build().unwrap().get(),
+            format!("sensors count=42,offset=-300,temperature=21.5 {}", NANOS),
+        );
+    }
+    
+    #[test]
+    fn non_numeric_values_become_tags() {
+        let line = line_protocol(record(TIMESTAMP, &[("location", "lab 1"), ("value", "1.5")]), &QueryOptions::default());
+        assert_eq!(line, format!("sensors,location=lab\\ 1 value=1.5 {}", NANOS));
+    }
+    
+    #[test]
+    fn empty_values_become_empty_tags() {
+        let line = line_protocol(record(TIMESTAMP, &[("note", ""), ("value", "2")]), &QueryOptions::default());
+        assert_eq!(line, format!("sensors,note= value=2 {}", NANOS));
+    }
+    
+    #[test]
+    fn tag_values_are_normalized() {
+        let fields = [("os", " Linux "), ("site", "Lab"), ("value", "2")];
+        let options = QueryOptions {
+            types: HashMap::from([("site".to_string(), ColumnType::Tag)]),
+            tag_case: Some(TagCase::Lower),
+            tag_trim: true,
+            ..QueryOptions::default()
+        };
+        let line = line_protocol(record(TIMESTAMP, &fields), &options);
+        assert_eq!(line, format!("sensors,os=linux,site=lab value=2 {}", NANOS));
+        
+        let options = QueryOptions { tag_case: Some(TagCase::Upper), ..QueryOptions::default() };
+        let line = line_protocol(record(TIMESTAMP, &fields), &options);
+        assert_eq!(line, format!("sensors,os=\\ LINUX\\ ,site=LAB value=2 {}", NANOS));
+    }
+    
+    #[test]
+    fn nan_tokens_are_missing_values() {
+        let options = QueryOptions {
+            types: HashMap::from([("count".to_string(), ColumnType::Int), ("site".to_string(), ColumnType::Tag)]),
+            nan_tokens: vec!["N/A".to_string(), "-".to_string()],
+            ..QueryOptions::default()
+        };
+        let line = line_protocol(record(TIMESTAMP, &[("cpu", "N/A"), ("count", " - "), ("site", "-"), ("mem", "2")]), &options);
+        assert_eq!(line, format!("sensors,site=- mem=2 {}", NANOS));
+        
+        // Without tokens the placeholder becomes a tag
+        let line = line_protocol(record(TIMESTAMP, &[("cpu", "N/A"), ("mem", "2")]), &QueryOptions::default());
+        assert_eq!(line, format!("sensors,cpu=N/A mem=2 {}", NANOS));
+    }
+    
+    #[test]
+    fn non_finite_floats_are_left_out() {
+        let options = QueryOptions {
+            types: HashMap::from([("temp".to_string(), ColumnType::Float)]),
+            ..QueryOptions::default()
+        };
+        let mut point = record(TIMESTAMP, &[("cpu", "NaN"), ("temp", "-inf"), ("load", "infinity"), ("mem", "2")]).into_point("sensors", &options);
+        assert_eq!(point.remove_non_finite_fields(), 3);
+        assert_eq!(point.to_line_protocol(), format!("sensors mem=2 {}", NANOS));
+        assert_eq!(point.remove_non_finite_fields(), 0);
+    }
+    
+    #[test]
+    fn null_sentinels_leave_out_fields_and_tags() {
+        let options = QueryOptions {
+            types: HashMap::from([("site".to_string(), ColumnType::Tag)]),
+            null_sentinels: vec!["-999.0".to_string(), "9999".to_string(), "N/A".to_string()],
+            ..QueryOptions::default()
+        };
+        let fields = [("temp", "-999.0"), ("rain", "9999"), ("site", "N/A"), ("wind", "-999"), ("mem", "2")];
+        let line = line_protocol(record(TIMESTAMP, &fields), &options);
+        assert_eq!(line, format!("sensors mem=2,wind=-999 {}", NANOS));
+    }
+    
+    #[test]
+    fn run_metadata_points_record_the_outcome() {
+        let clock = Clock(fixed_time);
+        let started = run_metadata_point("started", Path::new("/data"), None, &clock);
+        assert_eq!(started.measurement, "_import_runs");
+        assert_eq!(started.timestamp, fixed_time().timestamp_nanos_opt());
+        assert_eq!(started.tags["importer_version"], env!("CARGO_PKG_VERSION"));
+        assert!(started.tags.contains_key("host"));
+        assert_eq!(started.fields["scan_dir"], FieldValue::String("/data".to_string()));
+        assert!(!started.fields.contains_key("files_processed"));
+        
+        let stats = ImportStats { files_processed: 3, successful_inserts: 42, ..Default::default() };
+        let finished = run_metadata_point("finished", Path::new("/data"), Some(&stats), &clock);
+        assert_eq!(finished.fields["status"], FieldValue::String("finished".to_string()));
+        assert_eq!(finished.fields["files_processed"], FieldValue::Integer(3));
+        assert_eq!(finished.fields["records_written"], FieldValue::Integer(42));
+    }
+    
+    #[test]
+    fn timestamps_are_rounded_to_the_granularity() {
+        let fields = [("value", "2")];
+        for (granularity, nanos) in [
+            (TimeGranularity::Ns, NANOS + 600_400_500),
+            (TimeGranularity::Us, NANOS + 600_401_000),
+            (TimeGranularity::Ms, NANOS + 600_000_000),
+            (TimeGranularity::S, NANOS + 1_000_000_000),
+        ] {
+            let options = QueryOptions { time_granularity: granularity, ..QueryOptions::default() };
+            let line = line_protocol(record("2024-01-15T10:30:00.6004005Z", &fields), &options);
+            assert_eq!(line, format!("sensors value=2 {}", nanos), "{:?}", granularity);
+        }
+        assert_eq!(TimeGranularity::S.round(-1_500_000_000), -1_000_000_000);
+    }
+    
+    #[test]
+    fn records_without_numeric_values_have_no_fields() {
+        let query = record(TIMESTAMP, &[("note", ""), ("location", "lab")]).into_query("sensors");
+        assert!(query.build().is_err());
+    }
+    
+    #[test]
+    fn boolean_values_are_tags_unless_declared() {
+        let fields = [("enabled", "true"), ("value", "1")];
+        let line = line_protocol(record(TIMESTAMP, &fields), &QueryOptions::default());
+        assert_eq!(line, format!("sensors,enabled=true value=1 {}", NANOS));
+        
+        let options = QueryOptions {
+            types: HashMap::from([("enabled".to_string(), ColumnType::Bool)]),
+            ..QueryOptions::default()
+        };
+        let line = line_protocol(record(TIMESTAMP, &fields), &options);
+        assert_eq!(line, format!("sensors enabled=true,value=1 {}", NANOS));
+    }
+    
+    #[test]
+    fn declared_types_that_do_not_parse_are_skipped() {
+        let options = QueryOptions {
+            types: HashMap::from([("count".to_string(), ColumnType::Int)]),
+            ..QueryOptions::default()
+        };
+        let line = line_protocol(record(TIMESTAMP, &[("count", "many"), ("value", "1")]), &options);
+        assert_eq!(line, format!("sensors value=1 {}", NANOS));
+    }
+    
+    #[test]
+    fn timestamps_are_parsed_as_rfc3339() {
+        let line = line_protocol(record("2024-01-15T12:30:00+02:00", &[("value", "1")]), &QueryOptions::default());
+        assert_eq!(line, format!("sensors value=1 {}", NANOS));
+    }
+    
+    #[test]
+    fn validation_rules_parse_and_check_values() {
+        let range = parse_validation_rule("cpu:range:-10:100").unwrap();
+        assert_eq!(range.column, "cpu");
+        assert!(range.rule.check("42.5"));
+        assert!(range.rule.check("-10"));
+        assert!(!range.rule.check("100.1"));
+        assert!(!range.rule.check("high"));
+        assert!(range.rule.check(""));
+        
+        let regex = parse_validation_rule("host:regex:^web-[0-9]+$").unwrap();
+        assert!(regex.rule.check("web-12"));
+        assert!(!regex.rule.check("db-1"));
+        
+        let one_of = parse_validation_rule("state:oneof:up|down").unwrap();
+        assert!(one_of.rule.check("down"));
+        assert!(!one_of.rule.check("unknown"));
+        
+        let non_empty = parse_validation_rule("name:nonempty").unwrap();
+        assert!(!non_empty.rule.check(""));
+        assert_eq!(non_empty.rule.to_string(), "nonempty");
+        
+        assert!(parse_validation_rule("cpu:range:100:0").is_err());
+        assert!(parse_validation_rule("cpu:between:0:1").is_err());
+        assert!(parse_validation_rule("cpu").is_err());
+    }
+    
+    #[test]
+    fn records_failing_validation_are_skipped() {
+        let options = ParseOptions {
+            validations: vec![parse_validation_rule("cpu:range:0:100").unwrap()],
+            ..ParseOptions::default()
+        };
+        let csv = "timestamp,cpu\n2024-01-15T10:30:00Z,50\n2024-01-15T10:31:00Z,150\n";
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        assert_eq!(output.records.len(), 1);
+        assert_eq!(output.validation_failures, 1);
+    }
+    
+    #[test]
+    fn field_math_adds_computed_fields() {
+        let options = ParseOptions {
+            field_math: vec![
+                parse_field_math("efficiency = output / input").unwrap(),
+                parse_field_math("delta_ms=end_ms - start_ms").unwrap(),
+            ],
+            ..ParseOptions::default()
+        };
+        let csv = "timestamp,output,input,start_ms,end_ms\n\
+                   2024-01-15T10:30:00Z,3,4,100,250\n\
+                   2024-01-15T10:31:00Z,x,4,100,\n";
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        assert_eq!(output.records[0].fields.get("efficiency").unwrap(), "0.75");
+        assert_eq!(output.records[0].fields.get("delta_ms").unwrap(), "150");
+        // A non-numeric or empty variable omits the field
+        assert!(!output.records[1].fields.contains_key("efficiency"));
+        assert!(!output.records[1].fields.contains_key("delta_ms"));
+        assert_eq!(output.math_eval_errors, 2);
+        
+        assert!(parse_field_math("efficiency").is_err());
+        assert!(parse_field_math("efficiency=(output / input").is_err());
+    }
+    
+    #[test]
+    fn columns_are_classified_by_position() {
+        let options = ParseOptions {
+            timestamp_index: Some(1),
+            tag_indices: vec![2],
+            field_indices: vec![0],
+            ..ParseOptions::default()
+        };
+        let csv = "reading,when,site\n1.5,2024-01-15T10:30:00Z,42\n";
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        assert_eq!(output.records[0].timestamp, TIMESTAMP);
+        assert_eq!(output.column_types.get("site"), Some(&ColumnType::Tag));
+        assert_eq!(output.column_types.get("reading"), Some(&ColumnType::Float));
+        
+        let options = QueryOptions { types: output.column_types, ..QueryOptions::default() };
+        let line = line_protocol(output.records.into_iter().next().unwrap(), &options);
+        assert_eq!(line, format!("sensors,site=42 reading=1.5 {}", NANOS));
+    }
+    
+    #[test]
+    fn headerless_files_name_columns_by_position() {
+        let csv = format!("{},web-1,1,1\n2024-01-15T10:31:00Z,web-2,2,2\n", TIMESTAMP);
+        let options = ParseOptions { no_header: true, timestamp_index: Some(0), ..ParseOptions::default() };
+        let output = parse_csv_reader(Path::new("test.csv"), options.csv_reader(csv.as_bytes()), &options).unwrap();
+        assert_eq!(output.records.len(), 2);
+        assert_eq!(output.records[0].timestamp, TIMESTAMP);
+        // Equal values in the first row are data, not duplicate columns
+        let first: Vec<(&str, &String)> = output.records[0].fields.iter().collect();
+        assert_eq!(first, [("column_1", &"web-1".to_string()), ("column_2", &"1".to_string()), ("column_3", &"1".to_string())]);
+        
+        // Read with a header row, the first row would be lost
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &ParseOptions { timestamp_index: Some(0), ..ParseOptions::default() }).unwrap();
+        assert_eq!(output.records.len(), 1);
+    }
+    
+    #[test]
+    fn arrow_timestamps_keep_their_native_precision() {
+        use arrow::array::{Float64Array, StringArray, TimestampMicrosecondArray};
+        use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+        use arrow::record_batch::RecordBatch;
+        
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::Timestamp(TimeUnit::Microsecond, None), true),
+            Field::new("host", DataType::Utf8, false),
+            Field::new("cpu", DataType::Float64, false),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(TimestampMicrosecondArray::from(vec![Some(NANOS / 1000 + 1), None])),
+            Arc::new(StringArray::from(vec!["web-1", "web-2"])),
+            Arc::new(Float64Array::from(vec![0.5, 1.5])),
+        ]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.arrow");
+        let mut writer = arrow::ipc::writer::FileWriter::try_new(File::create(&path).unwrap(), &schema).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+        
+        let output = InputFormat::Arrow.parse(&path, &ParseOptions::default()).unwrap();
+        assert_eq!(output.records.len(), 1);
+        assert_eq!(output.skipped_no_timestamp, 1);
+        // The microsecond is kept exactly, not rounded through a formatted time
+        assert_eq!(output.records[0].timestamp_nanos, Some(NANOS + 1000));
+        assert_eq!(output.records[0].fields.get("host"), Some(&"web-1".to_string()));
+        
+        // --infer-schema reads the file as Arrow rather than CSV
+        let schema_file = dir.path().join("schema.types");
+        infer_schema(dir.path(), InputFormat::Arrow, &["arrow".to_string()], 10, &ParseOptions::default(), &[], &schema_file).unwrap();
+        let contents = std::fs::read_to_string(&schema_file).unwrap();
+        assert!(contents.contains("from 1 file(s)"));
+        assert!(contents.contains("cpu=float\n"));
+        assert!(contents.contains("host=tag\n"));
+    }
+    
+    #[test]
+    fn schema_inference_treats_nan_tokens_as_missing_readings() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.csv"), format!("timestamp,cpu,fan,host\n{},1.5,N/A,web-1\n{},N/A,N/A,web-2\n", TIMESTAMP, TIMESTAMP)).unwrap();
+        let schema_file = dir.path().join("schema.types");
+        let infer = |nan_tokens: &[String]| {
+            infer_schema(dir.path(), InputFormat::Csv, &["csv".to_string()], 10, &ParseOptions::default(), nan_tokens, &schema_file).unwrap();
+            std::fs::read_to_string(&schema_file).unwrap()
+        };
+        
+        let contents = infer(&[]);
+        assert!(contents.contains("cpu=tag\n") && contents.contains("fan=tag\n"));
+        let contents = infer(&["N/A".to_string()]);
+        assert!(contents.contains("cpu=float\n"));
+        assert!(contents.contains("fan=float\n"));
+        assert!(contents.contains("host=tag\n"));
+    }
+    
+    #[test]
+    fn fields_share_header_names_and_behave_like_a_map() {
+        let csv = "timestamp,host,cpu\n2024-01-15T10:30:00Z,a,1\n2024-01-15T10:31:00Z,b,2\n";
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &ParseOptions::default()).unwrap();
+        let [first, second] = [&output.records[0].fields, &output.records[1].fields].map(|fields| fields.0.clone());
+        assert!(Arc::ptr_eq(&first[0].0, &second[0].0));
+        
+        let mut fields = output.records[0].fields.clone();
+        fields.insert("cpu", "3".to_string());
+        fields.insert("mem".to_string(), "4".to_string());
+        assert_eq!(fields.iter().collect::<Vec<_>>(), [("host", &"a".to_string()), ("cpu", &"3".to_string()), ("mem", &"4".to_string())]);
+        
+        let json = serde_json::to_string(&fields).unwrap();
+        assert_eq!(json, r#"{"host":"a","cpu":"3","mem":"4"}"#);
+        let parsed: Fields = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.get("mem"), Some(&"4".to_string()));
+        assert_eq!(parsed.len(), 3);
+        
+        let collected: Fields = [("a", "1"), ("b", "2"), ("a", "3")].into_iter().map(|(k, v)| (k, v.to_string())).collect();
+        assert_eq!(collected.iter().collect::<Vec<_>>(), [("a", &"3".to_string()), ("b", &"2".to_string())]);
+    }
+    
+    #[test]
+    fn normalized_column_keys_keep_the_first_of_a_collision() {
+        let csv = format!("timestamp,CPU Load,cpuLoad,Mem-Free\n{},1,2,3\n", TIMESTAMP);
+        let options = ParseOptions { normalize_keys: Some(KeyCase::Snake), ..ParseOptions::default() };
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        let record = output.records.into_iter().next().unwrap();
+        assert_eq!(record.fields.iter().collect::<Vec<_>>(), [("cpu_load", &"1".to_string()), ("mem_free", &"3".to_string())]);
+        assert_eq!(line_protocol(record, &QueryOptions::default()), format!("sensors cpu_load=1,mem_free=3 {}", NANOS));
+        
+        // Lowercasing keeps the camelCase column apart
+        let options = ParseOptions { normalize_keys: Some(KeyCase::Lower), ..ParseOptions::default() };
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &options).unwrap();
+        let keys: Vec<&str> = output.records[0].fields.iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["cpu load", "cpuload", "mem-free"]);
+        
+        // Without normalization nothing is deduplicated by key case, and a repeated name keeps
+        // its last value
+        let csv = format!("timestamp,cpu,CPU,cpu\n{},1,2,3\n", TIMESTAMP);
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &ParseOptions::default()).unwrap();
+        assert_eq!(output.records[0].fields.iter().collect::<Vec<_>>(), [("CPU", &"2".to_string()), ("cpu", &"3".to_string())]);
+    }
+    
+    #[test]
+    fn empty_and_header_only_files_have_no_records() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in [("empty.csv", ""), ("header.csv", "timestamp,value\n")] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            let output = parse_csv_dynamic(path, &ParseOptions::default()).unwrap();
+            assert!(output.empty && output.records.is_empty(), "{}", name);
+        }
+        let csv = "timestamp,value\n,1\n";
+        let output = parse_csv_reader(Path::new("test.csv"), Reader::from_reader(csv.as_bytes()), &ParseOptions::default()).unwrap();
+        assert!(!output.empty && output.records.is_empty());
+    }
+    
+    #[test]
+    fn duplicate_records_are_collapsed_by_key() {
+        let records = vec![
+            record(TIMESTAMP, &[("host", "a"), ("value", "1")]),
+            record(TIMESTAMP, &[("host", "a"), ("value", "2")]),
+            record(TIMESTAMP, &[("host", "b"), ("value", "1")]),
+            record("2024-01-15T10:31:00Z", &[("host", "a"), ("value", "1")]),
+        ];
+        let (kept, dropped) = dedupe_records(records.clone(), &["host".to_string()]);
+        assert_eq!(dropped, 1);
+        assert_eq!(kept.iter().map(|r| r.fields.get("value").unwrap().as_str()).collect::<Vec<_>>(), ["1", "1", "1"]);
+        
+        // Whole records only collapse when every column matches
+        let (kept, dropped) = dedupe_records(records.into_iter().chain([record(TIMESTAMP, &[("host", "b"), ("value", "1")])]).collect(), &[]);
+        assert_eq!((kept.len(), dropped), (4, 1));
+        
+        // A missing key column is not the same as an empty one
+        let (kept, dropped) = dedupe_records(vec![record(TIMESTAMP, &[("value", "1")]), record(TIMESTAMP, &[("host", ""), ("value", "1")])], &["host".to_string()]);
+        assert_eq!((kept.len(), dropped), (2, 0));
+        assert!(parse_dedupe_column("").is_err());
+    }
+    
+    #[test]
+    fn measurement_templates_use_and_remove_columns() {
+        let template = parse_measurement_template("{region}_{ service }.v1").unwrap();
+        let mut fields: Fields = [("region", "eu"), ("service", "api"), ("latency", "3")]
+            .into_iter().map(|(k, v)| (k, v.to_string())).collect();
+        assert_eq!(template.render(&mut fields), ("eu_api.v1".to_string(), vec![]));
+        assert_eq!(fields.iter().map(|(k, _)| k).collect::<Vec<_>>(), ["latency"]);
+        
+        let mut fields: Fields = [("region", "eu"), ("service", " ")].into_iter().map(|(k, v)| (k, v.to_string())).collect();
+        assert_eq!(template.render(&mut fields), ("eu_{service}.v1".to_string(), vec!["service"]));
+        assert!(fields.contains_key("service"));
+        
+        for invalid in ["plain", "{}", "{region", "region}", "{a{b}}"] {
+            assert!(parse_measurement_template(invalid).is_err(), "{}", invalid);
+        }
+    }
+    
+    #[test]
+    fn column_groups_split_records_by_measurement() {
+        let groups = vec![
+            parse_column_group("cpu:cpu_user,cpu_sys,host").unwrap(),
+            parse_column_group("mem:mem_used,host").unwrap(),
+        ];
+        let fields = [("cpu_user", "1"), ("cpu_sys", "2"), ("mem_used", "3"), ("host", "web"), ("disk", "4")];
+        let split = split_record(record(TIMESTAMP, &fields), "system", &groups);
+        let lines: Vec<String> = split.into_iter()
+            .map(|(measurement, record)| record.into_query_with(measurement, &QueryOptions::default()).build().unwrap().get())
+            .collect();
+        assert_eq!(lines, vec![
+            format!("cpu,host=web cpu_sys=2,cpu_user=1 {}", NANOS),
+            format!("mem,host=web mem_used=3 {}", NANOS),
+            format!("system disk=4 {}", NANOS),
+        ]);
+        
+        assert!(parse_column_group("mem").is_err());
+        assert!(parse_column_group("mem:").is_err());
+    }
+    
+    #[test]
+    fn blank_measurements_are_rejected() {
+        assert_eq!(parse_measurement("cpu load").unwrap(), "cpu load");
+        assert!(parse_measurement("").is_err());
+        assert!(parse_measurement("  ").is_err());
+        assert!(parse_measurement("cpu\nload").is_err());
+        assert!(parse_column_group(" :cpu").is_err());
+        
+        assert_eq!(normalize_measurement_name(KeyCase::Snake, "CPU Load"), "cpu_load");
+        assert_eq!(normalize_measurement_name(KeyCase::Snake, "---"), "---");
+    }
+    
+    #[tokio::test]
+    async fn files_are_only_dropped_once_the_parser_stage_stops() {
+        let stats = Mutex::new(ImportStats::default());
+        let (file_tx, mut file_rx) = mpsc::channel(1);
+        let file = |path: &str| QueuedFile { path: PathBuf::from(path), hashed: None };
+        assert!(queue_file(&file_tx, file("a.csv"), &stats).await);
+        assert_eq!(file_rx.recv().await, Some(file("a.csv")));
+        
+        drop(file_rx);
+        assert!(!queue_file(&file_tx, file("b.csv"), &stats).await);
+        assert_eq!(stats.lock().unwrap().files_send_errors, 1);
+    }
+    
+    #[test]
+    fn hash_algorithms_match_their_reference_digests() {
+        assert_eq!(HashAlgo::Sha256.hasher().digest(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(HashAlgo::Blake3.hasher().digest(b"abc"), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!(HashAlgo::Xxhash64.hasher().digest(b"abc"), "44bc2cf5ad770999");
+        assert_eq!(HashAlgo::Xxh3.hasher().digest(b"abc"), "78af5f94892f3950");
+        
+        // Files larger than a chunk hash the same as their contents in one piece
+        let bytes: Vec<u8> = (0..HASH_CHUNK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), &bytes).unwrap();
+        assert_eq!(Sha256Hasher.hash(file.path()).unwrap(), format!("{:x}", Sha256::digest(&bytes)));
+        assert_eq!(Blake3Hasher.hash(file.path()).unwrap(), blake3::hash(&bytes).to_hex().to_string());
+        assert_eq!(Xxhash64Hasher.hash(file.path()).unwrap(), format!("{:016x}", xxhash_rust::xxh64::xxh64(&bytes, 0)));
+        assert_eq!(Xxh3Hasher.hash(file.path()).unwrap(), format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&bytes)));
+        
+        // Entries cached before the algorithm was recorded were hashed with SHA-256
+        let entry: FileMetadata = serde_json::from_value(serde_json::json!({
+            "path": "data/a.csv",
+            "hash": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            "last_processed": "2024-01-15T10:30:00Z",
+            "records_count": 1,
+        })).unwrap();
+        assert_eq!(entry.hash_algo, HashAlgo::Sha256);
+        assert_eq!(entry.signature(), None);
+    }
+    
+    #[test]
+    fn cache_saves_replace_the_file_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".import_cache.json");
+        let mut cache = HashMap::new();
+        mark_in_progress(&mut cache, "data/a.csv", "data/a.csv");
+        save_cache(&path, &cache).unwrap();
+        save_cache(&path, &cache).unwrap();
+        
+        // Only the cache itself is left behind
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(files, [std::ffi::OsString::from(".import_cache.json")]);
+        assert_eq!(load_cache(&path, CacheMismatch::Fail).unwrap().len(), 1);
+    }
+    
+    #[test]
+    fn truncated_caches_are_set_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".import_cache.json");
+        let mut cache = HashMap::new();
+        mark_in_progress(&mut cache, "data/a.csv", "data/a.csv");
+        save_cache(&path, &cache).unwrap();
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+        
+        assert!(load_cache(&path, CacheMismatch::Fail).unwrap().is_empty());
+        assert!(!path.exists());
+        let backups: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with(".import_cache.json.corrupt-"));
+        assert_eq!(std::fs::read(dir.path().join(&backups[0])).unwrap(), &contents[..contents.len() / 2]);
+    }
+    
+    #[test]
+    fn destination_cache_keys_separate_imports_of_one_file() {
+        let mut cache = HashMap::new();
+        let cpu = cache_key("data/a.csv", Some("cpu@http://127.0.0.1:8086/cursed_stats"));
+        let mem = cache_key("data/a.csv", Some("mem@http://127.0.0.1:8086/cursed_stats"));
+        mark_in_progress(&mut cache, &cpu, "data/a.csv");
+        cache.get_mut(&cpu).unwrap().hash = "abc".to_string();
+        
+        // Importing into a second measurement doesn't find the first import
+        assert!(cache.get(&mem).is_none());
+        mark_in_progress(&mut cache, &mem, "data/a.csv");
+        assert_eq!(cache.len(), 2);
+        assert!(cache.values().all(|m| m.path == "data/a.csv"));
+        
+        // Path keys stay compatible with existing caches
+        assert_eq!(cache_key("data/a.csv", None), "data/a.csv");
+        
+        // Sidecars, templates and column groups change where records go
+        let destination = CacheDestination {
+            targets: "http://127.0.0.1:8086/cursed_stats".to_string(),
+            measurement: "cpu".to_string(),
+            template: None,
+            column_groups: vec![],
+        };
+        assert_eq!(cache_key("data/a.csv", Some(&destination.key(None))), cpu);
+        assert_eq!(cache_key("data/a.csv", Some(&destination.key(Some("mem")))), mem);
+        let grouped = CacheDestination { template: Some("{host}".to_string()), column_groups: vec!["disk".to_string()], ..destination.clone() };
+        assert_eq!(grouped.key(Some("mem")), "{host}+disk@http://127.0.0.1:8086/cursed_stats");
+        
+        // Entries keyed by their path alone are taken to belong to the current destination
+        cache.clear();
+        mark_in_progress(&mut cache, "data/a.csv", "data/a.csv");
+        assert_eq!(assign_path_keys(&mut cache, &destination), 1);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), [&cpu]);
+    }
+    
+    #[test]
+    fn extensions_match_regardless_of_case() {
+        let defaults = scan_extensions(&Cli::try_parse_from(["importer"]).unwrap().args);
+        assert!(has_extension(Path::new("exports/DATA.CSV"), &defaults));
+        assert!(has_extension(Path::new("x.Csv"), &defaults));
+        assert!(!has_extension(Path::new("CSV"), &defaults));
+        
+        let args = Cli::try_parse_from(["importer", "--extensions", ".CSV, Dat"]).unwrap().args;
+        let extensions = scan_extensions(&args);
+        assert_eq!(extensions, ["csv", "dat"]);
+        assert!(has_extension(Path::new("DATA.CSV"), &extensions));
+        assert!(has_extension(Path::new("run.dat"), &extensions));
+        assert!(!has_extension(Path::new("notes.txt"), &extensions));
+    }
+    
+    #[test]
+    fn the_import_subcommand_is_optional() {
+        let bare = Cli::try_parse_from(["importer", "--scan-dir", "data", "--measurement", "cpu"]).unwrap();
+        assert!(bare.command.is_none());
+        assert_eq!(bare.args.measurement, "cpu");
+        let Some(Command::Import(args)) = Cli::try_parse_from(["importer", "import", "--scan-dir", "data"]).unwrap().command else {
+            panic!("expected the import subcommand");
+        };
+        assert_eq!(args.scan_dir, PathBuf::from("data"));
+        
+        let cli = Cli::try_parse_from(["importer", "cache", "list", "--format", "json", "--cache-file", "c.json"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Cache { file, action: CacheCommand::List { format: ListFormat::Json, .. } })
+            if file.cache_file.as_deref() == Some(Path::new("c.json"))));
+        // Import options don't go with cache commands
+        assert!(Cli::try_parse_from(["importer", "--scan-dir", "data", "cache", "list"]).is_err());
+        
+        let Some(Command::Retry(args)) = Cli::try_parse_from(["importer", "retry", "--dead-letter", "failed.ndjson"]).unwrap().command else {
+            panic!("expected the retry subcommand");
+        };
+        assert_eq!(args.dead_letter, Some(PathBuf::from("failed.ndjson")));
+    }
+    
+    #[test]
+    fn targets_files_fall_back_to_command_line_credentials() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("targets.toml");
+        std::fs::write(&path, r#"
+            [[target]]
+            url = "http://old-influx:8086"
+            
+            [[target]]
+            url = "http://other-influx:8086"
+            username = "other"
+            password = "other-secret"
+            retention_policy = "weekly"
+            
+            [[target]]
+            url = "http://new-influx:8086"
+            token = "my-token"
+        "#).unwrap();
+        let args = Cli::try_parse_from([
+            "importer", "--targets", path.to_str().unwrap(),
+            "--retention-policy", "daily", "--username", "importer", "--password", "secret",
+        ]).unwrap().args;
+        let targets = load_targets(&args).unwrap();
+        let settings: Vec<_> = targets.iter()
+            .map(|t| (t.retention_policy.as_deref(), t.username.as_deref(), t.password.as_deref()))
+            .collect();
+        // 2.x targets authenticate with their token and have no retention policy
+        assert_eq!(settings, [
+            (Some("daily"), Some("importer"), Some("secret")),
+            (Some("weekly"), Some("other"), Some("other-secret")),
+            (None, None, None),
+        ]);
+    }
+    
+    #[test]
+    fn line_protocol_files_are_not_shared_between_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let scan_dir = dir.path().join("data");
+        std::fs::create_dir(&scan_dir).unwrap();
+        let output = dir.path().join("lp");
+        let mut lp = LineProtocolDir::create(&output, &scan_dir, false).unwrap();
+        
+        assert_eq!(lp.output_path(Some(&scan_dir.join("a/b.csv"))), output.join("a/b.lp"));
+        assert_eq!(lp.output_path(Some(&scan_dir.join("c/b.csv"))), output.join("c/b.lp"));
+        assert_eq!(lp.output_path(Some(&scan_dir.join("a/b.tsv"))), output.join("a/b-2.lp"));
+        // Files outside the scan directory only keep their name
+        assert_eq!(lp.output_path(Some(Path::new("/x/b.csv"))), output.join("b.lp"));
+        assert_eq!(lp.output_path(Some(Path::new("/y/b.csv"))), output.join("b-2.lp"));
+        // An input keeps its file for every batch
+        assert_eq!(lp.output_path(Some(&scan_dir.join("a/b.csv"))), output.join("a/b.lp"));
+        assert_eq!(lp.output_path(None), output.join("import.lp"));
+    }
+    
+    #[test]
+    fn cache_show_compares_the_file_with_its_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.csv");
+        std::fs::write(&file, "timestamp,value\n").unwrap();
+        let path = canonical_path(&file);
+        let mut cache = HashMap::new();
+        mark_in_progress(&mut cache, &path, &path);
+        let entry = cache.get_mut(&path).unwrap();
+        entry.hash = entry.hash_algo.hasher().hash(&file).unwrap();
+        entry.in_progress = false;
+        
+        let listing = CacheListing::new(&path, &cache[&path], Some(disk_state(&cache[&path])));
+        assert_eq!(listing.on_disk, Some("matches"));
+        assert_eq!(listing.hash_prefix.len(), 12);
+        std::fs::write(&file, "timestamp,value\n2024-01-15T10:30:00Z,1\n").unwrap();
+        assert_eq!(disk_state(&cache[&path]), "changed");
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(disk_state(&cache[&path]), "missing");
+    }
+    
+    #[test]
+    fn invalidation_removes_paths_and_glob_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<String> = ["a.csv", "b.csv", "2024-01.csv", "2024-02.csv"].iter().map(|name| {
+            let file = dir.path().join(name);
+            std::fs::write(&file, "timestamp,value\n").unwrap();
+            canonical_path(&file)
+        }).collect();
+        let mut cache = HashMap::new();
+        for path in &files {
+            mark_in_progress(&mut cache, path, path);
+        }
+        let targets = [dir.path().join("a.csv").to_string_lossy().to_string(), "*/2024-*.csv".to_string()];
+        let mut expected = vec![files[0].clone(), files[2].clone(), files[3].clone()];
+        expected.sort();
+        assert_eq!(invalidate_entries(&mut cache, &targets).unwrap(), expected);
+        assert_eq!(cache.keys().collect::<Vec<_>>(), [&files[1]]);
+        assert!(invalidate_entries(&mut cache, &["[".to_string()]).is_err());
+    }
+    
+    #[test]
+    fn sharded_caches_are_saved_and_loaded_per_shard() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        let mut cache = HashMap::new();
+        for i in 0..20 {
+            let file = format!("/data/{}.csv", i);
+            mark_in_progress(&mut cache, &file, &file);
+        }
+        
+        let files = CacheFiles::new(&path, 4);
+        files.save_map(&cache).unwrap();
+        assert!(!path.exists());
+        for shard in 0..4 {
+            let entries = load_cache(&files.shard_path(shard), CacheMismatch::Fail).unwrap();
+            assert!(entries.values().all(|m| files.shard_of(&m.path) == shard));
+        }
+        assert_eq!(files.load(CacheMismatch::Fail).unwrap().len(), 20);
+        
+        // Fewer shards pick up the entries of the extra ones, which go once saved
+        let fewer = CacheFiles::new(&path, 2);
+        let loaded = Mutex::new(fewer.load(CacheMismatch::Fail).unwrap());
+        assert_eq!(loaded.lock().unwrap().len(), 20);
+        fewer.flush(&loaded).unwrap();
+        assert!(!files.shard_path(2).exists() && !files.shard_path(3).exists());
+        assert_eq!(fewer.load(CacheMismatch::Fail).unwrap().len(), 20);
+        
+        // Going back to a single file merges the shards and removes them on the first save
+        let unsharded = CacheFiles::new(&path, 1);
+        let loaded = Mutex::new(unsharded.load(CacheMismatch::Fail).unwrap());
+        assert_eq!(loaded.lock().unwrap().len(), 20);
+        unsharded.save_entries(&loaded, &["/data/0.csv".to_string()]).unwrap();
+        assert!(path.exists() && !files.shard_path(0).exists() && !files.shard_path(1).exists());
+        assert_eq!(load_cache(&path, CacheMismatch::Fail).unwrap().len(), 20);
+        
+        // And sharding again splits it up, leaving no stale single file behind
+        let resharded = CacheFiles::new(&path, 4);
+        let loaded = Mutex::new(resharded.load(CacheMismatch::Fail).unwrap());
+        resharded.save_entries(&loaded, &["/data/0.csv".to_string()]).unwrap();
+        assert!(!path.exists());
+        assert_eq!(CacheFiles::new(&path, 4).load(CacheMismatch::Fail).unwrap().len(), 20);
+        assert_eq!(CacheFiles::new(&path, 1).load(CacheMismatch::Fail).unwrap().len(), 20);
+        
+        // A single-file cache is split up on first use
+        let single = dir.path().join("single.json");
+        save_cache(&single, &cache).unwrap();
+        assert_eq!(CacheFiles::new(&single, 8).load(CacheMismatch::Fail).unwrap().len(), 20);
+    }
+    
+    #[test]
+    fn sqlite_caches_upsert_and_remove_single_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.db");
+        let store = sqlite_cache::SqliteCache::new(&path);
+        assert!(store.load(CacheMismatch::Fail).unwrap().is_empty());
+        assert!(!path.exists());
+        
+        let mut cache = HashMap::new();
+        for i in 0..5 {
+            let file = format!("/data/{}.csv", i);
+            mark_in_progress(&mut cache, &file, &file);
+        }
+        store.save_map(&cache).unwrap();
+        let cache = Mutex::new(store.load(CacheMismatch::Fail).unwrap());
+        assert_eq!(cache.lock().unwrap().len(), 5);
+        
+        // Only the given keys are written: a changed entry, a removed one and a new one
+        {
+            let mut cache = cache.lock().unwrap();
+            cache.get_mut("/data/0.csv").unwrap().records_count = 7;
+            cache.remove("/data/1.csv");
+            mark_in_progress(&mut cache, "/data/5.csv", "/data/5.csv");
+            cache.get_mut("/data/2.csv").unwrap().records_count = 3;
+        }
+        let keys = ["/data/0.csv", "/data/1.csv", "/data/5.csv"].map(String::from);
+        store.save_entries(&cache, &keys).unwrap();
+        let loaded = sqlite_cache::SqliteCache::new(&path).load(CacheMismatch::Fail).unwrap();
+        assert_eq!(loaded.len(), 5);
+        assert_eq!(loaded["/data/0.csv"].records_count, 7);
+        assert_eq!(loaded["/data/2.csv"].records_count, 0);
+        assert!(!loaded.contains_key("/data/1.csv") && loaded.contains_key("/data/5.csv"));
+        
+        // A database written with another schema version is handled like a JSON cache
+        rusqlite::Connection::open(&path).unwrap()
+            .execute("UPDATE meta SET value = '0' WHERE name = 'schema_version'", []).unwrap();
+        let stale = sqlite_cache::SqliteCache::new(&path);
+        assert!(matches!(stale.load(CacheMismatch::Fail), Err(CursedStatsError::CacheVersion { found: 0, .. })));
+        assert!(stale.load(CacheMismatch::Clear).unwrap().is_empty());
+        assert!(stale.load(CacheMismatch::Fail).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn cache_migrate_copies_the_json_cache_into_sqlite() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("cache.json");
+        let db = dir.path().join("cache.db");
+        let mut cache = HashMap::new();
+        for i in 0..5 {
+            let file = format!("/data/{}.csv", i);
+            mark_in_progress(&mut cache, &file, &file);
+            let entry = cache.get_mut(&file).unwrap();
+            entry.in_progress = i % 2 == 0;
+            entry.records_count = i;
+            entry.max_imported_timestamp = Some(fixed_time());
+        }
+        save_cache(&json, &cache).unwrap();
+        
+        let migrate = Cli::try_parse_from(["importer", "cache", "migrate", "--to", db.to_str().unwrap(), "--cache-file", json.to_str().unwrap()]).unwrap();
+        assert_eq!(run(migrate).unwrap(), 0);
+        
+        let entries = |cache: HashMap<String, FileMetadata>| -> BTreeMap<String, serde_json::Value> {
+            cache.into_iter().map(|(key, metadata)| (key, serde_json::to_value(metadata).unwrap())).collect()
+        };
+        let migrated = open_cache_store(CacheBackend::Sqlite, Some(db.as_path()), 1).load(CacheMismatch::Fail).unwrap();
+        assert_eq!(entries(migrated), entries(cache));
+        // The JSON cache is left as it was
+        assert_eq!(load_cache(&json, CacheMismatch::Fail).unwrap().len(), 5);
+        
+        // Only a JSON cache can be migrated
+        let from_sqlite = Cli::try_parse_from(["importer", "cache", "migrate", "--to", "x.db", "--cache-backend", "sqlite"]).unwrap();
+        assert!(run(from_sqlite).is_err());
+    }
+    
+    #[test]
+    fn sqlite_caches_take_concurrent_saves() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = sqlite_cache::SqliteCache::new(&dir.path().join("cache.db"));
+        let cache = Mutex::new(HashMap::new());
+        
+        // Two file tasks saving their own entries as each file completes
+        std::thread::scope(|scope| {
+            for thread in ["a", "b"] {
+                let (store, cache) = (&store, &cache);
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let file = format!("/data/{}/{}.csv", thread, i);
+                        mark_in_progress(&mut cache.lock().unwrap(), &file, &file);
+                        store.save_entries(cache, &[file]).unwrap();
+                    }
+                });
+            }
+        });
+        
+        let loaded = sqlite_cache::SqliteCache::new(&dir.path().join("cache.db")).load(CacheMismatch::Fail).unwrap();
+        assert_eq!(loaded.len(), 100);
+        assert!(loaded.contains_key("/data/a/49.csv") && loaded.contains_key("/data/b/0.csv"));
+    }
+    
+    #[test]
+    fn read_buffer_sizes_accept_binary_suffixes() {
+        assert_eq!(parse_byte_size("65536"), Ok(65536));
+        assert_eq!(parse_byte_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_byte_size("4M"), Ok(4 << 20));
+        assert!(parse_byte_size("0").is_err() && parse_byte_size("M").is_err() && parse_byte_size("4MB").is_err());
+        
+        // Buffered reads parse and hash the same as unbuffered ones
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "timestamp,value\n2024-01-15T10:30:00Z,1\n2024-01-15T10:31:00Z,2\n").unwrap();
+        let options = ParseOptions { read_buffer_size: Some(16), ..ParseOptions::default() };
+        assert_eq!(parse_csv_dynamic(file.path().to_path_buf(), &options).unwrap().records.len(), 2);
+        assert_eq!(Blake3Hasher.hash_file(file.path(), Some(HASH_CHUNK_SIZE * 2)).unwrap(), Blake3Hasher.hash(file.path()).unwrap());
+    }
+    
+    #[test]
+    fn file_ages_are_bounded() {
+        assert_eq!(parse_hours("0.5").map(hours), Ok(std::time::Duration::from_secs(1800)));
+        for invalid in ["-1", "NaN", "inf", "1e20", "876601"] {
+            assert!(parse_hours(invalid).is_err(), "{}", invalid);
+        }
+    }
+    
+    #[test]
+    fn pruning_removes_missing_and_old_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.csv");
+        std::fs::write(&file, "timestamp,value\n").unwrap();
+        let kept = canonical_path(&file);
+        let gone = dir.path().join("gone.csv").to_string_lossy().to_string();
+        let mut cache = HashMap::new();
+        // Relative paths are found next to the cache, wherever the command runs
+        for key in [kept.as_str(), gone.as_str(), "stdin:abc", "a.csv"] {
+            mark_in_progress(&mut cache, key, key);
+        }
+        let now = chrono::Utc::now();
+        assert_eq!(prune_cache(&mut cache, dir.path(), None, now), 1);
+        assert!(cache.contains_key(&kept) && cache.contains_key("stdin:abc") && cache.contains_key("a.csv"));
+        
+        assert_eq!(parse_age("90d"), Ok(chrono::Duration::days(90)));
+        assert!(parse_age("90").is_err() && parse_age("d").is_err() && parse_age("1w").is_err());
+        assert!(parse_age("9223372036854775807d").is_err());
+        assert_eq!(prune_cache(&mut cache, dir.path(), parse_age("1h").ok(), now + chrono::Duration::minutes(30)), 0);
+        assert_eq!(prune_cache(&mut cache, dir.path(), parse_age("1h").ok(), now + chrono::Duration::hours(2)), 3);
+        assert!(cache.is_empty());
+    }
+    
+    #[test]
+    fn cache_keys_are_canonical_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("data")).unwrap();
+        let file = dir.path().join("data").join("a.csv");
+        std::fs::write(&file, "timestamp,value\n").unwrap();
+        let canonical = canonical_path(&file);
+        assert!(Path::new(&canonical).is_absolute());
+        assert_eq!(canonical_path(&dir.path().join("data").join("..").join("data").join(".").join("a.csv")), canonical);
+        // Deleted files keep their path
+        assert_eq!(canonical_path(Path::new("data/gone.csv")), "data/gone.csv");
+        
+        // Entries from before canonical keys are re-keyed, including destination keys
+        let mut cache = HashMap::new();
+        let spelled = dir.path().join("data").join("..").join("data").join("a.csv").to_string_lossy().to_string();
+        mark_in_progress(&mut cache, &spelled, &spelled);
+        let destination = cache_key(&spelled, Some("cpu@http://127.0.0.1:8086/cursed_stats"));
+        mark_in_progress(&mut cache, &destination, &spelled);
+        mark_in_progress(&mut cache, "data/gone.csv", "data/gone.csv");
+        assert_eq!(canonicalize_cache_keys(&mut cache), 2);
+        assert_eq!(cache[&canonical].path, canonical);
+        assert!(cache.contains_key(&cache_key(&canonical, Some("cpu@http://127.0.0.1:8086/cursed_stats"))));
+        assert!(cache.contains_key("data/gone.csv"));
+        assert_eq!(canonicalize_cache_keys(&mut cache), 0);
+    }
+    
+    #[test]
+    fn questdb_table_names_are_sanitized() {
+        assert_eq!(questdb_table_name("cpu_load"), "cpu_load");
+        assert_eq!(questdb_table_name("sensors.v2/raw"), "sensors_v2_raw");
+        assert_eq!(questdb_table_name("load (1m)"), "load _1m_");
+        assert_eq!(questdb_table_name("a\"b'c\\d"), "a_b_c_d");
+    }
+    
+    #[test]
+    fn output_files_split_by_measurement_and_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("out.lp");
+        let mut output = LineProtocolFile::create(&base, Some(SplitPeriod::Day), true).unwrap();
+        let points: Vec<Point> = [("cpu", NANOS), ("mem/v2", NANOS), ("cpu", NANOS + 86_400_000_000_000), ("cpu", NANOS + 1)].into_iter()
+            .map(|(measurement, timestamp)| {
+                let mut point = Point::new(measurement, Some(timestamp));
+                point.fields.insert("value".to_string(), FieldValue::Integer(1));
+                point
+            })
+            .collect();
+        output.write(&points).unwrap();
+        output.finish().unwrap();
+        
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("out_cpu_2024-01-15.lp"), format!("cpu value=1i {}\ncpu value=1i {}\n", NANOS, NANOS + 1));
+        assert_eq!(read("out_mem_v2_2024-01-15.lp"), format!("mem/v2 value=1i {}\n", NANOS));
+        assert_eq!(read("out_cpu_2024-01-16.lp").lines().count(), 1);
+        assert!(!base.exists());
+    }
+    
+    #[tokio::test]
+    async fn stdout_stops_printing_at_the_limit() {
+        let sink = Mutex::new(LineProtocolStdout::new(Some(3)));
+        let points = || (0..2).map(|i| {
+            let mut point = Point::new("cpu", Some(NANOS + i));
+            point.fields.insert("value".to_string(), FieldValue::Integer(i));
+            point
+        }).collect::<Vec<_>>();
+        sink.write_batch(None, points()).await.unwrap();
+        let written = sink.write_batch(None, points()).await.unwrap();
+        assert_eq!(written.payload_bytes, format!("cpu value=0i {}", NANOS).len());
+        assert_eq!(sink.write_batch(None, points()).await.unwrap().wire_bytes, 0);
+        assert_eq!(sink.lines_written(), 3);
+    }
+    
+    #[tokio::test]
+    async fn questdb_sink_streams_one_line_per_point() {
+        use tokio::io::AsyncReadExt;
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sink = QuestDbSink::new(&listener.local_addr().unwrap().to_string(), 0);
+        let mut point = Point::new("sensors.v2", Some(NANOS));
+        point.tags.insert("host".to_string(), "a".to_string());
+        point.fields.insert("cpu".to_string(), FieldValue::Float(0.5));
+        let written = sink.write_batch(None, vec![point]).await.unwrap();
+        drop(sink);
+        
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut received = String::new();
+        socket.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, format!("sensors_v2,host=a cpu=0.5 {}\n", NANOS));
+        assert_eq!(written.payload_bytes, received.len());
+    }
+    
+    #[test]
+    fn partial_writes_are_attributed_to_lines() {
+        let batch: Vec<String> = ["cpu value=1.5 1", "cpu value=abc 2", "cpu value=3i 3", "mem value=4i 4"]
+            .iter().map(|line| line.to_string()).collect();
+        let rejected = |index: usize, reason: &str| RejectedLine { index, reason: reason.to_string() };
+        
+        // 1.x quotes each line it couldn't parse
+        let body = serde_json::json!({"error": "partial write:\nunable to parse 'cpu value=abc 2': invalid number"}).to_string();
+        assert_eq!(parse_partial_write(&body, &batch), Some(vec![rejected(1, "unable to parse: invalid number")]));
+        
+        // Type conflicts name the field, so every line writing it with the rejected type fails
+        let conflict = "partial write: field type conflict: input field \"value\" on measurement \"cpu\" is type integer, already exists as type float dropped=1";
+        let body = serde_json::json!({"error": conflict}).to_string();
+        assert_eq!(parse_partial_write(&body, &batch), Some(vec![rejected(2, conflict)]));
+        
+        // 2.x numbers the lines from 1, in the message or only in the "line" field
+        let body = serde_json::json!({
+            "code": "invalid",
+            "message": "partial write error (3 written):\nline 2: invalid field format",
+            "line": 2,
+        }).to_string();
+        assert_eq!(parse_partial_write(&body, &batch), Some(vec![rejected(1, "invalid field format")]));
+        let body = serde_json::json!({"code": "invalid", "message": "partial write error (3 written)", "line": 4}).to_string();
+        assert_eq!(parse_partial_write(&body, &batch), Some(vec![rejected(3, "partial write error (3 written)")]));
+        
+        // Failures that can't be tied to a line leave the whole batch failed
+        for body in [
+            serde_json::json!({"error": "partial write: points beyond retention policy dropped=2"}).to_string(),
+            serde_json::json!({"error": "partial write:\nunable to parse 'disk value=x 5': invalid number"}).to_string(),
+            serde_json::json!({"code": "invalid", "message": "partial write error (0 written)", "line": 9}).to_string(),
+            serde_json::json!({"error": "database not found: \"metrics\""}).to_string(),
+            "<html>Bad Request</html>".to_string(),
+        ] {
+            assert_eq!(parse_partial_write(&body, &batch), None, "{}", body);
+        }
+    }
+    
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        assert_eq!(retry_after(" 2 "), Some(std::time::Duration::from_secs(2)));
+        assert_eq!(retry_after("86400"), Some(MAX_RETRY_AFTER));
+        assert_eq!(retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(std::time::Duration::ZERO));
+        assert_eq!(retry_after("soon"), None);
+    }
+    
+    // Accept one connection per response and answer its request with it; yields the bodies
+    // of the requests
+    fn serve_writes(listener: tokio::net::TcpListener, responses: Vec<&'static str>) -> JoinHandle<Vec<String>> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        
+        tokio::spawn(async move {
+            let mut bodies = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let body = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head.lines()
+                            .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break body.to_string();
+                        }
+                    }
+                };
+                socket.write_all(response.as_bytes()).await.unwrap();
+                bodies.push(body);
+            }
+            bodies
+        })
+    }
+    
+    // A 1.x-style writer for these endpoints, without pre-flight state
+    fn line_writer(endpoints: Vec<String>) -> LineWriter {
+        LineWriter {
+            target: endpoints[0].clone(),
+            http: reqwest::Client::new(),
+            endpoints,
+            path: "/write",
+            health_path: "/ping",
+            active: Arc::new(AtomicUsize::new(0)),
+            failovers: Arc::new(AtomicUsize::new(0)),
+            last_recheck: Arc::new(Mutex::new(std::time::Instant::now())),
+            throttled_until: Arc::new(Mutex::new(None)),
+            throttled: Arc::new(AtomicUsize::new(0)),
+            params: Vec::new(),
+            auth_header: None,
+            compress: false,
+            retries: 3,
+        }
+    }
+    
+    const THROTTLED: &str = "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const UNAVAILABLE: &str = "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const ACCEPTED: &str = "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n";
+    
+    #[tokio::test]
+    async fn throttled_writes_are_retried_without_losing_records() {
+        // Answers 429 twice, then accepts the write
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = serve_writes(listener, vec![THROTTLED, THROTTLED, ACCEPTED]);
+        
+        let writer = line_writer(vec![endpoint]);
+        let batch = vec!["cpu value=1 1".to_string(), "cpu value=2 2".to_string()];
+        let outcome = writer.write(&batch).await.unwrap();
+        assert!(outcome.rejected.is_empty());
+        assert_eq!(server.await.unwrap()[2], batch.join("\n"));
+        assert_eq!(writer.throttled.load(Ordering::SeqCst), 2);
+        assert_eq!(writer.failovers.load(Ordering::SeqCst), 0);
+    }
+    
+    #[tokio::test]
+    async fn unavailable_endpoints_fail_over_to_the_next() {
+        let unavailable = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let available = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoints = [&unavailable, &available].map(|listener| format!("http://{}", listener.local_addr().unwrap()));
+        let first = serve_writes(unavailable, vec![UNAVAILABLE]);
+        let second = serve_writes(available, vec![ACCEPTED]);
+        
+        let writer = line_writer(endpoints.to_vec());
+        let batch = vec!["cpu value=1 1".to_string()];
+        assert!(writer.write(&batch).await.unwrap().rejected.is_empty());
+        assert_eq!(first.await.unwrap().len(), 1);
+        assert_eq!(second.await.unwrap(), [batch.join("\n")]);
+        assert_eq!(writer.failovers.load(Ordering::SeqCst), 1);
+        assert_eq!(writer.active.load(Ordering::SeqCst), 1);
+        assert_eq!(writer.throttled.load(Ordering::SeqCst), 0);
+    }
+    
+    #[test]
+    fn unparseable_timestamps_fall_back_to_the_clock() {
+        let options = QueryOptions { clock: Clock(fixed_time), ..QueryOptions::default() };
+        let line = line_protocol(record("15/01/2024 10:30", &[("value", "1")]), &options);
+        assert_eq!(line, "sensors value=1 1700000000000000000");
+    }
+    
+    #[test]
+    fn benchmark_throughput_uses_time_spent_in_the_stage() {
+        let mut timer = StageTimer::default();
+        assert_eq!(timer.per_second(100), 0.0);
+        timer.record(std::time::Duration::from_millis(250), 500, 1_000_000);
+        timer.record(std::time::Duration::from_millis(250), 500, 1_000_000);
+        assert_eq!(timer.per_second(timer.items), 2000.0);
+        assert_eq!(timer.megabytes_per_second(), 4.0);
+    }
+    
+    #[test]
+    fn batch_latency_percentiles_use_the_nearest_rank() {
+        let mut timings = Timings::default();
+        assert!(timings.batch_percentiles().is_none());
+        timings.batch_latencies = (1..=100).rev().map(std::time::Duration::from_millis).collect();
+        let percentiles = timings.batch_percentiles().unwrap();
+        assert_eq!(percentiles, [50, 95, 99].map(std::time::Duration::from_millis));
+        
+        timings.file_times = vec![("a.csv".to_string(), std::time::Duration::from_secs(1)), ("b.csv".to_string(), std::time::Duration::from_secs(3))];
+        assert_eq!(timings.slowest_file(), Some(("b.csv", std::time::Duration::from_secs(3))));
+        assert_eq!(timings.mean_file_time(), std::time::Duration::from_secs(2));
+    }
+    
+    #[test]
+    fn completion_status_reflects_the_failures() {
+        let mut stats = ImportStats { successful_inserts: 10, ..Default::default() };
+        assert_eq!(stats.status(true), "success");
+        assert_eq!(stats.status(false), "failed");
+        stats.files_failed = 1;
+        assert_eq!(stats.status(true), "partial");
+        stats.failed_inserts = 5;
+        assert_eq!(completion_event(&stats, true, false)["status"], "partial");
+        stats.successful_inserts = 0;
+        assert_eq!(stats.status(true), "failed");
+    }
+    
+    // Accepts every batch, but panics when completing any file after the first
+    #[derive(Default)]
+    struct PanickingSink {
+        first_file: Mutex<Option<PathBuf>>,
+    }
+    
+    #[async_trait::async_trait]
+    impl Sink for PanickingSink {
+        fn name(&self) -> String {
+            "panicking".to_string()
+        }
+        
+        async fn write_batch(&self, _source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+            Ok(WriteOutcome { payload_bytes: points.len(), wire_bytes: points.len(), rejected: Vec::new() })
+        }
+        
+        fn end_file(&self, source: &Path) -> std::result::Result<(), CursedStatsError> {
+            let first = self.first_file.lock().unwrap().get_or_insert_with(|| source.to_path_buf()).clone();
+            assert_eq!(first, source, "simulated writer panic");
+            Ok(())
+        }
+        
+        fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn writer_panics_fail_the_run_and_keep_completed_files_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        for (i, name) in ["a.csv", "b.csv"].iter().enumerate() {
+            std::fs::write(data.join(name), format!("timestamp,value\n2024-01-15T10:30:00Z,{}\n", i)).unwrap();
+        }
+        let cache_file = dir.path().join("cache.json");
+        
+        // A single DB thread writes one file at a time, so the first is complete before the second panics
+        let args = Cli::try_parse_from([
+            "importer", "--scan-dir", data.to_str().unwrap(), "--cache-file", cache_file.to_str().unwrap(), "--db-threads", "1",
+        ]).unwrap().args;
+        assert!(import_into(args, Some(vec![Box::new(PanickingSink::default())])).is_err());
+        
+        let cache = CacheFiles::new(&cache_file, 1).load(CacheMismatch::Fail).unwrap();
+        let (completed, interrupted): (Vec<_>, Vec<_>) = cache.values().partition(|m| !m.in_progress);
+        assert_eq!(completed.len(), 1);
+        assert_eq!((completed[0].records_count, completed[0].successful_count), (1, 1));
+        // The file that panicked is still marked in progress, so the next run imports it again
+        assert_eq!(interrupted.len(), 1);
+        assert_ne!(interrupted[0].path, completed[0].path);
+    }
+}
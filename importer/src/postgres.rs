@@ -0,0 +1,263 @@
+// PostgreSQL/TimescaleDB sink (--pg-dsn), built with the `postgres` cargo feature
+
+use crate::{ColumnType, CursedStatsError, FieldValue, Point, Sink, WriteOutcome};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::{Client, NoTls};
+
+// Writes points into a table with COPY ... FROM STDIN BINARY. Columns declared in the
+// schema (--types/--types-file) get their own typed column; everything else is kept in
+// the JSONB `tags` and `fields` columns
+pub(crate) struct PostgresSink {
+    config: tokio_postgres::Config,
+    // Table name as given, and quoted for use in SQL
+    table: String,
+    quoted_table: String,
+    columns: Vec<(String, ColumnType)>,
+    retries: u32,
+    // Reconnected on the next write when the connection drops
+    client: tokio::sync::Mutex<Option<Client>>,
+}
+
+// Columns every table has, which schema columns can't replace
+const FIXED_COLUMNS: [&str; 4] = ["time", "measurement", "tags", "fields"];
+
+type SqlValue = Box<dyn ToSql + Sync + Send>;
+
+impl PostgresSink {
+    pub(crate) fn new(dsn: &str, table: &str, types: &HashMap<String, ColumnType>, retries: u32) -> Result<Self> {
+        let config: tokio_postgres::Config = dsn.parse().context("Invalid --pg-dsn")?;
+        let quoted_table = table.split('.').map(quote_identifier).collect::<Vec<_>>().join(".");
+        let columns: BTreeMap<String, ColumnType> = types
+            .iter()
+            .filter(|(name, _)| !FIXED_COLUMNS.contains(&name.as_str()))
+            .map(|(name, column_type)| (name.clone(), *column_type))
+            .collect();
+        if columns.is_empty() {
+            info!("Writing to PostgreSQL table {} with JSONB tags and fields", table);
+        } else {
+            info!("Writing to PostgreSQL table {} with {} typed columns from the schema", table, columns.len());
+        }
+        Ok(PostgresSink {
+            config,
+            table: table.to_string(),
+            quoted_table,
+            columns: columns.into_iter().collect(),
+            retries,
+            client: tokio::sync::Mutex::new(None),
+        })
+    }
+    
+    async fn connect(&self) -> std::result::Result<Client, tokio_postgres::Error> {
+        let (client, connection) = self.config.connect(NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("PostgreSQL connection failed: {}", e);
+            }
+        });
+        Ok(client)
+    }
+    
+    fn create_table_sql(&self) -> String {
+        let mut columns = vec!["time TIMESTAMPTZ NOT NULL".to_string(), "measurement TEXT NOT NULL".to_string()];
+        for (name, column_type) in &self.columns {
+            columns.push(format!("{} {}", quote_identifier(name), sql_type(*column_type)));
+        }
+        columns.push("tags JSONB".to_string());
+        columns.push("fields JSONB".to_string());
+        format!("CREATE TABLE IF NOT EXISTS {} ({})", self.quoted_table, columns.join(", "))
+    }
+    
+    fn copy_sql(&self) -> String {
+        let mut columns = vec!["time".to_string(), "measurement".to_string()];
+        columns.extend(self.columns.iter().map(|(name, _)| quote_identifier(name)));
+        columns.push("tags".to_string());
+        columns.push("fields".to_string());
+        format!("COPY {} ({}) FROM STDIN BINARY", self.quoted_table, columns.join(", "))
+    }
+    
+    fn column_types(&self) -> Vec<Type> {
+        let mut types = vec![Type::TIMESTAMPTZ, Type::TEXT];
+        types.extend(self.columns.iter().map(|(_, column_type)| match column_type {
+            ColumnType::Float => Type::FLOAT8,
+            ColumnType::Int => Type::INT8,
+            ColumnType::Bool => Type::BOOL,
+            ColumnType::String | ColumnType::Tag => Type::TEXT,
+        }));
+        types.extend([Type::JSONB, Type::JSONB]);
+        types
+    }
+    
+    // Values of one row, in the order of copy_sql
+    fn row(&self, point: &Point) -> Vec<SqlValue> {
+        // PostgreSQL stores microseconds; points without a time are stored at the current time
+        let time = point.timestamp
+            .and_then(|nanos| chrono::DateTime::from_timestamp(nanos.div_euclid(1_000_000_000), nanos.rem_euclid(1_000_000_000) as u32))
+            .unwrap_or_else(chrono::Utc::now);
+        let mut tags = point.tags.clone();
+        let mut fields = point.fields.clone();
+        let mut row: Vec<SqlValue> = vec![Box::new(time), Box::new(point.measurement.clone())];
+        for (name, column_type) in &self.columns {
+            row.push(column_value(*column_type, fields.remove(name), tags.remove(name)));
+        }
+        let tags: serde_json::Map<String, serde_json::Value> = tags.into_iter().map(|(k, v)| (k, v.into())).collect();
+        let fields: serde_json::Map<String, serde_json::Value> = fields.into_iter().map(|(k, v)| (k, v.into())).collect();
+        row.push(Box::new(serde_json::Value::Object(tags)));
+        row.push(Box::new(serde_json::Value::Object(fields)));
+        row
+    }
+    
+    // Bulk-load a batch in a single COPY, which either stores every row or none
+    async fn copy(&self, points: &[Point]) -> std::result::Result<u64, tokio_postgres::Error> {
+        let mut connection = self.client.lock().await;
+        let client = match connection.take() {
+            Some(client) if !client.is_closed() => client,
+            _ => self.connect().await?,
+        };
+        let client = connection.insert(client);
+        
+        let sink = client.copy_in(&self.copy_sql()).await?;
+        let writer = BinaryCopyInWriter::new(sink, &self.column_types());
+        tokio::pin!(writer);
+        for point in points {
+            let row = self.row(point);
+            let values: Vec<&(dyn ToSql + Sync)> = row.iter().map(|v| v.as_ref() as &(dyn ToSql + Sync)).collect();
+            writer.as_mut().write(&values).await?;
+        }
+        writer.finish().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for PostgresSink {
+    fn name(&self) -> String {
+        self.table.clone()
+    }
+    
+    // Connect, create the table and make it a hypertable when TimescaleDB is installed
+    async fn preflight(&mut self) -> Result<()> {
+        let client = self.connect().await
+            .with_context(|| format!("Failed to connect to PostgreSQL for table {}", self.table))?;
+        client.batch_execute(&self.create_table_sql()).await
+            .with_context(|| format!("Failed to create table {}", self.table))?;
+        let timescale = client.query_opt("SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'", &[]).await
+            .context("Failed to check for the TimescaleDB extension")?
+            .is_some();
+        if timescale {
+            let sql = format!("SELECT create_hypertable('{}', 'time', if_not_exists => TRUE)", self.quoted_table.replace('\'', "''"));
+            client.batch_execute(&sql).await
+                .with_context(|| format!("Failed to make {} a hypertable", self.table))?;
+            info!("Table {} is a TimescaleDB hypertable", self.table);
+        }
+        info!("Pre-flight checks passed for PostgreSQL table {}", self.table);
+        *self.client.get_mut() = Some(client);
+        Ok(())
+    }
+    
+    async fn write_batch(&self, _source: Option<&Path>, points: Vec<Point>) -> std::result::Result<WriteOutcome, CursedStatsError> {
+        let mut attempt = 0;
+        loop {
+            match self.copy(&points).await {
+                Ok(_) => return Ok(WriteOutcome { payload_bytes: 0, wire_bytes: 0, rejected: Vec::new() }),
+                Err(e) if attempt < self.retries && is_retryable(&e) => {
+                    let delay = std::time::Duration::from_millis(500 * 2u64.pow(attempt));
+                    attempt += 1;
+                    warn!("Write to {} failed ({}), retry {}/{} in {:?}", self.table, e, attempt, self.retries, delay);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(cause) => return Err(CursedStatsError::PostgresWrite { table: self.table.clone(), cause }),
+            }
+        }
+    }
+    
+    fn flush(&self) -> std::result::Result<(), CursedStatsError> {
+        Ok(())
+    }
+}
+
+// Connection problems, aborted transactions and resource shortages may pass; bad data won't
+fn is_retryable(e: &tokio_postgres::Error) -> bool {
+    match e.code() {
+        Some(state) => matches!(&state.code()[..2], "08" | "40" | "53" | "57"),
+        None => true,
+    }
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_type(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Float => "DOUBLE PRECISION",
+        ColumnType::Int => "BIGINT",
+        ColumnType::Bool => "BOOLEAN",
+        ColumnType::String | ColumnType::Tag => "TEXT",
+    }
+}
+
+// Value of a schema column; a value that doesn't match the declared type is stored as NULL
+fn column_value(column_type: ColumnType, field: Option<FieldValue>, tag: Option<String>) -> SqlValue {
+    match column_type {
+        ColumnType::Float => Box::new(match field {
+            Some(FieldValue::Float(v)) => Some(v),
+            Some(FieldValue::Integer(v)) => Some(v as f64),
+            Some(FieldValue::Unsigned(v)) => Some(v as f64),
+            _ => None,
+        }),
+        ColumnType::Int => Box::new(match field {
+            Some(FieldValue::Integer(v)) => Some(v),
+            Some(FieldValue::Unsigned(v)) => i64::try_from(v).ok(),
+            _ => None,
+        }),
+        ColumnType::Bool => Box::new(match field {
+            Some(FieldValue::Boolean(v)) => Some(v),
+            _ => None,
+        }),
+        ColumnType::String | ColumnType::Tag => Box::new(match field {
+            Some(FieldValue::String(v)) => Some(v),
+            _ => tag,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sink(types: &[(&str, ColumnType)]) -> PostgresSink {
+        let types = types.iter().map(|(name, column_type)| (name.to_string(), *column_type)).collect();
+        PostgresSink::new("host=localhost user=stats", "metrics.cpu", &types, 0).unwrap()
+    }
+    
+    #[test]
+    fn dynamic_tables_keep_tags_and_fields_as_jsonb() {
+        let sink = sink(&[]);
+        assert_eq!(
+            sink.create_table_sql(),
+            "CREATE TABLE IF NOT EXISTS \"metrics\".\"cpu\" (time TIMESTAMPTZ NOT NULL, measurement TEXT NOT NULL, tags JSONB, fields JSONB)",
+        );
+        assert_eq!(sink.copy_sql(), "COPY \"metrics\".\"cpu\" (time, measurement, tags, fields) FROM STDIN BINARY");
+    }
+    
+    #[test]
+    fn schema_columns_get_typed_columns() {
+        let sink = sink(&[("load", ColumnType::Float), ("host", ColumnType::Tag), ("time", ColumnType::Int)]);
+        assert_eq!(
+            sink.create_table_sql(),
+            "CREATE TABLE IF NOT EXISTS \"metrics\".\"cpu\" (time TIMESTAMPTZ NOT NULL, measurement TEXT NOT NULL, \
+             \"host\" TEXT, \"load\" DOUBLE PRECISION, tags JSONB, fields JSONB)",
+        );
+        assert_eq!(sink.column_types(), vec![Type::TIMESTAMPTZ, Type::TEXT, Type::TEXT, Type::FLOAT8, Type::JSONB, Type::JSONB]);
+    }
+    
+    #[test]
+    fn identifiers_are_quoted_and_types_mapped() {
+        assert_eq!(sql_type(ColumnType::Bool), "BOOLEAN");
+        assert_eq!(quote_identifier("odd\"name"), "\"odd\"\"name\"");
+    }
+}
@@ -0,0 +1,153 @@
+// SQLite cache backend (--cache-backend sqlite)
+
+use crate::{check_cache_version, CacheMismatch, CacheStore, CursedStatsError, FileMetadata, CACHE_SCHEMA_VERSION};
+use log::warn;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Keeps one row per cache entry, the entry itself stored as JSON so fields added later load
+// with their defaults as they do from the JSON cache. Saving a file's entry upserts its row
+// instead of rewriting the whole cache
+pub(crate) struct SqliteCache {
+    path: PathBuf,
+    // Opened on first use, so runs that never touch the cache don't create the database.
+    // Held while an entry is read from the cache and written, and always taken before the
+    // cache itself
+    conn: Mutex<Option<Connection>>,
+}
+
+impl SqliteCache {
+    pub(crate) fn new(path: &Path) -> Self {
+        SqliteCache {
+            path: path.to_path_buf(),
+            conn: Mutex::new(None),
+        }
+    }
+    
+    // Run f on the connection, opening the database first if needed
+    fn with_connection<T>(&self, f: impl FnOnce(&mut Connection) -> rusqlite::Result<T>) -> Result<T, CursedStatsError> {
+        let db_error = |cause| CursedStatsError::CacheDb { path: self.path.clone(), cause };
+        let mut conn = self.conn.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if conn.is_none() {
+            *conn = Some(open(&self.path).map_err(db_error)?);
+        }
+        f(conn.as_mut().expect("connection was just opened")).map_err(db_error)
+    }
+}
+
+fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    // Another importer sharing the cache waits for our writes instead of failing
+    conn.busy_timeout(std::time::Duration::from_secs(30))?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (name TEXT PRIMARY KEY, value TEXT NOT NULL);
+         CREATE TABLE IF NOT EXISTS entries (key TEXT PRIMARY KEY, path TEXT NOT NULL, metadata TEXT NOT NULL);",
+    )?;
+    Ok(conn)
+}
+
+// Schema version and importer version the database was last written with
+fn read_version(conn: &mut Connection) -> rusqlite::Result<Option<(u32, String)>> {
+    let value = |name: &str| conn.query_row("SELECT value FROM meta WHERE name = ?1", [name], |row| row.get::<_, String>(0)).optional();
+    let Some(found) = value("schema_version")? else {
+        return Ok(None);
+    };
+    let written_by = value("importer_version")?.unwrap_or_else(|| "unknown".to_string());
+    // An unreadable version counts as a mismatch
+    Ok(Some((found.parse().unwrap_or(0), written_by)))
+}
+
+// Upsert the given entries and delete those given as None in one transaction, first
+// deleting every other entry with `replace`
+fn write_entries<'a>(conn: &mut Connection, replace: bool, entries: impl IntoIterator<Item = (&'a str, Option<&'a FileMetadata>)>) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    if replace {
+        tx.execute("DELETE FROM entries", [])?;
+    }
+    {
+        let mut upsert = tx.prepare_cached(
+            "INSERT INTO entries (key, path, metadata) VALUES (?1, ?2, ?3)
+             ON CONFLICT (key) DO UPDATE SET path = excluded.path, metadata = excluded.metadata",
+        )?;
+        let mut delete = tx.prepare_cached("DELETE FROM entries WHERE key = ?1")?;
+        for (key, metadata) in entries {
+            match metadata {
+                Some(metadata) => {
+                    let json = serde_json::to_string(metadata).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                    upsert.execute(params![key, metadata.path, json])?;
+                }
+                None => {
+                    delete.execute([key])?;
+                }
+            }
+        }
+    }
+    tx.execute(
+        "INSERT INTO meta (name, value) VALUES ('schema_version', ?1), ('importer_version', ?2)
+         ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+        params![CACHE_SCHEMA_VERSION.to_string(), env!("CARGO_PKG_VERSION")],
+    )?;
+    tx.commit()
+}
+
+impl CacheStore for SqliteCache {
+    fn location(&self) -> &Path {
+        &self.path
+    }
+    
+    fn load(&self, on_mismatch: CacheMismatch) -> Result<HashMap<String, FileMetadata>, CursedStatsError> {
+        if !self.path.exists() {
+            return Ok(HashMap::new());
+        }
+        if let Some((found, written_by)) = self.with_connection(read_version)? {
+            if !check_cache_version(&self.path, found, written_by, on_mismatch)? {
+                // Entries are only ever upserted, so the old ones go now rather than on save
+                self.with_connection(|conn| write_entries(conn, true, []))?;
+                return Ok(HashMap::new());
+            }
+        }
+        
+        let rows: Vec<(String, String)> = self.with_connection(|conn| {
+            let mut select = conn.prepare("SELECT key, metadata FROM entries")?;
+            let rows = select.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?.collect();
+            rows
+        })?;
+        let mut cache = HashMap::with_capacity(rows.len());
+        for (key, metadata) in rows {
+            match serde_json::from_str::<FileMetadata>(&metadata) {
+                Ok(metadata) => {
+                    cache.insert(key, metadata);
+                }
+                Err(e) => warn!("Ignoring unreadable entry {} in cache {}: {}", key, self.path.display(), e),
+            }
+        }
+        Ok(cache)
+    }
+    
+    fn save_entries(&self, cache: &Mutex<HashMap<String, FileMetadata>>, keys: &[String]) -> Result<(), CursedStatsError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        self.with_connection(|conn| {
+            // Copied under the connection lock so an older copy of an entry can't be written
+            // after a newer one
+            let entries: Vec<(&str, Option<FileMetadata>)> = {
+                let cache = cache.lock().unwrap();
+                keys.iter().map(|key| (key.as_str(), cache.get(key).cloned())).collect()
+            };
+            write_entries(conn, false, entries.iter().map(|(key, metadata)| (*key, metadata.as_ref())))
+        })
+    }
+    
+    // Every change was already written by save_entries
+    fn flush(&self, _cache: &Mutex<HashMap<String, FileMetadata>>) -> Result<(), CursedStatsError> {
+        Ok(())
+    }
+    
+    fn save_map(&self, cache: &HashMap<String, FileMetadata>) -> Result<(), CursedStatsError> {
+        self.with_connection(|conn| write_entries(conn, true, cache.iter().map(|(key, metadata)| (key.as_str(), Some(metadata)))))
+    }
+}